@@ -0,0 +1,152 @@
+use serde::Deserialize;
+use serde::Deserializer;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IP network in CIDR notation like 10.0.0.0/8 or fd00::/8,
+/// a plain address is a network of just that address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+  addr: IpAddr,
+  prefix_len: u8,
+}
+
+impl Cidr {
+  pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, String> {
+    let max_len = max_prefix_len(&addr);
+    if prefix_len > max_len {
+      return Err(format!(
+        "prefix length {} of {} is greater than {}",
+        prefix_len, addr, max_len
+      ));
+    }
+    Ok(Cidr { addr, prefix_len })
+  }
+
+  /// Whether the address is in the network, IPv4 mapped IPv6
+  /// addresses (from dual stack sockets) match IPv4 networks.
+  pub fn contains(&self, addr: &IpAddr) -> bool {
+    match (self.addr, unmap(addr)) {
+      (IpAddr::V4(network), IpAddr::V4(addr)) => {
+        prefix_eq(&network.octets(), &addr.octets(), self.prefix_len)
+      }
+      (IpAddr::V6(network), IpAddr::V6(addr)) => {
+        prefix_eq(&network.octets(), &addr.octets(), self.prefix_len)
+      }
+      _ => false,
+    }
+  }
+}
+
+fn max_prefix_len(addr: &IpAddr) -> u8 {
+  match addr {
+    IpAddr::V4(_) => 32,
+    IpAddr::V6(_) => 128,
+  }
+}
+
+fn unmap(addr: &IpAddr) -> IpAddr {
+  match addr {
+    IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+      Some(v4) => IpAddr::V4(v4),
+      None => *addr,
+    },
+    IpAddr::V4(_) => *addr,
+  }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+  let whole_bytes = (prefix_len / 8) as usize;
+  let remaining_bits = prefix_len % 8;
+  if a[..whole_bytes] != b[..whole_bytes] {
+    return false;
+  }
+  if remaining_bits == 0 {
+    return true;
+  }
+  let mask = 0xFFu8 << (8 - remaining_bits);
+  a[whole_bytes] & mask == b[whole_bytes] & mask
+}
+
+impl FromStr for Cidr {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid CIDR {:?}", s);
+    match s.find('/') {
+      Some(i) => {
+        let addr: IpAddr = s[..i].parse().map_err(|_| invalid())?;
+        let prefix_len: u8 = s[i + 1..].parse().map_err(|_| invalid())?;
+        Cidr::new(addr, prefix_len)
+      }
+      None => {
+        let addr: IpAddr = s.parse().map_err(|_| invalid())?;
+        Cidr::new(addr, max_prefix_len(&addr))
+      }
+    }
+  }
+}
+
+impl fmt::Display for Cidr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_fmt(format_args!("{}/{}", self.addr, self.prefix_len))
+  }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!("10.0.0.0/8".parse::<Cidr>(), Cidr::new(ip("10.0.0.0"), 8));
+    assert_eq!("::1".parse::<Cidr>(), Cidr::new(ip("::1"), 128));
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("10.0.0/8".parse::<Cidr>().is_err());
+    assert!("fd00::/x".parse::<Cidr>().is_err());
+  }
+
+  #[test]
+  fn test_contains_v4() {
+    let cidr: Cidr = "192.168.16.0/20".parse().unwrap();
+    assert!(cidr.contains(&ip("192.168.16.1")));
+    assert!(cidr.contains(&ip("192.168.31.255")));
+    assert!(!cidr.contains(&ip("192.168.32.0")));
+    assert!(!cidr.contains(&ip("::1")));
+  }
+
+  #[test]
+  fn test_contains_v6() {
+    let cidr: Cidr = "fd00::/8".parse().unwrap();
+    assert!(cidr.contains(&ip("fd12:3456::1")));
+    assert!(!cidr.contains(&ip("fe80::1")));
+    assert!(!cidr.contains(&ip("10.0.0.1")));
+  }
+
+  #[test]
+  fn test_contains_ipv4_mapped() {
+    let cidr: Cidr = "127.0.0.0/8".parse().unwrap();
+    assert!(cidr.contains(&ip("::ffff:127.0.0.1")));
+  }
+
+  #[test]
+  fn test_zero_prefix() {
+    let cidr: Cidr = "0.0.0.0/0".parse().unwrap();
+    assert!(cidr.contains(&ip("203.0.113.7")));
+  }
+}
@@ -0,0 +1,244 @@
+use super::util::invalid_data;
+use rcgen::CertificateParams;
+use rcgen::DistinguishedName;
+use rcgen::DnType;
+use rcgen::ExtendedKeyUsagePurpose;
+use rcgen::KeyPair;
+use rcgen::SanType;
+use rcgen::PKCS_ECDSA_P256_SHA256;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use time::Duration;
+use time::OffsetDateTime;
+use tokio_rustls::rustls::sign::{any_ecdsa_type, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, ClientHello, PrivateKey, ResolvesServerCert};
+
+/// hostname used for the leaf when the client does not send SNI
+/// (for example when it dials an IP address)
+static DEFAULT_HOSTNAME: &str = "localhost";
+
+/// most leaf certificates kept, a long run can visit many hostnames
+const MAX_CACHED_LEAVES: usize = 1000;
+
+/// Local certificate authority that mints leaf certificates on demand.
+pub(super) struct CertificateAuthority {
+  cert_der: Certificate,
+  signer: rcgen::Certificate,
+}
+
+impl CertificateAuthority {
+  pub(super) fn new(cert_der: Certificate, key_pair: KeyPair) -> Result<Self, Error> {
+    let params = CertificateParams::from_ca_cert_der(cert_der.as_ref(), key_pair)
+      .map_err(|err| invalid_data(format!("Invalid CA certificate: {}", err)))?;
+    let signer = rcgen::Certificate::from_params(params).map_err(invalid_data)?;
+    Ok(CertificateAuthority { cert_der, signer })
+  }
+
+  pub(super) fn cert_der(&self) -> &Certificate {
+    &self.cert_der
+  }
+
+  /// Mint a leaf certificate for the hostname, the chain includes the CA
+  /// cert so the spki digest of the CA can still be used with chrome.
+  fn mint(&self, hostname: &str) -> Result<CertifiedKey, Error> {
+    let mut params = CertificateParams::default();
+    params.alg = &PKCS_ECDSA_P256_SHA256;
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.subject_alt_names = vec![match hostname.parse::<IpAddr>() {
+      Ok(ip) => SanType::IpAddress(ip),
+      Err(_) => SanType::DnsName(hostname.to_owned()),
+    }];
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, hostname);
+    params.distinguished_name = distinguished_name;
+    // keep validity short, some platforms reject long lived leaf certs
+    // even when they chain to a locally trusted root
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(90);
+
+    let leaf = rcgen::Certificate::from_params(params).map_err(invalid_data)?;
+    let leaf_der = leaf
+      .serialize_der_with_signer(&self.signer)
+      .map_err(invalid_data)?;
+    let key = any_ecdsa_type(&PrivateKey(leaf.serialize_private_key_der()))
+      .map_err(|_| invalid_data("unsupported leaf private key"))?;
+
+    Ok(CertifiedKey::new(
+      vec![Certificate(leaf_der), self.cert_der.clone()],
+      Arc::new(key),
+    ))
+  }
+}
+
+/// Resolves a leaf certificate for the SNI hostname of the handshake,
+/// minting and caching it on first use.
+pub(super) struct LeafCertResolver {
+  authority: CertificateAuthority,
+  cache: Mutex<LeafCache>,
+}
+
+impl LeafCertResolver {
+  pub(super) fn new(authority: CertificateAuthority) -> Self {
+    LeafCertResolver {
+      authority,
+      cache: Mutex::new(LeafCache::new(MAX_CACHED_LEAVES)),
+    }
+  }
+}
+
+impl ResolvesServerCert for LeafCertResolver {
+  fn resolve(&self, client_hello: ClientHello<'_>) -> Option<CertifiedKey> {
+    let hostname: &str = match client_hello.server_name() {
+      Some(name) => name.into(),
+      None => DEFAULT_HOSTNAME,
+    };
+
+    if let Some(certified_key) = self.cache.lock().unwrap().get(hostname) {
+      return Some(certified_key);
+    }
+
+    // minting generates a key, so it is done without holding the lock
+    // and handshakes for other hostnames are not held up
+    match self.authority.mint(hostname) {
+      Ok(certified_key) => {
+        log::debug!("minted leaf certificate for {}", hostname);
+        Some(self.cache.lock().unwrap().insert(hostname, certified_key))
+      }
+      Err(err) => {
+        log::warn!("failed to mint leaf certificate for {}: {}", hostname, err);
+        None
+      }
+    }
+  }
+}
+
+/// Minted leaf certificates by hostname, the oldest is evicted once the
+/// capacity is reached.
+struct LeafCache {
+  capacity: usize,
+  leaves: HashMap<String, CertifiedKey>,
+  order: VecDeque<String>,
+}
+
+impl LeafCache {
+  fn new(capacity: usize) -> Self {
+    LeafCache {
+      capacity,
+      leaves: HashMap::new(),
+      order: VecDeque::new(),
+    }
+  }
+
+  fn get(&self, hostname: &str) -> Option<CertifiedKey> {
+    self.leaves.get(hostname).cloned()
+  }
+
+  /// Caches the leaf and returns the cached leaf for the hostname, a
+  /// concurrent handshake may have minted one first.
+  fn insert(&mut self, hostname: &str, certified_key: CertifiedKey) -> CertifiedKey {
+    if let Some(cached) = self.leaves.get(hostname) {
+      return cached.clone();
+    }
+    if self.order.len() >= self.capacity {
+      if let Some(oldest) = self.order.pop_front() {
+        self.leaves.remove(&oldest);
+      }
+    }
+    self.order.push_back(hostname.to_owned());
+    self
+      .leaves
+      .insert(hostname.to_owned(), certified_key.clone());
+    certified_key
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::BasicConstraints;
+  use rcgen::IsCa;
+  use std::convert::TryFrom;
+  use tokio::io::duplex;
+  use tokio_rustls::rustls::{ClientConfig, NoClientAuth, RootCertStore, ServerConfig};
+  use tokio_rustls::webpki::DNSNameRef;
+  use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+  fn authority() -> CertificateAuthority {
+    let mut params = CertificateParams::default();
+    params.alg = &PKCS_ECDSA_P256_SHA256;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+      .distinguished_name
+      .push(DnType::CommonName, "test CA");
+    let ca = rcgen::Certificate::from_params(params).unwrap();
+    let cert_der = Certificate(ca.serialize_der().unwrap());
+    let key_pair = KeyPair::from_der(&ca.serialize_private_key_der()).unwrap();
+    CertificateAuthority::new(cert_der, key_pair).unwrap()
+  }
+
+  /// Handshakes with the resolver as a client that only trusts the CA,
+  /// returns the certificate chain the server sent.
+  async fn handshake(resolver: Arc<LeafCertResolver>, sni: &str) -> Vec<Certificate> {
+    let mut roots = RootCertStore::empty();
+    roots.add(resolver.authority.cert_der()).unwrap();
+    let mut client_config = ClientConfig::new();
+    client_config.root_store = roots;
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.cert_resolver = resolver;
+
+    let (client, server) = duplex(64 * 1024);
+    let accept = TlsAcceptor::from(Arc::new(server_config)).accept(server);
+    let connect = TlsConnector::from(Arc::new(client_config))
+      .connect(DNSNameRef::try_from_ascii_str(sni).unwrap(), client);
+    let (accepted, connected) = tokio::join!(accept, connect);
+    accepted.unwrap();
+    let connected = connected.unwrap();
+    let (_, session) = connected.get_ref();
+    tokio_rustls::rustls::Session::get_peer_certificates(session).unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_leaf_for_sni_chains_to_authority() {
+    let resolver = Arc::new(LeafCertResolver::new(authority()));
+    let chain = handshake(resolver.clone(), "app.example.com").await;
+
+    // the client verified the chain against the CA as its only root
+    assert_eq!(chain.len(), 2);
+    assert_eq!(&chain[1], resolver.authority.cert_der());
+    let leaf = webpki::EndEntityCert::try_from(chain[0].as_ref()).unwrap();
+    let dns_name = |name| webpki::DnsNameRef::try_from_ascii_str(name).unwrap();
+    assert!(leaf
+      .verify_is_valid_for_dns_name(dns_name("app.example.com"))
+      .is_ok());
+    assert!(leaf
+      .verify_is_valid_for_dns_name(dns_name("other.example.com"))
+      .is_err());
+
+    // the leaf is minted once per hostname
+    let again = handshake(resolver, "app.example.com").await;
+    assert_eq!(again[0], chain[0]);
+  }
+
+  #[test]
+  fn test_cache_evicts_oldest() {
+    let authority = authority();
+    let mut cache = LeafCache::new(2);
+    for hostname in &["a.test", "b.test", "c.test"] {
+      cache.insert(hostname, authority.mint(hostname).unwrap());
+    }
+    assert!(cache.get("a.test").is_none());
+    assert!(cache.get("b.test").is_some());
+    assert!(cache.get("c.test").is_some());
+
+    // the first leaf cached for a hostname is kept
+    let cached = cache.get("c.test").unwrap();
+    let minted = authority.mint("c.test").unwrap();
+    assert_eq!(cache.insert("c.test", minted).cert, cached.cert);
+    assert_eq!(cache.order.len(), 2);
+  }
+}
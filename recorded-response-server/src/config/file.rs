@@ -0,0 +1,693 @@
+use super::util::{invalid_input, read_config_file};
+use super::TlsSettings;
+use crate::Cidr;
+use crate::CorsPolicy;
+use crate::DestinationPattern;
+use crate::FallbackResponse;
+use crate::FallbackRule;
+use crate::ProxyProtocol;
+use crate::Timeouts;
+use bytes::Bytes;
+use http::header::HeaderName;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+use http::Uri;
+use std::collections::HashMap;
+use std::io::Error;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracerbench_recorded_response_set::RecordedResponse;
+
+/// TOML or JSON config file that a `Config` can be built from.
+///
+/// Relative paths are resolved against the directory of the file.
+///
+/// ```toml
+/// archives = ["sets.cbor"]
+/// bind = "0.0.0.0"
+/// allow = ["10.0.0.0/8"]
+/// port_offset = 1000
+/// log_format = "json"
+///
+/// [tls]
+/// cert = "cert.pem"
+/// key = "key.pem"
+/// resumption = "disabled"
+///
+/// [socks.credentials]
+/// benchmark = "secret"
+///
+/// [timeouts]
+/// tls = 5
+/// idle = 300
+///
+/// [cors]
+/// allow_origin = "https://app.example.com"
+///
+/// [sets.control]
+/// port = 9000
+/// proxy = "http_connect"
+///
+/// [sets.experiment]
+/// port = 9001
+/// tls = { versions = ["1.2"] }
+/// ```
+///
+/// With `[shared]` all sets are served from one port instead:
+///
+/// ```toml
+/// [shared]
+/// port = 9000
+/// default_set = "control"
+///
+/// [sets.experiment]
+/// destinations = ["experiment.example.com:443"]
+/// ```
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+  /// recorded response set archives, the sets of all archives are served
+  pub archives: Vec<PathBuf>,
+  /// address the socks proxy servers bind to, defaults to 127.0.0.1
+  pub bind: Option<IpAddr>,
+  /// accept IPv4 connections as well when binding an IPv6 address
+  pub dual_stack: Option<bool>,
+  /// only accept connections from peers in these networks, binding
+  /// beyond localhost exposes the recorded data on the network, must
+  /// not be empty
+  pub allow: Option<Vec<Cidr>>,
+  /// added to the recorded socks port of sets without an explicit port
+  pub port_offset: Option<u16>,
+  /// bind port 0 for sets without an explicit port and report the
+  /// assigned port when listening
+  pub ephemeral_ports: Option<bool>,
+  /// respond 421 Misdirected Request when the :authority of a request
+  /// does not match the host:port dialed through the socks proxy
+  pub check_authority: Option<bool>,
+  /// end server-sent event streams after the last recorded event
+  /// instead of keeping them open until the client closes them
+  pub close_event_streams: Option<bool>,
+  /// answer Range requests for every recorded 200 response, not only
+  /// those recorded with accept-ranges: bytes
+  pub range_requests: Option<bool>,
+  /// synthesize CORS preflight responses missing from the sets and
+  /// add access-control headers to recorded responses without them
+  pub cors: Option<CorsSettings>,
+  /// responses for misses in every set instead of 404
+  pub fallbacks: Vec<FallbackSettings>,
+  /// remove the recorded headers HTTP/2 forbids, like upgrade and te,
+  /// instead of only warning about them when the archives are loaded
+  pub strip_invalid_headers: Option<bool>,
+  /// log output format
+  pub log_format: Option<LogFormat>,
+  /// shared TLS settings, cert and key are required
+  pub tls: TlsSettings,
+  /// proxy protocol the servers accept: socks, http_connect or auto
+  /// to detect it per connection, defaults to socks
+  pub proxy: Option<ProxyProtocol>,
+  /// socks proxy settings
+  pub socks: SocksSettings,
+  /// connection timeouts
+  pub timeouts: TimeoutSettings,
+  /// host:port of an HTTP/1.1 server requests missing from a set are
+  /// forwarded to instead of responding 404, e.g. a local dev server
+  pub upstream: Option<String>,
+  /// forward misses of sets without an upstream to the host:port the
+  /// client dialed through the proxy, over TLS
+  pub forward_to_destination: Option<bool>,
+  /// archive the responses forwarded upstream are written to, it has
+  /// a set for each set with forwarded requests
+  pub new_recordings: Option<PathBuf>,
+  /// serve all sets from one port instead of a port per set
+  pub shared: Option<SharedSettings>,
+  /// per set overrides by response set name
+  pub sets: HashMap<String, SetSettings>,
+}
+
+/// Socks proxy settings shared by all servers.
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocksSettings {
+  /// require RFC 1929 username/password authentication with one of
+  /// these username -> password credentials
+  pub credentials: Option<HashMap<String, String>>,
+  /// reply to a socks 5 CONNECT with the local address of the
+  /// connection instead of 0.0.0.0:0
+  pub report_bound_addr: Option<bool>,
+}
+
+/// Timeouts in seconds, 0 disables a timeout and unset ones keep the
+/// defaults of 10 seconds for each handshake, 30 seconds for upstreams
+/// and no idle timeout.
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutSettings {
+  /// socks or HTTP CONNECT handshake
+  pub proxy: Option<f64>,
+  /// TLS handshake
+  pub tls: Option<f64>,
+  /// HTTP2 connection preface
+  pub preface: Option<f64>,
+  /// connection without open streams
+  pub idle: Option<f64>,
+  /// forwarded request and its response
+  pub upstream: Option<f64>,
+}
+
+impl TimeoutSettings {
+  /// The timeouts with these settings applied on top.
+  pub fn apply(&self, timeouts: Timeouts) -> Result<Timeouts, Error> {
+    Ok(Timeouts {
+      proxy_handshake: duration(self.proxy, timeouts.proxy_handshake, "timeouts.proxy")?,
+      tls_handshake: duration(self.tls, timeouts.tls_handshake, "timeouts.tls")?,
+      h2_preface: duration(self.preface, timeouts.h2_preface, "timeouts.preface")?,
+      idle: duration(self.idle, timeouts.idle, "timeouts.idle")?,
+      upstream: duration(self.upstream, timeouts.upstream, "timeouts.upstream")?,
+    })
+  }
+}
+
+/// CORS policy, unset fields echo the request.
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSettings {
+  /// access-control-allow-origin, echoes the Origin of the request if unset
+  pub allow_origin: Option<String>,
+  /// preflight access-control-allow-methods, echoes the requested method if unset
+  pub allow_methods: Option<Vec<String>>,
+  /// preflight access-control-allow-headers, echoes the requested headers if unset
+  pub allow_headers: Option<Vec<String>>,
+  /// access-control-expose-headers added to recorded responses
+  pub expose_headers: Option<Vec<String>>,
+  /// access-control-allow-credentials: true, defaults to true unless
+  /// allow_origin is "*"
+  pub allow_credentials: Option<bool>,
+  /// preflight access-control-max-age in seconds
+  pub max_age: Option<u64>,
+}
+
+impl CorsSettings {
+  /// The policy described by these settings.
+  pub fn policy(&self) -> Result<CorsPolicy, Error> {
+    let any_origin = self.allow_origin.as_deref() == Some("*");
+    let allow_credentials = self.allow_credentials.unwrap_or(!any_origin);
+    if any_origin && allow_credentials {
+      return Err(invalid_input(
+        "cors.allow_credentials is not allowed with an allow_origin of \"*\"",
+      ));
+    }
+    let policy = CorsPolicy {
+      allow_origin: self.allow_origin.clone(),
+      allow_methods: self.allow_methods.clone().unwrap_or_default(),
+      allow_headers: self.allow_headers.clone().unwrap_or_default(),
+      expose_headers: self.expose_headers.clone().unwrap_or_default(),
+      allow_credentials,
+      max_age: self.max_age,
+    };
+    let values = policy
+      .allow_origin
+      .iter()
+      .chain(&policy.allow_methods)
+      .chain(&policy.allow_headers)
+      .chain(&policy.expose_headers);
+    for value in values {
+      if HeaderValue::from_str(value).is_err() {
+        return Err(invalid_input(format!(
+          "cors value {:?} is not a valid header value",
+          value
+        )));
+      }
+    }
+    Ok(policy)
+  }
+}
+
+/// Push resources are paths or URLs with a scheme and authority.
+fn validate_push_rule(set_name: &str, key: &str, resources: &[String]) -> Result<(), Error> {
+  for resource in resources {
+    let valid = resource.starts_with('/')
+      || matches!(
+        resource.parse::<Uri>(),
+        Ok(uri) if uri.scheme().is_some() && uri.authority().is_some()
+      );
+    if !valid {
+      return Err(invalid_input(format!(
+        "sets.{}.push {:?} resource {:?} must be a path or a URL",
+        set_name, key, resource
+      )));
+    }
+  }
+  Ok(())
+}
+
+/// Upstreams are host:port, the host may be a name or an IP address
+/// with IPv6 in brackets.
+fn validate_upstream(name: &str, upstream: &str) -> Result<(), Error> {
+  let valid = match upstream.rfind(':') {
+    Some(i) => i > 0 && upstream[i + 1..].parse::<u16>().is_ok(),
+    None => false,
+  };
+  if valid {
+    Ok(())
+  } else {
+    Err(invalid_input(format!(
+      "{} must be host:port, got {:?}",
+      name, upstream
+    )))
+  }
+}
+
+fn duration(
+  seconds: Option<f64>,
+  default: Option<Duration>,
+  name: &str,
+) -> Result<Option<Duration>, Error> {
+  match seconds {
+    None => Ok(default),
+    Some(0.0) => Ok(None),
+    Some(seconds) if seconds.is_finite() && seconds > 0.0 => {
+      Ok(Some(Duration::from_secs_f64(seconds)))
+    }
+    Some(seconds) => Err(invalid_input(format!(
+      "{} must be a positive number of seconds or 0, got {}",
+      name, seconds
+    ))),
+  }
+}
+
+/// One listener for all sets, a connection is routed to the set named by
+/// the socks username, then by CONNECT destination, then the default set.
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SharedSettings {
+  /// port of the shared listener, 0 for an OS assigned port
+  pub port: u16,
+  /// set for connections no route matches
+  pub default_set: Option<String>,
+}
+
+/// Overrides for a single response set.
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SetSettings {
+  /// address to bind instead of the shared bind address
+  pub bind: Option<IpAddr>,
+  /// socks port to listen on instead of the recorded port
+  pub port: Option<u16>,
+  /// TLS settings applied on top of the shared TLS settings
+  pub tls: Option<TlsSettings>,
+  /// proxy protocol instead of the shared proxy protocol
+  pub proxy: Option<ProxyProtocol>,
+  /// CONNECT destinations routed to this set on the shared listener
+  pub destinations: Option<Vec<DestinationPattern>>,
+  /// upstream for misses instead of the shared upstream
+  pub upstream: Option<String>,
+  /// responses for misses, checked before the fallbacks for all sets
+  pub fallbacks: Vec<FallbackSettings>,
+  /// HTTP/2 server push, request key of a document -> paths or https
+  /// URLs of the recorded responses pushed with it
+  pub push: Option<HashMap<String, Vec<String>>>,
+}
+
+/// A response for misses whose request key matches, either inline or
+/// the recorded response of another request key of the set. Rules are
+/// checked before a miss is forwarded upstream.
+///
+/// ```toml
+/// [[fallbacks]]
+/// key = "POST * /beacon*"
+/// status = 204
+///
+/// [[sets.control.fallbacks]]
+/// key = "GET * /api/telemetry*"
+/// headers = { "content-type" = "application/json" }
+/// body = "{}"
+/// ```
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FallbackSettings {
+  /// request key pattern, * matches any characters
+  pub key: String,
+  /// status of the inline response, defaults to 200
+  pub status: Option<u16>,
+  /// headers of the inline response
+  pub headers: Option<HashMap<String, String>>,
+  /// body of the inline response
+  pub body: Option<String>,
+  /// request key of the recorded response to respond with instead of
+  /// an inline response
+  pub response: Option<String>,
+}
+
+impl FallbackSettings {
+  /// The fallback rule described by these settings.
+  pub fn rule(&self) -> Result<FallbackRule, Error> {
+    if self.key.is_empty() {
+      return Err(invalid_input("fallbacks key must be specified"));
+    }
+    let response = match &self.response {
+      Some(_) if self.status.is_some() || self.headers.is_some() || self.body.is_some() => {
+        return Err(invalid_input(format!(
+          "fallback {:?} has both a recorded response and an inline response",
+          self.key
+        )))
+      }
+      Some(key) => FallbackResponse::Recorded(key.clone()),
+      None => FallbackResponse::Inline(self.inline_response()?),
+    };
+    Ok(FallbackRule::new(&self.key, response))
+  }
+
+  fn inline_response(&self) -> Result<RecordedResponse, Error> {
+    let invalid =
+      |what: &str| invalid_input(format!("fallback {:?} has an invalid {}", self.key, what));
+    let status = StatusCode::from_u16(self.status.unwrap_or(200)).map_err(|_| invalid("status"))?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in self.headers.iter().flatten() {
+      let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid("header name"))?;
+      let value = HeaderValue::from_str(value).map_err(|_| invalid("header value"))?;
+      headers.append(name, value);
+    }
+    let body = self.body.clone().map(Bytes::from);
+    Ok(RecordedResponse::new(status, Arc::new(headers), body))
+  }
+}
+
+/// Log output format.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+  /// colored human readable output
+  #[default]
+  Pretty,
+  /// one JSON object per line
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "pretty" => Ok(LogFormat::Pretty),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!(
+        "unsupported log format {:?}, expected pretty or json",
+        s
+      )),
+    }
+  }
+}
+
+impl ConfigFile {
+  pub fn read(path: &PathBuf) -> Result<Self, Error> {
+    let mut file: ConfigFile = read_config_file(path)?;
+    file.resolve_paths(path.parent().unwrap_or_else(|| Path::new("")));
+    Ok(file)
+  }
+
+  pub fn bind_addr(&self) -> IpAddr {
+    self
+      .bind
+      .unwrap_or_else(|| Ipv4Addr::new(127, 0, 0, 1).into())
+  }
+
+  /// Upstream for misses in the named set, if any.
+  pub fn upstream_for(&self, set_name: &str) -> Option<&str> {
+    match self
+      .sets
+      .get(set_name)
+      .and_then(|set| set.upstream.as_ref())
+    {
+      Some(upstream) => Some(upstream),
+      None => self.upstream.as_deref(),
+    }
+  }
+
+  /// Checks what can be checked without loading archives or certs.
+  pub(super) fn validate(&self) -> Result<(), Error> {
+    if self.tls.cert.is_none() || self.tls.key.is_none() {
+      return Err(invalid_input("tls.cert and tls.key must be specified"));
+    }
+    if let Some(credentials) = &self.socks.credentials {
+      if credentials.is_empty() {
+        return Err(invalid_input("socks.credentials is empty"));
+      }
+      if credentials
+        .keys()
+        .any(|username| username.is_empty() || username.len() > 255)
+      {
+        return Err(invalid_input(
+          "socks.credentials usernames must be 1 to 255 bytes",
+        ));
+      }
+    }
+    if matches!(&self.allow, Some(allow) if allow.is_empty()) {
+      return Err(invalid_input(
+        "allow is empty and would refuse every peer, leave it out to accept all",
+      ));
+    }
+    if let Some(upstream) = &self.upstream {
+      validate_upstream("upstream", upstream)?;
+    }
+    for (name, set) in self.sets.iter() {
+      if let Some(upstream) = &set.upstream {
+        validate_upstream(&format!("sets.{}.upstream", name), upstream)?;
+      }
+      for (key, resources) in set.push.iter().flatten() {
+        validate_push_rule(name, key, resources)?;
+      }
+    }
+    if self.new_recordings.is_some()
+      && self.upstream.is_none()
+      && self.forward_to_destination != Some(true)
+      && self.sets.values().all(|set| set.upstream.is_none())
+    {
+      return Err(invalid_input("new_recordings requires an upstream"));
+    }
+    if self.shared.is_none() {
+      for (name, set) in self.sets.iter() {
+        if set.destinations.is_some() {
+          return Err(invalid_input(format!(
+            "sets.{}.destinations requires the shared listener",
+            name
+          )));
+        }
+      }
+    } else {
+      for (name, set) in self.sets.iter() {
+        if set.proxy.is_some() {
+          return Err(invalid_input(format!(
+            "sets.{}.proxy is not supported with the shared listener, set proxy instead",
+            name
+          )));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn resolve_paths(&mut self, base: &Path) {
+    for archive in self.archives.iter_mut() {
+      *archive = base.join(&archive);
+    }
+    self.tls.resolve_paths(base);
+    if let Some(new_recordings) = self.new_recordings.as_mut() {
+      *new_recordings = base.join(&new_recordings);
+    }
+    for set in self.sets.values_mut() {
+      if let Some(tls) = set.tls.as_mut() {
+        tls.resolve_paths(base);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Resumption;
+
+  fn config_file() -> ConfigFile {
+    let mut file = ConfigFile::default();
+    file.tls.cert = Some(PathBuf::from("cert.pem"));
+    file.tls.key = Some(PathBuf::from("key.pem"));
+    file
+  }
+
+  #[test]
+  fn test_empty_allow_is_invalid() {
+    let mut file = config_file();
+    file.allow = Some(Vec::new());
+    assert!(file.validate().is_err());
+    file.allow = Some(vec!["10.0.0.0/8".parse().unwrap()]);
+    assert!(file.validate().is_ok());
+    file.allow = None;
+    assert!(file.validate().is_ok());
+  }
+
+  /// Validates the TOML with a tls.cert and tls.key added.
+  fn validate(toml: &str) -> Result<(), Error> {
+    let text = format!("{}\n[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"\n", toml);
+    toml::from_str::<ConfigFile>(&text).unwrap().validate()
+  }
+
+  fn invalid(toml: &str) -> String {
+    validate(toml).unwrap_err().to_string()
+  }
+
+  fn write_file(name: &str, text: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("config-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, text).unwrap();
+    path
+  }
+
+  #[test]
+  fn test_read_toml() {
+    let path = write_file(
+      "config.toml",
+      r#"
+archives = ["sets.cbor"]
+port_offset = 1000
+
+[tls]
+cert = "cert.pem"
+key = "key.pem"
+resumption = "disabled"
+
+[timeouts]
+idle = 300
+
+[sets.control]
+port = 9000
+proxy = "http_connect"
+"#,
+    );
+    let file = ConfigFile::read(&path).unwrap();
+    let dir = path.parent().unwrap();
+    assert_eq!(file.archives, [dir.join("sets.cbor")]);
+    assert_eq!(file.tls.cert, Some(dir.join("cert.pem")));
+    assert_eq!(file.tls.resumption, Some(Resumption::Disabled));
+    assert_eq!(file.port_offset, Some(1000));
+    assert_eq!(file.timeouts.idle, Some(300.0));
+    assert_eq!(file.sets["control"].port, Some(9000));
+    assert_eq!(file.sets["control"].proxy, Some(ProxyProtocol::HttpConnect));
+    assert!(file.validate().is_ok());
+  }
+
+  #[test]
+  fn test_read_json() {
+    let path = write_file(
+      "config.json",
+      r#"{
+        "archives": ["sets.cbor"],
+        "tls": { "cert": "cert.pem", "key": "key.pem" },
+        "sets": { "experiment": { "tls": { "versions": ["1.2"] } } }
+      }"#,
+    );
+    let file = ConfigFile::read(&path).unwrap();
+    let dir = path.parent().unwrap();
+    assert_eq!(file.archives, [dir.join("sets.cbor")]);
+    let tls = file.sets["experiment"].tls.as_ref().unwrap();
+    assert_eq!(tls.versions, Some(vec!["1.2".to_owned()]));
+  }
+
+  #[test]
+  fn test_unknown_fields_are_rejected() {
+    assert!(toml::from_str::<ConfigFile>("archive = [\"sets.cbor\"]").is_err());
+    assert!(toml::from_str::<ConfigFile>("[sets.control]\nprot = 9000").is_err());
+    assert!(toml::from_str::<ConfigFile>("[tls]\nversion = [\"1.2\"]").is_err());
+    assert!(serde_json::from_str::<ConfigFile>(r#"{ "timeouts": { "handshake": 5 } }"#).is_err());
+
+    let path = write_file("unknown.toml", "bindd = \"0.0.0.0\"");
+    let err = ConfigFile::read(&path).unwrap_err().to_string();
+    assert!(err.contains("Invalid config file"), "{}", err);
+  }
+
+  #[test]
+  fn test_tls_cert_and_key_are_required() {
+    let file: ConfigFile = toml::from_str("[tls]\ncert = \"cert.pem\"").unwrap();
+    let err = file.validate().unwrap_err().to_string();
+    assert!(err.contains("tls.cert and tls.key"), "{}", err);
+  }
+
+  #[test]
+  fn test_socks_credentials() {
+    assert!(invalid("[socks.credentials]").contains("socks.credentials is empty"));
+    let username = "u".repeat(256);
+    let toml = format!("[socks.credentials]\n{} = \"secret\"", username);
+    assert!(invalid(&toml).contains("1 to 255 bytes"));
+    assert!(validate("[socks.credentials]\nbenchmark = \"secret\"").is_ok());
+  }
+
+  #[test]
+  fn test_upstreams_are_host_port() {
+    assert!(invalid("upstream = \"localhost\"").contains("upstream must be host:port"));
+    assert!(invalid("upstream = \":8080\"").contains("upstream must be host:port"));
+    assert!(invalid("[sets.control]\nupstream = \"localhost:http\"")
+      .contains("sets.control.upstream must be host:port"));
+    assert!(validate("upstream = \"[::1]:8080\"").is_ok());
+  }
+
+  #[test]
+  fn test_push_resources_are_paths_or_urls() {
+    let toml = "[sets.control.push]\n\"GET localhost /\" = [\"app.js\"]";
+    assert!(invalid(toml).contains("must be a path or a URL"));
+    let toml =
+      "[sets.control.push]\n\"GET localhost /\" = [\"/app.js\", \"https://cdn.test/lib.js\"]";
+    assert!(validate(toml).is_ok());
+  }
+
+  #[test]
+  fn test_new_recordings_requires_an_upstream() {
+    let toml = "new_recordings = \"new.cbor\"";
+    assert!(invalid(toml).contains("new_recordings requires an upstream"));
+    assert!(validate("new_recordings = \"new.cbor\"\nforward_to_destination = true").is_ok());
+    assert!(validate(
+      "new_recordings = \"new.cbor\"\n[sets.control]\nupstream = \"localhost:8080\""
+    )
+    .is_ok());
+  }
+
+  #[test]
+  fn test_shared_listener_settings() {
+    let toml = "[sets.experiment]\ndestinations = [\"experiment.example.com:443\"]";
+    assert!(invalid(toml).contains("requires the shared listener"));
+    let toml = "[shared]\nport = 9000\n[sets.experiment]\nproxy = \"http_connect\"";
+    assert!(invalid(toml).contains("is not supported with the shared listener"));
+    let toml =
+      "[shared]\nport = 9000\n[sets.experiment]\ndestinations = [\"experiment.example.com:443\"]";
+    assert!(validate(toml).is_ok());
+  }
+
+  #[test]
+  fn test_timeouts() {
+    let settings = TimeoutSettings {
+      idle: Some(0.0),
+      tls: Some(2.5),
+      ..TimeoutSettings::default()
+    };
+    let timeouts = settings.apply(Timeouts::default()).unwrap();
+    assert_eq!(timeouts.idle, None);
+    assert_eq!(timeouts.tls_handshake, Some(Duration::from_millis(2500)));
+
+    let settings = TimeoutSettings {
+      proxy: Some(-1.0),
+      ..TimeoutSettings::default()
+    };
+    let err = settings.apply(Timeouts::default()).unwrap_err().to_string();
+    assert!(
+      err.contains("timeouts.proxy must be a positive number"),
+      "{}",
+      err
+    );
+  }
+}
@@ -1,12 +1,54 @@
+mod ca;
+mod file;
+mod tls;
 mod util;
 
+use crate::Cidr;
+use crate::CorsPolicy;
+use crate::DestinationPattern;
+use crate::FallbackResponse;
+use crate::Fallbacks;
+use crate::ProxyProtocol;
+use crate::PushRules;
+use crate::Timeouts;
+use ca::CertificateAuthority;
+use ca::LeafCertResolver;
+pub use file::ConfigFile;
+pub use file::CorsSettings;
+pub use file::FallbackSettings;
+pub use file::LogFormat;
+pub use file::SetSettings;
+pub use file::SharedSettings;
+pub use file::SocksSettings;
+pub use file::TimeoutSettings;
+use std::collections::HashMap;
 use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
+pub use tls::Resumption;
+pub use tls::TlsSettings;
 use tokio_rustls::rustls;
+use tracerbench_recorded_response_set::RecordedResponseSet;
 use tracerbench_recorded_response_set::RecordedResponseSets;
 use util::*;
 
+/// A response set to record by forwarding the requests made through
+/// the server.
+#[derive(Debug, Clone)]
+pub struct Recording {
+  /// name of the recorded set
+  pub name: String,
+  /// socks port the server listens on, recorded as the socks port of the set
+  pub port: u16,
+  /// key of the entry request, defaults to the key of the first response
+  pub entry_key: Option<String>,
+  /// CBOR request key program the responses are keyed with
+  pub request_key_program: PathBuf,
+  /// archive the recorded set is written to
+  pub output: PathBuf,
+}
+
 /// Server config
 pub struct Config {
   /// Value for chrome switch ignore-certificate-errors-spki-list
@@ -16,6 +58,61 @@ pub struct Config {
   pub tls_config: Arc<rustls::ServerConfig>,
   /// Recorded response sets
   pub response_sets: RecordedResponseSets,
+  /// TLS configs overriding the shared config by response set name
+  pub set_tls_configs: HashMap<String, Arc<rustls::ServerConfig>>,
+  /// spki_digest of the response sets with their own certificate
+  pub set_spki_digests: HashMap<String, String>,
+  /// Address the socks proxy servers bind to
+  pub bind_addr: IpAddr,
+  /// Bind addresses overriding the shared address by response set name
+  pub set_bind_addrs: HashMap<String, IpAddr>,
+  /// Accept IPv4 connections on IPv6 bind addresses
+  pub dual_stack: bool,
+  /// Networks connections are accepted from, empty accepts all
+  pub allowed_peers: Vec<Cidr>,
+  /// Proxy protocol the servers accept
+  pub proxy_protocol: ProxyProtocol,
+  /// Proxy protocols overriding the shared protocol by response set name
+  pub set_proxy_protocols: HashMap<String, ProxyProtocol>,
+  /// Socks username -> password, username/password authentication
+  /// is required when not empty
+  pub socks_credentials: HashMap<String, String>,
+  /// Reply to socks 5 CONNECT with the local address of the connection
+  pub report_bound_addr: bool,
+  /// Deadlines for the handshakes and idle connections
+  pub timeouts: Timeouts,
+  /// host:port misses are forwarded to instead of responding 404
+  pub upstream: Option<String>,
+  /// Upstreams overriding the shared upstream by response set name
+  pub set_upstreams: HashMap<String, String>,
+  /// Forward misses of sets without an upstream to the CONNECT destination
+  pub forward_to_destination: bool,
+  /// Archive the responses forwarded upstream are written to
+  pub new_recordings: Option<PathBuf>,
+  /// Respond 421 when :authority does not match the CONNECT destination
+  pub check_authority: bool,
+  /// End server-sent event streams after the last recorded event
+  pub close_event_streams: bool,
+  /// Answer Range requests for every recorded 200 response
+  pub range_requests: bool,
+  /// Synthesize missing CORS preflights and add access-control headers
+  pub cors: Option<CorsPolicy>,
+  /// Responses for misses instead of 404
+  pub fallbacks: Fallbacks,
+  /// Recorded responses pushed with documents by response set name
+  pub push_rules: PushRules,
+  /// Serve all sets from this port instead of a port per set
+  pub shared_port: Option<u16>,
+  /// Set for shared port connections that match no route
+  pub shared_default_set: Option<String>,
+  /// CONNECT destinations routed on the shared port by response set name
+  pub set_destinations: HashMap<String, Vec<DestinationPattern>>,
+  /// Socks ports overriding the recorded port by response set name
+  pub set_ports: HashMap<String, u16>,
+  /// Added to recorded socks ports
+  pub port_offset: u16,
+  /// Listen on an OS assigned port instead of the recorded socks port
+  pub ephemeral_ports: bool,
 }
 
 impl Config {
@@ -28,6 +125,358 @@ impl Config {
       spki_digest,
       tls_config,
       response_sets,
+      set_tls_configs: HashMap::new(),
+      set_spki_digests: HashMap::new(),
+      bind_addr: Ipv4Addr::new(127, 0, 0, 1).into(),
+      set_bind_addrs: HashMap::new(),
+      dual_stack: false,
+      allowed_peers: Vec::new(),
+      proxy_protocol: ProxyProtocol::Socks,
+      set_proxy_protocols: HashMap::new(),
+      socks_credentials: HashMap::new(),
+      report_bound_addr: false,
+      timeouts: Timeouts::default(),
+      upstream: None,
+      set_upstreams: HashMap::new(),
+      forward_to_destination: false,
+      new_recordings: None,
+      check_authority: false,
+      close_event_streams: false,
+      range_requests: false,
+      cors: None,
+      fallbacks: Fallbacks::default(),
+      push_rules: PushRules::default(),
+      shared_port: None,
+      shared_default_set: None,
+      set_destinations: HashMap::new(),
+      set_ports: HashMap::new(),
+      port_offset: 0,
+      ephemeral_ports: false,
+    }
+  }
+
+  /// Builds and validates the config described by the config file,
+  /// all errors are caught here before any port is bound.
+  pub fn from_config_file(file: &ConfigFile) -> Result<Self, io::Error> {
+    if file.archives.is_empty() {
+      return Err(invalid_input("no recorded response set archives specified"));
+    }
+    file.validate()?;
+
+    let mut response_sets = read_response_set_archives(&file.archives)?;
+    check_h2_headers(
+      &mut response_sets,
+      file.strip_invalid_headers.unwrap_or(false),
+    )?;
+
+    Self::from_config_file_and_sets(file, response_sets)
+  }
+
+  /// Builds the config for recording a new response set, every request
+  /// is forwarded upstream (the CONNECT destination unless the file
+  /// has an upstream) and the responses are written to the output.
+  pub fn for_recording(file: &ConfigFile, recording: &Recording) -> Result<Self, io::Error> {
+    if !file.archives.is_empty() {
+      return Err(invalid_input(
+        "archives are not served when recording a response set",
+      ));
+    }
+    let mut file = file.clone();
+    file.new_recordings = Some(recording.output.clone());
+    if file.upstream_for(&recording.name).is_none() {
+      file.forward_to_destination = Some(true);
+    }
+    file.validate()?;
+
+    let request_key = read_request_key_cbor(&recording.request_key_program)?;
+    let response_set = RecordedResponseSet::new(
+      recording.port,
+      &recording.name,
+      recording.entry_key.as_deref().unwrap_or_default(),
+      request_key,
+    );
+
+    Self::from_config_file_and_sets(&file, vec![Arc::new(response_set)].into())
+  }
+
+  fn from_config_file_and_sets(
+    file: &ConfigFile,
+    response_sets: RecordedResponseSets,
+  ) -> Result<Self, io::Error> {
+    for name in file.sets.keys() {
+      if !response_sets.iter().any(|set| set.name() == name) {
+        let names: Vec<&str> = response_sets.iter().map(|set| set.name()).collect();
+        return Err(invalid_input(format!(
+          "settings for unknown response set {:?}, the archives contain {}",
+          name,
+          names.join(", ")
+        )));
+      }
+    }
+
+    let mut config = Self::from_tls_settings(&file.tls, response_sets)?
+      .with_default_tls_settings(&file.tls.without_cert())?;
+
+    let mut set_tls_settings = HashMap::new();
+    for (name, set) in file.sets.iter() {
+      if let Some(tls) = &set.tls {
+        set_tls_settings.insert(name.to_owned(), tls.clone());
+      }
+      if let Some(port) = set.port {
+        config.set_ports.insert(name.to_owned(), port);
+      }
+      if let Some(bind) = set.bind {
+        config.set_bind_addrs.insert(name.to_owned(), bind);
+      }
+      if let Some(proxy) = set.proxy {
+        config.set_proxy_protocols.insert(name.to_owned(), proxy);
+      }
+      if let Some(upstream) = &set.upstream {
+        config
+          .set_upstreams
+          .insert(name.to_owned(), upstream.clone());
+      }
+      if let Some(destinations) = &set.destinations {
+        config
+          .set_destinations
+          .insert(name.to_owned(), destinations.clone());
+      }
+      for fallback in set.fallbacks.iter() {
+        let rule = fallback.rule()?;
+        if let FallbackResponse::Recorded(key) = rule.response() {
+          let recorded = config.response_sets.iter().any(|response_set| {
+            response_set.name() == name && response_set.get_response(key).is_some()
+          });
+          if !recorded {
+            return Err(invalid_input(format!(
+              "sets.{}.fallbacks response {:?} is not recorded in the set",
+              name, key
+            )));
+          }
+        }
+        config.fallbacks = config.fallbacks.with_set_rule(name, rule);
+      }
+      for (key, resources) in set.push.iter().flatten() {
+        config.push_rules = config.push_rules.with_rule(name, key, resources.clone());
+      }
+    }
+    config = config.with_tls_settings(&set_tls_settings)?;
+    config.bind_addr = file.bind_addr();
+    config.dual_stack = file.dual_stack.unwrap_or(false);
+    config.allowed_peers = file.allow.clone().unwrap_or_default();
+    config.port_offset = file.port_offset.unwrap_or(0);
+    config.ephemeral_ports = file.ephemeral_ports.unwrap_or(false);
+    config.proxy_protocol = file.proxy.unwrap_or_default();
+    config.socks_credentials = file.socks.credentials.clone().unwrap_or_default();
+    config.report_bound_addr = file.socks.report_bound_addr.unwrap_or(false);
+    config.timeouts = file.timeouts.apply(config.timeouts)?;
+    config.upstream = file.upstream.clone();
+    config.forward_to_destination = file.forward_to_destination.unwrap_or(false);
+    config.new_recordings = file.new_recordings.clone();
+    config.check_authority = file.check_authority.unwrap_or(false);
+    config.close_event_streams = file.close_event_streams.unwrap_or(false);
+    config.range_requests = file.range_requests.unwrap_or(false);
+    if let Some(cors) = &file.cors {
+      config.cors = Some(cors.policy()?);
+    }
+    for fallback in file.fallbacks.iter() {
+      config.fallbacks = config.fallbacks.with_rule(fallback.rule()?);
+    }
+    if let Some(shared) = &file.shared {
+      config.shared_port = Some(shared.port);
+      config.shared_default_set = shared.default_set.clone();
+    }
+    config.validate_ports()?;
+
+    Ok(config)
+  }
+
+  /// Address the server for the response set listens on, port 0 means
+  /// the OS assigns one when the server binds. Fails if the recorded
+  /// port plus the port offset is out of range.
+  pub fn addr_for(&self, response_set: &RecordedResponseSet) -> Result<SocketAddr, io::Error> {
+    let bind_addr = match self.set_bind_addrs.get(response_set.name()) {
+      Some(&bind_addr) => bind_addr,
+      None => self.bind_addr,
+    };
+    Ok(SocketAddr::new(bind_addr, self.port_for(response_set)?))
+  }
+
+  /// Checks that every port is in range and that no two response sets
+  /// listen on the same port of overlapping bind addresses.
+  pub fn validate_ports(&self) -> Result<(), io::Error> {
+    if let Some(port) = self.shared_port {
+      return self.validate_shared(port);
+    }
+    let mut addrs: Vec<(SocketAddr, &str)> = Vec::with_capacity(self.response_sets.len());
+    for response_set in self.response_sets.iter() {
+      let addr = self.addr_for(response_set)?;
+      if addr.port() != 0 {
+        if let Some((other_addr, other)) = addrs
+          .iter()
+          .find(|(other_addr, _)| addrs_overlap(addr, *other_addr, self.dual_stack))
+        {
+          return Err(invalid_input(format!(
+            "response sets {:?} on {} and {:?} on {} use the same port",
+            other,
+            other_addr,
+            response_set.name(),
+            addr
+          )));
+        }
+        addrs.push((addr, response_set.name()));
+      }
+      if !addr.ip().is_loopback() && self.allowed_peers.is_empty() {
+        log::warn!(
+          "response set {} listens on {} without an allow list, recorded responses are exposed to the network",
+          response_set.name(),
+          addr
+        );
+      }
+    }
+    Ok(())
+  }
+
+  /// Proxy protocol the server for the named response set accepts.
+  pub fn proxy_protocol_for(&self, set_name: &str) -> ProxyProtocol {
+    match self.set_proxy_protocols.get(set_name) {
+      Some(&proxy_protocol) => proxy_protocol,
+      None => self.proxy_protocol,
+    }
+  }
+
+  /// Upstream for misses in the set, if any.
+  pub fn upstream_for(&self, set_name: &str) -> Option<&str> {
+    match self.set_upstreams.get(set_name) {
+      Some(upstream) => Some(upstream),
+      None => self.upstream.as_deref(),
+    }
+  }
+
+  /// TLS config for the named response set.
+  pub fn tls_config_for(&self, set_name: &str) -> Arc<rustls::ServerConfig> {
+    match self.set_tls_configs.get(set_name) {
+      Some(tls_config) => tls_config.clone(),
+      None => self.tls_config.clone(),
+    }
+  }
+
+  /// Apply TLS settings to the shared TLS config, this should be done
+  /// before per set overrides since they are applied on top of it.
+  pub fn with_default_tls_settings(mut self, settings: &TlsSettings) -> Result<Self, io::Error> {
+    self.tls_config = settings
+      .build(&self.tls_config)
+      .map_err(|err| with_context(err, "Invalid TLS settings"))?;
+    log::info!(
+      "TLS session resumption: {}",
+      settings.resumption_description().unwrap_or_default()
+    );
+    Ok(self)
+  }
+
+  /// Override TLS settings of response sets by name, settings are
+  /// applied on top of the shared TLS config.
+  pub fn with_tls_settings(
+    mut self,
+    settings: &HashMap<String, TlsSettings>,
+  ) -> Result<Self, io::Error> {
+    for (name, set_settings) in settings.iter() {
+      if !self.response_sets.iter().any(|set| set.name() == name) {
+        return Err(invalid_input(format!(
+          "TLS settings for unknown response set {:?}",
+          name
+        )));
+      }
+      let tls_config = set_settings.build(&self.tls_config).map_err(|err| {
+        with_context(
+          err,
+          format!("Invalid TLS settings for response set {:?}", name),
+        )
+      })?;
+      if let Some(cert) = &set_settings.cert {
+        let digest = spki_digest(read_cert_pem(cert)?[0].as_ref())?;
+        log::warn!(
+          "response set {} has its own certificate, its spki digest {} is needed as well as {}",
+          name,
+          digest,
+          self.spki_digest
+        );
+        self.set_spki_digests.insert(name.to_owned(), digest);
+      }
+      if let Some(resumption) = set_settings.resumption_description() {
+        log::info!(
+          "response set {} TLS session resumption: {}",
+          name,
+          resumption
+        );
+      }
+      self.set_tls_configs.insert(name.to_owned(), tls_config);
+    }
+    Ok(self)
+  }
+
+  /// Override TLS settings of response sets from a TOML or JSON file
+  /// mapping set names to TLS settings.
+  pub fn with_tls_config_file(self, path: &PathBuf) -> Result<Self, io::Error> {
+    let settings = read_tls_settings(path)?;
+    self.with_tls_settings(&settings)
+  }
+
+  fn validate_shared(&self, port: u16) -> Result<(), io::Error> {
+    if let Some(default_set) = &self.shared_default_set {
+      if !self
+        .response_sets
+        .iter()
+        .any(|set| set.name() == default_set)
+      {
+        return Err(invalid_input(format!(
+          "shared.default_set {:?} is not a response set",
+          default_set
+        )));
+      }
+    }
+    let addr = SocketAddr::new(self.bind_addr, port);
+    if !addr.ip().is_loopback() && self.allowed_peers.is_empty() {
+      log::warn!(
+        "shared server listens on {} without an allow list, recorded responses are exposed to the network",
+        addr
+      );
+    }
+    Ok(())
+  }
+
+  fn port_for(&self, response_set: &RecordedResponseSet) -> Result<u16, io::Error> {
+    if let Some(&port) = self.set_ports.get(response_set.name()) {
+      return Ok(port);
+    }
+    if self.ephemeral_ports {
+      return Ok(0);
+    }
+    let recorded = response_set.socks_port();
+    recorded.checked_add(self.port_offset).ok_or_else(|| {
+      invalid_input(format!(
+        "response set {:?} socks port {} plus port offset {} is out of range",
+        response_set.name(),
+        recorded,
+        self.port_offset
+      ))
+    })
+  }
+
+  fn from_tls_settings(
+    settings: &TlsSettings,
+    response_sets: RecordedResponseSets,
+  ) -> Result<Self, io::Error> {
+    match (&settings.cert, &settings.key) {
+      (Some(cert), Some(key)) if settings.is_ca() => Self::from_ca_parts(
+        read_cert_pem(cert)?.remove(0),
+        read_ca_key_pem(key)?,
+        response_sets,
+      ),
+      (Some(cert), Some(key)) => {
+        Self::from_parts(read_cert_pem(cert)?, read_key_pem(key)?, response_sets)
+      }
+      _ => Err(invalid_input("tls.cert and tls.key must be specified")),
     }
   }
 
@@ -43,6 +492,21 @@ impl Config {
     ))
   }
 
+  /// Config where a leaf certificate is minted for each SNI hostname and
+  /// signed by the local CA, any client that trusts the CA can connect.
+  pub fn from_ca_parts(
+    ca_cert: rustls::Certificate,
+    ca_key: rcgen::KeyPair,
+    response_sets: RecordedResponseSets,
+  ) -> Result<Self, io::Error> {
+    let authority = CertificateAuthority::new(ca_cert, ca_key)?;
+    Ok(Self::new(
+      spki_digest(authority.cert_der().as_ref())?,
+      build_tls_config_with_resolver(Arc::new(LeafCertResolver::new(authority))),
+      response_sets,
+    ))
+  }
+
   pub fn from_args(
     cert_pem: &PathBuf,
     key_pem: &PathBuf,
@@ -54,4 +518,16 @@ impl Config {
       read_response_set_cbor(response_sets_cbor)?,
     )
   }
+
+  pub fn from_ca_args(
+    ca_cert_pem: &PathBuf,
+    ca_key_pem: &PathBuf,
+    response_sets_cbor: &PathBuf,
+  ) -> Result<Self, io::Error> {
+    Self::from_ca_parts(
+      read_cert_pem(ca_cert_pem)?.remove(0),
+      read_ca_key_pem(ca_key_pem)?,
+      read_response_set_cbor(response_sets_cbor)?,
+    )
+  }
 }
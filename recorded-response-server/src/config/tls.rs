@@ -0,0 +1,421 @@
+use super::ca::{CertificateAuthority, LeafCertResolver};
+use super::util::{invalid_input, read_ca_key_pem, read_cert_pem, read_key_pem};
+use std::fmt;
+use std::io::Error;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+  NoServerSessionStorage, ProducesTickets, ProtocolVersion, ServerConfig, ServerSessionMemoryCache,
+  SupportedCipherSuite, Ticketer, ALL_CIPHERSUITES,
+};
+
+/// rustls default size of the stateful session cache
+const DEFAULT_SESSION_CACHE_SIZE: usize = 256;
+
+/// How clients may resume TLS sessions, a resumed connection skips
+/// the full handshake which changes page load timings a lot.
+#[derive(Debug, Clone, Copy, PartialEq, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resumption {
+  /// every connection does a full handshake
+  Disabled,
+  /// stateful session cache on the server
+  Cache,
+  /// stateless session tickets, no session cache
+  Tickets,
+}
+
+impl FromStr for Resumption {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "disabled" => Ok(Resumption::Disabled),
+      "cache" => Ok(Resumption::Cache),
+      "tickets" => Ok(Resumption::Tickets),
+      _ => Err(format!(
+        "unsupported resumption {:?}, expected disabled, cache or tickets",
+        s
+      )),
+    }
+  }
+}
+
+/// TLS settings for a response set, anything left unset falls back
+/// to the shared TLS config.
+#[derive(Debug, Default, Clone, serde_derive::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+  /// PEM certificate chain, requires key
+  pub cert: Option<PathBuf>,
+  /// PEM RSA private key, requires cert
+  pub key: Option<PathBuf>,
+  /// treat cert and key as a local CA that signs a leaf
+  /// certificate per SNI hostname, the key must be PKCS#8
+  pub ca: Option<bool>,
+  /// ALPN protocols in order of preference, for example ["h2"]
+  pub alpn: Option<Vec<String>>,
+  /// allowed TLS versions, "1.2" and/or "1.3"
+  pub versions: Option<Vec<String>>,
+  /// allowed cipher suites by IANA name, for example
+  /// "TLS13_AES_128_GCM_SHA256" or "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"
+  pub cipher_suites: Option<Vec<String>>,
+  /// session resumption mode
+  pub resumption: Option<Resumption>,
+  /// max sessions in the cache, only valid with the cache resumption mode
+  pub session_cache_size: Option<usize>,
+  /// accept 0-RTT early data, only valid with the tickets resumption mode
+  pub early_data: Option<bool>,
+}
+
+impl TlsSettings {
+  pub(super) fn is_ca(&self) -> bool {
+    self.ca == Some(true)
+  }
+
+  /// The settings without cert, key and ca for applying on top
+  /// of a config that was already built from them.
+  pub(super) fn without_cert(&self) -> TlsSettings {
+    TlsSettings {
+      cert: None,
+      key: None,
+      ca: None,
+      ..self.clone()
+    }
+  }
+
+  /// Resolve relative cert and key paths against the directory
+  /// of the file the settings were read from.
+  pub(super) fn resolve_paths(&mut self, base: &Path) {
+    if let Some(cert) = self.cert.take() {
+      self.cert = Some(base.join(cert));
+    }
+    if let Some(key) = self.key.take() {
+      self.key = Some(base.join(key));
+    }
+  }
+
+  /// Apply these settings on top of the shared config.
+  pub(super) fn build(&self, shared: &ServerConfig) -> Result<Arc<ServerConfig>, Error> {
+    let mut config = shared.clone();
+
+    match (&self.cert, &self.key) {
+      (Some(cert), Some(key)) if self.is_ca() => {
+        let authority =
+          CertificateAuthority::new(read_cert_pem(cert)?.remove(0), read_ca_key_pem(key)?)?;
+        config.cert_resolver = Arc::new(LeafCertResolver::new(authority));
+      }
+      (Some(cert), Some(key)) => {
+        config
+          .set_single_cert(read_cert_pem(cert)?, read_key_pem(key)?)
+          .map_err(invalid_input)?;
+      }
+      (None, None) if self.ca.is_some() => {
+        return Err(invalid_input("ca requires cert and key to be specified"))
+      }
+      (None, None) => (),
+      _ => return Err(invalid_input("cert and key must be specified together")),
+    }
+
+    if let Some(alpn) = &self.alpn {
+      config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+    }
+
+    if let Some(versions) = &self.versions {
+      config.versions = versions
+        .iter()
+        .map(|version| parse_version(version))
+        .collect::<Result<_, _>>()?;
+    }
+
+    if let Some(cipher_suites) = &self.cipher_suites {
+      config.ciphersuites = cipher_suites
+        .iter()
+        .map(|name| parse_cipher_suite(name))
+        .collect::<Result<_, _>>()?;
+    }
+
+    self.apply_resumption(&mut config)?;
+
+    let usable = config.versions.iter().any(|&version| {
+      config
+        .ciphersuites
+        .iter()
+        .any(|suite| suite.usable_for_version(version))
+    });
+    if !usable {
+      return Err(invalid_input(
+        "none of the cipher suites are usable with the allowed TLS versions",
+      ));
+    }
+
+    Ok(Arc::new(config))
+  }
+
+  /// Describes the session resumption these settings select, None if
+  /// they leave it unchanged.
+  pub(super) fn resumption_description(&self) -> Option<ResumptionDescription> {
+    self
+      .resumption
+      .map(|resumption| ResumptionDescription(resumption, self.session_cache_size))
+  }
+
+  fn apply_resumption(&self, config: &mut ServerConfig) -> Result<(), Error> {
+    if self.session_cache_size.is_some() && self.resumption != Some(Resumption::Cache) {
+      return Err(invalid_input(
+        "session_cache_size requires the cache resumption mode",
+      ));
+    }
+
+    if self.early_data == Some(true) {
+      // rustls 0.19 only implements server side early data for QUIC
+      return Err(invalid_input(
+        "early data (0-RTT) is not supported by the TLS implementation",
+      ));
+    }
+
+    match self.resumption {
+      Some(Resumption::Disabled) => {
+        config.session_storage = Arc::new(NoServerSessionStorage {});
+        config.ticketer = Arc::new(NoTickets);
+      }
+      Some(Resumption::Cache) => {
+        config.session_storage = ServerSessionMemoryCache::new(
+          self
+            .session_cache_size
+            .unwrap_or(DEFAULT_SESSION_CACHE_SIZE),
+        );
+        config.ticketer = Arc::new(NoTickets);
+      }
+      Some(Resumption::Tickets) => {
+        config.session_storage = Arc::new(NoServerSessionStorage {});
+        config.ticketer = Ticketer::new();
+      }
+      None => (),
+    }
+
+    Ok(())
+  }
+}
+
+/// Display of the chosen resumption mode for logging.
+pub(super) struct ResumptionDescription(Resumption, Option<usize>);
+
+impl Default for ResumptionDescription {
+  /// rustls defaults to a stateful session cache
+  fn default() -> Self {
+    ResumptionDescription(Resumption::Cache, None)
+  }
+}
+
+impl fmt::Display for ResumptionDescription {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0 {
+      Resumption::Disabled => f.write_str("disabled"),
+      Resumption::Cache => f.write_fmt(format_args!(
+        "session cache of {} entries",
+        self.1.unwrap_or(DEFAULT_SESSION_CACHE_SIZE)
+      )),
+      Resumption::Tickets => f.write_str("session tickets, early data disabled"),
+    }
+  }
+}
+
+/// Ticketer that never issues tickets, rustls does not export its own.
+struct NoTickets;
+
+impl ProducesTickets for NoTickets {
+  fn enabled(&self) -> bool {
+    false
+  }
+
+  fn get_lifetime(&self) -> u32 {
+    0
+  }
+
+  fn encrypt(&self, _plain: &[u8]) -> Option<Vec<u8>> {
+    None
+  }
+
+  fn decrypt(&self, _cipher: &[u8]) -> Option<Vec<u8>> {
+    None
+  }
+}
+
+fn parse_version(version: &str) -> Result<ProtocolVersion, Error> {
+  match version {
+    "1.2" => Ok(ProtocolVersion::TLSv1_2),
+    "1.3" => Ok(ProtocolVersion::TLSv1_3),
+    _ => Err(invalid_input(format!(
+      "unsupported TLS version {:?}, expected \"1.2\" or \"1.3\"",
+      version
+    ))),
+  }
+}
+
+fn parse_cipher_suite(name: &str) -> Result<&'static SupportedCipherSuite, Error> {
+  ALL_CIPHERSUITES
+    .iter()
+    .copied()
+    .find(|suite| format!("{:?}", suite.suite) == name)
+    .ok_or_else(|| {
+      let names: Vec<String> = ALL_CIPHERSUITES
+        .iter()
+        .map(|suite| format!("{:?}", suite.suite))
+        .collect();
+      invalid_input(format!(
+        "unsupported cipher suite {:?}, expected one of {}",
+        name,
+        names.join(", ")
+      ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio_rustls::rustls::{CipherSuite, NoClientAuth};
+
+  fn build(settings: TlsSettings) -> Result<Arc<ServerConfig>, Error> {
+    settings.build(&ServerConfig::new(NoClientAuth::new()))
+  }
+
+  fn build_err(settings: TlsSettings) -> String {
+    match build(settings) {
+      Ok(_) => panic!("settings are valid"),
+      Err(err) => err.to_string(),
+    }
+  }
+
+  fn strings(values: &[&str]) -> Option<Vec<String>> {
+    Some(values.iter().map(|value| (*value).to_owned()).collect())
+  }
+
+  #[test]
+  fn test_versions() {
+    let config = build(TlsSettings {
+      versions: strings(&["1.2"]),
+      ..TlsSettings::default()
+    })
+    .unwrap();
+    assert_eq!(config.versions, [ProtocolVersion::TLSv1_2]);
+
+    let err = build_err(TlsSettings {
+      versions: strings(&["1.1"]),
+      ..TlsSettings::default()
+    });
+    assert!(err.contains("unsupported TLS version"));
+  }
+
+  #[test]
+  fn test_cipher_suites() {
+    let config = build(TlsSettings {
+      cipher_suites: strings(&[
+        "TLS13_AES_128_GCM_SHA256",
+        "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+      ]),
+      ..TlsSettings::default()
+    })
+    .unwrap();
+    let suites: Vec<CipherSuite> = config
+      .ciphersuites
+      .iter()
+      .map(|suite| suite.suite)
+      .collect();
+    assert_eq!(
+      suites,
+      [
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+        CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+      ]
+    );
+
+    let err = build_err(TlsSettings {
+      cipher_suites: strings(&["TLS_RSA_WITH_RC4_128_MD5"]),
+      ..TlsSettings::default()
+    });
+    assert!(err.contains("unsupported cipher suite"));
+  }
+
+  #[test]
+  fn test_alpn() {
+    let config = build(TlsSettings {
+      alpn: strings(&["h2", "http/1.1"]),
+      ..TlsSettings::default()
+    })
+    .unwrap();
+    assert_eq!(
+      config.alpn_protocols,
+      [b"h2".to_vec(), b"http/1.1".to_vec()]
+    );
+  }
+
+  #[test]
+  fn test_unusable_combination() {
+    let err = build_err(TlsSettings {
+      versions: strings(&["1.3"]),
+      cipher_suites: strings(&["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"]),
+      ..TlsSettings::default()
+    });
+    assert!(err
+      .to_string()
+      .contains("none of the cipher suites are usable"));
+  }
+
+  /// How many of the sessions put in the session storage it keeps.
+  fn stored_sessions(config: &ServerConfig, count: u8) -> usize {
+    for i in 0..count {
+      config.session_storage.put(vec![i], vec![i]);
+    }
+    (0..count)
+      .filter(|i| config.session_storage.get(&[*i]).is_some())
+      .count()
+  }
+
+  #[test]
+  fn test_resumption_modes() {
+    let disabled = build(TlsSettings {
+      resumption: Some(Resumption::Disabled),
+      ..TlsSettings::default()
+    })
+    .unwrap();
+    assert_eq!(stored_sessions(&disabled, 4), 0);
+    assert!(!disabled.ticketer.enabled());
+
+    let cache = build(TlsSettings {
+      resumption: Some(Resumption::Cache),
+      session_cache_size: Some(2),
+      ..TlsSettings::default()
+    })
+    .unwrap();
+    assert_eq!(stored_sessions(&cache, 4), 2);
+    assert!(!cache.ticketer.enabled());
+
+    let tickets = build(TlsSettings {
+      resumption: Some(Resumption::Tickets),
+      ..TlsSettings::default()
+    })
+    .unwrap();
+    assert_eq!(stored_sessions(&tickets, 4), 0);
+    assert!(tickets.ticketer.enabled());
+  }
+
+  #[test]
+  fn test_invalid_resumption_settings() {
+    let err = build_err(TlsSettings {
+      resumption: Some(Resumption::Tickets),
+      session_cache_size: Some(2),
+      ..TlsSettings::default()
+    });
+    assert!(err.contains("requires the cache resumption mode"));
+
+    let err = build_err(TlsSettings {
+      resumption: Some(Resumption::Tickets),
+      early_data: Some(true),
+      ..TlsSettings::default()
+    });
+    assert!(err.contains("early data"));
+  }
+}
@@ -1,14 +1,26 @@
+use super::TlsSettings;
+use http::header::HeaderName;
 use memmap::Mmap;
+use rcgen::KeyPair;
 use ring::digest::digest;
 use ring::digest::SHA256;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
 use std::error;
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio_rustls::rustls::internal::pemfile;
-use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+use tokio_rustls::rustls::{
+  Certificate, NoClientAuth, PrivateKey, ResolvesServerCert, ServerConfig,
+};
 use tracerbench_recorded_response_set::RecordedResponseSets;
+use tracerbench_request_key::RequestKey;
 use webpki::TrustAnchor;
 
 static ALPN_H2: &[u8] = b"h2";
@@ -23,8 +35,17 @@ pub(super) fn build_tls_config(
   Ok(Arc::new(config))
 }
 
+pub(super) fn build_tls_config_with_resolver(
+  resolver: Arc<dyn ResolvesServerCert>,
+) -> Arc<ServerConfig> {
+  let mut config = ServerConfig::new(NoClientAuth::new());
+  config.cert_resolver = resolver;
+  config.alpn_protocols.push(ALPN_H2.to_vec());
+  Arc::new(config)
+}
+
 pub(super) fn read_cert_pem(path: &PathBuf) -> Result<Vec<Certificate>, Error> {
-  let file = File::open(path)?;
+  let file = open_file(path)?;
   let mut reader = BufReader::new(&file);
   if let Ok(certs) = pemfile::certs(&mut reader) {
     if certs.is_empty() {
@@ -38,7 +59,7 @@ pub(super) fn read_cert_pem(path: &PathBuf) -> Result<Vec<Certificate>, Error> {
 }
 
 pub(super) fn read_key_pem(path: &PathBuf) -> Result<PrivateKey, Error> {
-  let file = File::open(path)?;
+  let file = open_file(path)?;
   let mut reader = BufReader::new(&file);
   if let Ok(mut private_keys) = pemfile::rsa_private_keys(&mut reader) {
     if private_keys.is_empty() {
@@ -51,13 +72,132 @@ pub(super) fn read_key_pem(path: &PathBuf) -> Result<PrivateKey, Error> {
   }
 }
 
+/// Reads a PKCS#8 private key for signing leaf certificates,
+/// ECDSA, Ed25519 and RSA keys are supported.
+pub(super) fn read_ca_key_pem(path: &PathBuf) -> Result<KeyPair, Error> {
+  let pem = read_to_string(path)?;
+  KeyPair::from_pem(&pem).map_err(|_| missing_pkcs8_private_key(path))
+}
+
 pub(super) fn read_response_set_cbor(path: &PathBuf) -> Result<RecordedResponseSets, Error> {
-  let file = File::open(path)?;
+  let file = open_file(path)?;
   let mmap = unsafe { Mmap::map(&file)? };
-  let response_sets = serde_cbor::from_slice(&mmap).map_err(invalid_data)?;
+  let response_sets = serde_cbor::from_slice(&mmap)
+    .map_err(|err| invalid_data(format!("Invalid response set archive {:?}: {}", path, err)))?;
   Ok(response_sets)
 }
 
+/// Reads a CBOR request key program, as found in the requestKeyProgram
+/// of a response set.
+pub(super) fn read_request_key_cbor(path: &PathBuf) -> Result<RequestKey, Error> {
+  let file = open_file(path)?;
+  serde_cbor::from_reader(BufReader::new(file))
+    .map_err(|err| invalid_data(format!("Invalid request key program {:?}: {}", path, err)))
+}
+
+/// Reads the archives into one list of response sets, set names
+/// must be unique across archives.
+pub(super) fn read_response_set_archives(paths: &[PathBuf]) -> Result<RecordedResponseSets, Error> {
+  let mut response_sets = read_response_set_cbor(&paths[0])?;
+  for path in &paths[1..] {
+    let mut more = read_response_set_cbor(path)?;
+    for response_set in more.drain(..) {
+      if response_sets
+        .iter()
+        .any(|existing| existing.name() == response_set.name())
+      {
+        return Err(invalid_input(format!(
+          "response set {:?} in {:?} was already loaded from another archive",
+          response_set.name(),
+          path
+        )));
+      }
+      response_sets.push(response_set);
+    }
+  }
+  Ok(response_sets)
+}
+
+/// Warns about each recorded header HTTP/2 forbids by set and request
+/// key, or strips them if strip is set, which needs the sets to not be
+/// shared yet.
+pub(super) fn check_h2_headers(
+  response_sets: &mut RecordedResponseSets,
+  strip: bool,
+) -> Result<(), Error> {
+  for response_set in response_sets.iter_mut() {
+    let invalid: Vec<(String, HeaderName)> = response_set
+      .invalid_h2_headers()
+      .into_iter()
+      .map(|(key, name)| (key.to_owned(), name.clone()))
+      .collect();
+    if invalid.is_empty() {
+      continue;
+    }
+    if !strip {
+      for (key, name) in invalid.iter() {
+        log::warn!(
+          "{} {:?} has a {} header, which HTTP/2 forbids and fails the response, see strip_invalid_headers",
+          response_set.name(),
+          key,
+          name
+        );
+      }
+      continue;
+    }
+    let name = response_set.name().to_owned();
+    match Arc::get_mut(response_set) {
+      Some(response_set) => response_set.strip_invalid_h2_headers(),
+      None => {
+        return Err(invalid_input(format!(
+          "response set {:?} is already shared, its invalid HTTP/2 headers cannot be stripped",
+          name
+        )))
+      }
+    }
+    for (key, header) in invalid.iter() {
+      log::info!("{} stripped {} header of {:?}", name, header, key);
+    }
+  }
+  Ok(())
+}
+
+/// Whether both addresses can't be bound, an unspecified address takes
+/// the port on every address of its family, and of both families when
+/// an IPv6 address is dual stack.
+pub(super) fn addrs_overlap(a: SocketAddr, b: SocketAddr, dual_stack: bool) -> bool {
+  let covers = |unspecified: IpAddr, other: IpAddr| {
+    unspecified.is_unspecified()
+      && (unspecified.is_ipv4() == other.is_ipv4() || (dual_stack && unspecified.is_ipv6()))
+  };
+  a.port() == b.port() && (a.ip() == b.ip() || covers(a.ip(), b.ip()) || covers(b.ip(), a.ip()))
+}
+
+/// Reads a config file as JSON if it has a .json extension otherwise as TOML.
+pub(super) fn read_config_file<T>(path: &PathBuf) -> Result<T, Error>
+where
+  T: DeserializeOwned,
+{
+  let text = read_to_string(path)?;
+  let is_json = path.extension() == Some(OsStr::new("json"));
+  let result = if is_json {
+    serde_json::from_str(&text).map_err(invalid_data)
+  } else {
+    toml::from_str(&text).map_err(invalid_data)
+  };
+  result.map_err(|err| with_context(err, format!("Invalid config file {:?}", path)))
+}
+
+/// Reads a file mapping response set names to TLS settings.
+pub(super) fn read_tls_settings(path: &PathBuf) -> Result<HashMap<String, TlsSettings>, Error> {
+  let mut settings: HashMap<String, TlsSettings> = read_config_file(path)?;
+  let base = path.parent().unwrap_or_else(|| Path::new(""));
+  for set_settings in settings.values_mut() {
+    set_settings.resolve_paths(base);
+  }
+  Ok(settings)
+}
+
 /// The base64 encoded SHA256 digest of subject public key info.
 /// This is needed for the chrome command line switch ignore-certificate-errors-spki-list
 pub(super) fn spki_digest(cert_der: &[u8]) -> Result<String, Error> {
@@ -99,6 +239,14 @@ pub(super) fn spki_digest(cert_der: &[u8]) -> Result<String, Error> {
   Ok(base64::encode(spki_digest.as_ref()))
 }
 
+fn open_file(path: &PathBuf) -> Result<File, Error> {
+  File::open(path).map_err(|err| with_context(err, format!("{:?}", path)))
+}
+
+fn read_to_string(path: &PathBuf) -> Result<String, Error> {
+  std::fs::read_to_string(path).map_err(|err| with_context(err, format!("{:?}", path)))
+}
+
 fn missing_certificate(path: &PathBuf) -> Error {
   invalid_input(format!(
     "Missing CERTIFICATE section in PEM file: {:?}",
@@ -113,18 +261,33 @@ fn missing_rsa_private_key(path: &PathBuf) -> Error {
   ))
 }
 
+fn missing_pkcs8_private_key(path: &PathBuf) -> Error {
+  invalid_input(format!(
+    "Missing or unsupported PRIVATE KEY section in PEM file: {:?}",
+    path
+  ))
+}
+
 fn invalid_pem_file(path: &PathBuf) -> Error {
   invalid_data(format!("Invalid PEM file: {:?}", path))
 }
 
-fn invalid_data<E>(err: E) -> Error
+/// Prefix the error message keeping the error kind.
+pub(super) fn with_context<C>(err: Error, context: C) -> Error
+where
+  C: fmt::Display,
+{
+  Error::new(err.kind(), format!("{}: {}", context, err))
+}
+
+pub(super) fn invalid_data<E>(err: E) -> Error
 where
   E: Into<Box<dyn error::Error + Send + Sync>>,
 {
   Error::new(ErrorKind::InvalidData, err)
 }
 
-fn invalid_input<E>(err: E) -> Error
+pub(super) fn invalid_input<E>(err: E) -> Error
 where
   E: Into<Box<dyn error::Error + Send + Sync>>,
 {
@@ -1,10 +1,35 @@
 #![warn(rust_2018_idioms)]
 #![warn(clippy::all)]
 
+mod cidr;
 mod config;
 mod server;
 mod servers;
 
+pub use cidr::Cidr;
 pub use config::Config;
+pub use config::ConfigFile;
+pub use config::CorsSettings;
+pub use config::FallbackSettings;
+pub use config::LogFormat;
+pub use config::Recording;
+pub use config::Resumption;
+pub use config::SetSettings;
+pub use config::SharedSettings;
+pub use config::SocksSettings;
+pub use config::TimeoutSettings;
+pub use config::TlsSettings;
+pub use server::CorsPolicy;
+pub use server::DestinationPattern;
+pub use server::FallbackResponse;
+pub use server::FallbackRule;
+pub use server::Fallbacks;
+pub use server::NewRecordings;
+pub use server::ProxyProtocol;
+pub use server::PushRules;
+pub use server::Route;
 pub use server::Server;
+pub use server::ServerError;
+pub use server::Timeouts;
+pub use server::Upstream;
 pub use servers::Servers;
@@ -0,0 +1,126 @@
+use http::header::HeaderMap;
+use http::header::HeaderName;
+use http::header::HeaderValue;
+use http::header::CACHE_CONTROL;
+use http::header::CONTENT_LOCATION;
+use http::header::DATE;
+use http::header::ETAG;
+use http::header::EXPIRES;
+use http::header::IF_MODIFIED_SINCE;
+use http::header::IF_NONE_MATCH;
+use http::header::LAST_MODIFIED;
+use http::header::VARY;
+use http::Response;
+use http::StatusCode;
+
+/// The headers of the 200 response a 304 response repeats (RFC 9110 15.4.5).
+const NOT_MODIFIED_HEADERS: [HeaderName; 7] = [
+  CACHE_CONTROL,
+  CONTENT_LOCATION,
+  DATE,
+  ETAG,
+  EXPIRES,
+  LAST_MODIFIED,
+  VARY,
+];
+
+/// The 304 response if the validators of a GET or HEAD request match
+/// the recorded 200 response. If-None-Match takes precedence over
+/// If-Modified-Since, which is only compared to Last-Modified.
+pub(super) fn not_modified(
+  request_headers: &HeaderMap,
+  response: &Response<()>,
+) -> Option<Response<()>> {
+  if response.status() != StatusCode::OK {
+    return None;
+  }
+  let headers = response.headers();
+  let matches = if let Some(if_none_match) = request_headers.get(IF_NONE_MATCH) {
+    etag_matches(if_none_match, headers.get(ETAG)?)
+  } else if let Some(if_modified_since) = request_headers.get(IF_MODIFIED_SINCE) {
+    not_modified_since(if_modified_since, headers.get(LAST_MODIFIED)?)
+  } else {
+    false
+  };
+  if !matches {
+    return None;
+  }
+  let mut not_modified = Response::new(());
+  *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+  for name in NOT_MODIFIED_HEADERS.iter() {
+    for value in headers.get_all(name) {
+      not_modified.headers_mut().append(name, value.clone());
+    }
+  }
+  Some(not_modified)
+}
+
+/// Weak comparison of the If-None-Match list with the ETag.
+fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+  let (if_none_match, etag) = match (if_none_match.to_str(), etag.to_str()) {
+    (Ok(if_none_match), Ok(etag)) => (if_none_match, etag),
+    _ => return false,
+  };
+  let etag = opaque_tag(etag);
+  if_none_match
+    .split(',')
+    .map(str::trim)
+    .any(|tag| tag == "*" || opaque_tag(tag) == etag)
+}
+
+fn opaque_tag(tag: &str) -> &str {
+  tag.trim().trim_start_matches("W/")
+}
+
+/// Last-Modified is not later than If-Modified-Since, invalid dates
+/// never match.
+fn not_modified_since(if_modified_since: &HeaderValue, last_modified: &HeaderValue) -> bool {
+  let parse = |value: &HeaderValue| {
+    value
+      .to_str()
+      .ok()
+      .and_then(|value| httpdate::parse_http_date(value).ok())
+  };
+  match (parse(if_modified_since), parse(last_modified)) {
+    (Some(if_modified_since), Some(last_modified)) => last_modified <= if_modified_since,
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn value(value: &'static str) -> HeaderValue {
+    HeaderValue::from_static(value)
+  }
+
+  #[test]
+  fn test_etag_matches() {
+    assert!(etag_matches(&value("\"a\""), &value("\"a\"")));
+    assert!(etag_matches(&value("\"b\", W/\"a\""), &value("\"a\"")));
+    assert!(etag_matches(&value("\"a\""), &value("W/\"a\"")));
+    assert!(etag_matches(&value("*"), &value("\"a\"")));
+    assert!(!etag_matches(&value("\"b\""), &value("\"a\"")));
+    assert!(!etag_matches(&value("a"), &value("\"a\"")));
+  }
+
+  #[test]
+  fn test_not_modified_since() {
+    let last_modified = value("Tue, 15 Nov 1994 12:45:26 GMT");
+    assert!(not_modified_since(
+      &value("Tue, 15 Nov 1994 12:45:26 GMT"),
+      &last_modified
+    ));
+    // obsolete RFC 850 date
+    assert!(not_modified_since(
+      &value("Wednesday, 16-Nov-94 00:00:00 GMT"),
+      &last_modified
+    ));
+    assert!(!not_modified_since(
+      &value("Tue, 15 Nov 1994 12:45:25 GMT"),
+      &last_modified
+    ));
+    assert!(!not_modified_since(&value("yesterday"), &last_modified));
+  }
+}
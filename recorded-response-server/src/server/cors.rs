@@ -0,0 +1,136 @@
+use http::header::HeaderMap;
+use http::header::HeaderName;
+use http::header::HeaderValue;
+use http::header::ACCESS_CONTROL_ALLOW_CREDENTIALS;
+use http::header::ACCESS_CONTROL_ALLOW_HEADERS;
+use http::header::ACCESS_CONTROL_ALLOW_METHODS;
+use http::header::ACCESS_CONTROL_ALLOW_ORIGIN;
+use http::header::ACCESS_CONTROL_EXPOSE_HEADERS;
+use http::header::ACCESS_CONTROL_MAX_AGE;
+use http::header::ACCESS_CONTROL_REQUEST_HEADERS;
+use http::header::ACCESS_CONTROL_REQUEST_METHOD;
+use http::header::ORIGIN;
+use http::header::VARY;
+use http::Method;
+use http::Response;
+use http::StatusCode;
+
+/// How CORS preflights missing from a set are answered and which
+/// access-control headers are added to recorded responses without
+/// them. Unset fields echo the request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsPolicy {
+  /// access-control-allow-origin, None echoes the Origin of the request
+  pub allow_origin: Option<String>,
+  /// preflight access-control-allow-methods, empty echoes the requested method
+  pub allow_methods: Vec<String>,
+  /// preflight access-control-allow-headers, empty echoes the requested headers
+  pub allow_headers: Vec<String>,
+  /// access-control-expose-headers of recorded responses
+  pub expose_headers: Vec<String>,
+  /// send access-control-allow-credentials: true, browsers reject it
+  /// with an allowed origin of "*"
+  pub allow_credentials: bool,
+  /// preflight access-control-max-age in seconds
+  pub max_age: Option<u64>,
+}
+
+impl Default for CorsPolicy {
+  /// Echoes the request and allows credentials, any cross-origin
+  /// request the page makes is allowed.
+  fn default() -> Self {
+    CorsPolicy {
+      allow_origin: None,
+      allow_methods: Vec::new(),
+      allow_headers: Vec::new(),
+      expose_headers: Vec::new(),
+      allow_credentials: true,
+      max_age: None,
+    }
+  }
+}
+
+impl CorsPolicy {
+  /// The 204 response to a preflight request, None if the request is
+  /// not a preflight.
+  pub(super) fn preflight(&self, method: &Method, headers: &HeaderMap) -> Option<Response<()>> {
+    if method != Method::OPTIONS {
+      return None;
+    }
+    let origin = headers.get(ORIGIN)?;
+    let request_method = headers.get(ACCESS_CONTROL_REQUEST_METHOD)?;
+    let mut response = Response::new(());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    self.allow_origin(origin, response.headers_mut());
+    let response_headers = response.headers_mut();
+    if self.allow_methods.is_empty() {
+      response_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, request_method.clone());
+    } else {
+      insert_list(
+        response_headers,
+        ACCESS_CONTROL_ALLOW_METHODS,
+        &self.allow_methods,
+      );
+    }
+    if !self.allow_headers.is_empty() {
+      insert_list(
+        response_headers,
+        ACCESS_CONTROL_ALLOW_HEADERS,
+        &self.allow_headers,
+      );
+    } else if let Some(request_headers) = headers.get(ACCESS_CONTROL_REQUEST_HEADERS) {
+      response_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, request_headers.clone());
+    }
+    if let Some(max_age) = self.max_age {
+      response_headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+    Some(response)
+  }
+
+  /// Adds the access-control headers to a response to a cross-origin
+  /// request unless it was recorded with access-control-allow-origin.
+  pub(super) fn add_headers(&self, request_headers: &HeaderMap, response: &mut Response<()>) {
+    let origin = match request_headers.get(ORIGIN) {
+      Some(origin) => origin,
+      None => return,
+    };
+    if response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+      return;
+    }
+    self.allow_origin(origin, response.headers_mut());
+    if !self.expose_headers.is_empty() {
+      insert_list(
+        response.headers_mut(),
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        &self.expose_headers,
+      );
+    }
+  }
+
+  fn allow_origin(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+    let allow_origin = match &self.allow_origin {
+      Some(allow_origin) => HeaderValue::from_str(allow_origin).ok(),
+      None => {
+        // the response differs by origin
+        headers.append(VARY, HeaderValue::from_static("origin"));
+        Some(origin.clone())
+      }
+    };
+    if let Some(allow_origin) = allow_origin {
+      headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    }
+    if self.allow_credentials {
+      headers.insert(
+        ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        HeaderValue::from_static("true"),
+      );
+    }
+  }
+}
+
+/// Inserts the comma separated list, invalid header values are left out.
+fn insert_list(headers: &mut HeaderMap, name: HeaderName, values: &[String]) {
+  if let Ok(value) = HeaderValue::from_str(&values.join(", ")) {
+    headers.insert(name, value);
+  }
+}
@@ -1,15 +1,24 @@
 use std::error;
 use std::fmt;
 use std::io;
+use std::time::Duration;
 use tokio_rustls::rustls::TLSError;
+use tracerbench_http_connect_proxy::HttpConnectError;
 use tracerbench_socks_proxy::SocksError;
+use tracerbench_socks_proxy::SocksRequest;
 
 #[derive(Debug)]
-pub(super) enum ServerError {
+pub enum ServerError {
   IO(io::Error),
   Socks(SocksError),
+  HttpConnect(HttpConnectError),
   TLS(TLSError),
   H2(h2::Error),
+  NoRoute(SocksRequest),
+  ProxyTimeout(Duration),
+  TlsTimeout(Duration),
+  PrefaceTimeout(Duration),
+  IdleTimeout(Duration),
 }
 
 impl From<io::Error> for ServerError {
@@ -24,6 +33,12 @@ impl From<SocksError> for ServerError {
   }
 }
 
+impl From<HttpConnectError> for ServerError {
+  fn from(err: HttpConnectError) -> ServerError {
+    ServerError::HttpConnect(err)
+  }
+}
+
 impl From<TLSError> for ServerError {
   fn from(err: TLSError) -> ServerError {
     ServerError::TLS(err)
@@ -41,8 +56,14 @@ impl error::Error for ServerError {
     match *self {
       ServerError::IO(ref err) => error::Error::source(err),
       ServerError::Socks(ref err) => error::Error::source(err),
+      ServerError::HttpConnect(ref err) => error::Error::source(err),
       ServerError::TLS(ref err) => error::Error::source(err),
       ServerError::H2(ref err) => error::Error::source(err),
+      ServerError::NoRoute(_)
+      | ServerError::ProxyTimeout(_)
+      | ServerError::TlsTimeout(_)
+      | ServerError::PrefaceTimeout(_)
+      | ServerError::IdleTimeout(_) => None,
     }
   }
 }
@@ -52,8 +73,27 @@ impl fmt::Display for ServerError {
     match *self {
       ServerError::IO(ref err) => err.fmt(f),
       ServerError::Socks(ref err) => err.fmt(f),
+      ServerError::HttpConnect(ref err) => err.fmt(f),
       ServerError::TLS(ref err) => err.fmt(f),
       ServerError::H2(ref err) => err.fmt(f),
+      ServerError::NoRoute(ref request) => {
+        f.write_fmt(format_args!("no response set for CONNECT {}", request))
+      }
+      ServerError::ProxyTimeout(duration) => f.write_fmt(format_args!(
+        "proxy handshake timed out after {:?}",
+        duration
+      )),
+      ServerError::TlsTimeout(duration) => {
+        f.write_fmt(format_args!("TLS handshake timed out after {:?}", duration))
+      }
+      ServerError::PrefaceTimeout(duration) => f.write_fmt(format_args!(
+        "HTTP2 connection preface timed out after {:?}",
+        duration
+      )),
+      ServerError::IdleTimeout(duration) => f.write_fmt(format_args!(
+        "HTTP2 connection closed after being idle for {:?}",
+        duration
+      )),
     }
   }
 }
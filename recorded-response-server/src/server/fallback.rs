@@ -0,0 +1,123 @@
+use bytes::Bytes;
+use http::Response;
+use std::collections::HashMap;
+use tracerbench_recorded_response_set::RecordedResponse;
+use tracerbench_recorded_response_set::RecordedResponseSet;
+
+/// Responses for requests missing from the sets instead of 404,
+/// picked by the first rule whose pattern matches the request key.
+/// The rules of a set are checked before the rules for all sets.
+#[derive(Debug, Clone, Default)]
+pub struct Fallbacks {
+  rules: Vec<FallbackRule>,
+  set_rules: HashMap<String, Vec<FallbackRule>>,
+}
+
+/// A request key pattern and the response for matching misses.
+#[derive(Debug, Clone)]
+pub struct FallbackRule {
+  pattern: String,
+  response: FallbackResponse,
+}
+
+#[derive(Debug, Clone)]
+pub enum FallbackResponse {
+  /// this response
+  Inline(RecordedResponse),
+  /// the recorded response for this request key of the set, the rule
+  /// is skipped in sets without it
+  Recorded(String),
+}
+
+impl Fallbacks {
+  /// Adds a rule checked for misses in every set.
+  pub fn with_rule(mut self, rule: FallbackRule) -> Self {
+    self.rules.push(rule);
+    self
+  }
+
+  /// Adds a rule checked for misses in the named set.
+  pub fn with_set_rule(mut self, set_name: &str, rule: FallbackRule) -> Self {
+    self
+      .set_rules
+      .entry(set_name.to_owned())
+      .or_default()
+      .push(rule);
+    self
+  }
+
+  /// The fallback response for a miss of the key in the set.
+  pub(super) fn response_for(
+    &self,
+    set: &RecordedResponseSet,
+    key: &str,
+  ) -> Option<(Response<()>, Option<Bytes>)> {
+    let set_rules = self.set_rules.get(set.name()).into_iter().flatten();
+    set_rules
+      .chain(self.rules.iter())
+      .filter(|rule| matches_pattern(&rule.pattern, key))
+      .find_map(|rule| match &rule.response {
+        FallbackResponse::Inline(response) => Some(response.to_parts()),
+        FallbackResponse::Recorded(key) => set.get_response(key).map(RecordedResponse::to_parts),
+      })
+  }
+}
+
+impl FallbackRule {
+  /// A rule for request keys matching the pattern, * in the pattern
+  /// matches any characters.
+  pub fn new(pattern: &str, response: FallbackResponse) -> Self {
+    FallbackRule {
+      pattern: pattern.to_owned(),
+      response,
+    }
+  }
+
+  pub fn response(&self) -> &FallbackResponse {
+    &self.response
+  }
+}
+
+/// Matches text against a pattern where * matches any characters.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+  let mut parts = pattern.split('*');
+  let first = parts.next().unwrap_or_default();
+  let mut rest = match text.strip_prefix(first) {
+    Some(rest) => rest,
+    None => return false,
+  };
+  let parts: Vec<&str> = parts.collect();
+  let (last, middle) = match parts.split_last() {
+    Some(split) => split,
+    // no * in the pattern
+    None => return rest.is_empty(),
+  };
+  for part in middle {
+    match rest.find(part) {
+      Some(i) => rest = &rest[i + part.len()..],
+      None => return false,
+    }
+  }
+  rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_matches_pattern() {
+    assert!(matches_pattern("GET localhost /", "GET localhost /"));
+    assert!(!matches_pattern("GET localhost /", "GET localhost /a"));
+    assert!(matches_pattern("*", ""));
+    assert!(matches_pattern("POST * /beacon*", "POST a.com /beacon?v=1"));
+    assert!(!matches_pattern("POST * /beacon*", "GET a.com /beacon"));
+    assert!(matches_pattern(
+      "*/api/telemetry",
+      "GET a.com /api/telemetry"
+    ));
+    assert!(matches_pattern("a*b*b", "abb"));
+    assert!(!matches_pattern("a*b*b", "ab"));
+    assert!(!matches_pattern("a*ba", "aba*"));
+  }
+}
@@ -1,23 +1,59 @@
+mod conditional;
+mod cors;
 mod error;
+mod fallback;
+mod proxy;
+mod push;
+mod range;
+mod router;
 mod serve;
+mod timeouts;
+mod upstream;
+mod websocket;
 
-use error::ServerError;
+use crate::Cidr;
+pub use cors::CorsPolicy;
+pub use error::ServerError;
+pub use fallback::FallbackResponse;
+pub use fallback::FallbackRule;
+pub use fallback::Fallbacks;
+use proxy::proxy_handshake;
+pub use proxy::ProxyProtocol;
+pub use push::PushRules;
+pub use router::DestinationPattern;
+pub use router::Route;
+use router::Router;
 use serve::serve_h2;
+use serve::ServeOptions;
+use socket2::{Domain, Protocol, Socket, Type};
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use timeouts::with_timeout;
+pub use timeouts::Timeouts;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
 use tokio_rustls::rustls;
 use tokio_rustls::TlsAcceptor;
 use tracerbench_recorded_response_set::RecordedResponseSet;
-use tracerbench_socks_proxy::socks5_handshake;
+use tracerbench_socks_proxy::Authentication;
+use tracerbench_socks_proxy::SocksRequest;
+pub use upstream::NewRecordings;
+pub use upstream::Upstream;
 
-/// Server listens on a port with a socks proxy -> tls -> h2
-/// serving recorded responses from a set
+/// Server listens on a port with a socks (or HTTP CONNECT) proxy -> tls -> h2
+/// serving recorded responses from a set, or from several sets
+/// when the server is shared
 pub struct Server {
-  tls_config: Arc<rustls::ServerConfig>,
-  response_set: Arc<RecordedResponseSet>,
+  router: Arc<Router>,
+  addr: SocketAddr,
+  dual_stack: bool,
+  allowed_peers: Arc<Vec<Cidr>>,
+  proxy_protocol: ProxyProtocol,
+  authentication: Arc<Authentication>,
+  report_bound_addr: bool,
+  timeouts: Timeouts,
+  options: ServeOptions,
 }
 
 impl Server {
@@ -25,58 +61,277 @@ impl Server {
     tls_config: Arc<rustls::ServerConfig>,
     response_set: Arc<RecordedResponseSet>,
   ) -> Self {
-    Server {
+    Self::from_route(Route {
       tls_config,
       response_set,
+      destinations: Vec::new(),
+      upstream: None,
+    })
+  }
+
+  /// Server dedicated to the set of the route, destinations are
+  /// ignored since every connection is routed to the set.
+  pub fn from_route(route: Route) -> Self {
+    let addr = SocketAddr::new(
+      Ipv4Addr::new(127, 0, 0, 1).into(),
+      route.response_set.socks_port(),
+    );
+    let router = Router::single(route);
+    Server {
+      router: Arc::new(router),
+      addr,
+      dual_stack: false,
+      allowed_peers: Arc::new(Vec::new()),
+      proxy_protocol: ProxyProtocol::Socks,
+      authentication: Arc::new(Authentication::None),
+      report_bound_addr: false,
+      timeouts: Timeouts::default(),
+      options: ServeOptions::default(),
     }
   }
 
+  /// Server for several sets on one port, a connection is routed by
+  /// socks username (the set name), then by CONNECT destination and
+  /// otherwise to the default set if there is one.
+  pub fn shared(addr: SocketAddr, routes: Vec<Route>, default_set: Option<String>) -> Self {
+    Server {
+      router: Arc::new(Router::shared(routes, default_set)),
+      addr,
+      dual_stack: false,
+      allowed_peers: Arc::new(Vec::new()),
+      proxy_protocol: ProxyProtocol::Socks,
+      authentication: Arc::new(Authentication::AnyUsername),
+      report_bound_addr: false,
+      timeouts: Timeouts::default(),
+      options: ServeOptions::default(),
+    }
+  }
+
+  /// Listen on the address instead of the recorded socks port on localhost.
+  pub fn with_addr(mut self, addr: SocketAddr) -> Self {
+    self.addr = addr;
+    self
+  }
+
+  /// Also accept IPv4 connections when listening on an IPv6 address.
+  pub fn with_dual_stack(mut self, dual_stack: bool) -> Self {
+    self.dual_stack = dual_stack;
+    self
+  }
+
+  /// Only accept connections from peers in these networks,
+  /// all peers are accepted if empty.
+  pub fn with_allowed_peers(mut self, allowed_peers: Arc<Vec<Cidr>>) -> Self {
+    self.allowed_peers = allowed_peers;
+    self
+  }
+
+  /// Accept HTTP CONNECT instead of or as well as socks.
+  pub fn with_proxy_protocol(mut self, proxy_protocol: ProxyProtocol) -> Self {
+    self.proxy_protocol = proxy_protocol;
+    self
+  }
+
+  /// Socks authentication to require instead of none, or any username
+  /// for a shared server.
+  pub fn with_authentication(mut self, authentication: Arc<Authentication>) -> Self {
+    self.authentication = authentication;
+    self
+  }
+
+  /// Respond 421 Misdirected Request when the :authority of a request
+  /// does not match the host:port the client dialed through the proxy.
+  pub fn with_check_authority(mut self, check_authority: bool) -> Self {
+    self.options.check_authority = check_authority;
+    self
+  }
+
+  /// End server-sent event streams after the last recorded event
+  /// instead of keeping them open until the client resets them.
+  pub fn with_close_event_streams(mut self, close_event_streams: bool) -> Self {
+    self.options.close_event_streams = close_event_streams;
+    self
+  }
+
+  /// Answer Range requests for every recorded 200 response instead of
+  /// only those recorded with accept-ranges: bytes.
+  pub fn with_range_requests(mut self, range_requests: bool) -> Self {
+    self.options.range_requests = range_requests;
+    self
+  }
+
+  /// Synthesize CORS preflight responses missing from the set and add
+  /// access-control headers to recorded responses with this policy.
+  pub fn with_cors(mut self, cors: Option<CorsPolicy>) -> Self {
+    self.options.cors = cors;
+    self
+  }
+
+  /// Respond to misses matching a fallback rule with its response
+  /// instead of 404.
+  pub fn with_fallbacks(mut self, fallbacks: Fallbacks) -> Self {
+    self.options.fallbacks = fallbacks;
+    self
+  }
+
+  /// Push recorded responses with the documents that have push rules.
+  /// Responses are only pushed to clients that enable push and advertise
+  /// a concurrent stream limit.
+  pub fn with_push_rules(mut self, push_rules: PushRules) -> Self {
+    self.options.push_rules = push_rules;
+    self
+  }
+
+  /// Reply to a socks 5 CONNECT with the local address of the
+  /// connection as BND.ADDR and BND.PORT instead of 0.0.0.0:0.
+  pub fn with_report_bound_addr(mut self, report_bound_addr: bool) -> Self {
+    self.report_bound_addr = report_bound_addr;
+    self
+  }
+
+  /// Deadlines for the handshakes and idle connections.
+  pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+    self.timeouts = timeouts;
+    self
+  }
+
   pub fn name(&self) -> &str {
-    self.response_set.name()
+    self.router.name()
   }
 
   pub fn addr(&self) -> SocketAddr {
-    SocketAddr::new(
-      Ipv4Addr::new(127, 0, 0, 1).into(),
-      self.response_set.socks_port(),
-    )
+    self.addr
   }
 
   pub async fn start(&self) -> Result<(), io::Error> {
-    let listener = TcpListener::bind(self.addr()).await?;
+    let listener = self.listen().await?;
 
     println!(
       "response set {} socks proxy server listening at {}",
       self.name(),
-      self.addr()
+      listener.local_addr()?
     );
 
+    self.serve(listener).await
+  }
+
+  /// Bind the listener, use local_addr() for the assigned port
+  /// when listening on port 0.
+  pub async fn listen(&self) -> Result<TcpListener, io::Error> {
+    let addr = self.addr();
+    if !(self.dual_stack && addr.is_ipv6()) {
+      return TcpListener::bind(addr).await;
+    }
+    // dual stack depends on the OS default for IPV6_V6ONLY
+    // so the socket is set up explicitly
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+  }
+
+  fn is_allowed(&self, peer_addr: &IpAddr) -> bool {
+    self.allowed_peers.is_empty()
+      || self
+        .allowed_peers
+        .iter()
+        .any(|cidr| cidr.contains(peer_addr))
+  }
+
+  /// Accept connections from the listener.
+  pub async fn serve(&self, listener: TcpListener) -> Result<(), io::Error> {
+    let handler = self.handler();
     loop {
       match listener.accept().await {
-        Ok((socket, _peer_addr)) => self.spawn(socket),
+        Ok((socket, peer_addr)) if self.is_allowed(&peer_addr.ip()) => {
+          handler.clone().spawn(socket)
+        }
+        Ok((_socket, peer_addr)) => log::warn!(
+          "{} rejected connection from {} not in allow list",
+          self.name(),
+          peer_addr
+        ),
         Err(err) => log::warn!("failed to accept client {:?}", err),
       }
     }
   }
 
-  fn spawn(&self, socket: TcpStream) {
-    let tls_config = self.tls_config.clone();
-    let response_set = self.response_set.clone();
+  /// Handle an accepted connection to the end, serve spawns this
+  /// for each connection.
+  pub async fn handle(&self, socket: TcpStream) -> Result<(), ServerError> {
+    self.handler().handle(socket).await
+  }
+
+  fn handler(&self) -> Handler {
+    Handler {
+      router: self.router.clone(),
+      proxy_protocol: self.proxy_protocol,
+      authentication: self.authentication.clone(),
+      report_bound_addr: self.report_bound_addr,
+      timeouts: self.timeouts,
+      options: Arc::new(self.options.clone()),
+    }
+  }
+}
+
+/// What a connection task needs from the server.
+#[derive(Clone)]
+struct Handler {
+  router: Arc<Router>,
+  proxy_protocol: ProxyProtocol,
+  authentication: Arc<Authentication>,
+  report_bound_addr: bool,
+  timeouts: Timeouts,
+  options: Arc<ServeOptions>,
+}
+
+impl Handler {
+  fn spawn(self, socket: TcpStream) {
     tokio::spawn(async move {
-      if let Err(err) = handle_tcp_connection(socket, tls_config, response_set).await {
-        log::warn!("{:?}", err);
+      match self.handle(socket).await {
+        Ok(()) => (),
+        Err(err @ ServerError::IdleTimeout(_)) => log::debug!("{}", err),
+        Err(err) => log::warn!("{:?}", err),
       }
     });
   }
-}
 
-async fn handle_tcp_connection(
-  socket: TcpStream,
-  tls_config: Arc<rustls::ServerConfig>,
-  response_set: Arc<RecordedResponseSet>,
-) -> Result<(), ServerError> {
-  let socket = socks5_handshake(socket).await?;
-  let tls_socket = TlsAcceptor::from(tls_config).accept(socket).await?;
-  serve_h2(tls_socket, response_set).await?;
-  Ok(())
+  async fn handle(&self, socket: TcpStream) -> Result<(), ServerError> {
+    // refuse requests with no route during the handshake
+    let allow = |request: &SocksRequest| self.router.route(request).is_some();
+    let (socket, request) = with_timeout(
+      self.timeouts.proxy_handshake,
+      proxy_handshake(
+        socket,
+        self.proxy_protocol,
+        &self.authentication,
+        &allow,
+        self.report_bound_addr,
+      ),
+      ServerError::ProxyTimeout,
+    )
+    .await?;
+    let route = match self.router.route(&request) {
+      Some(route) => route,
+      None => return Err(ServerError::NoRoute(request)),
+    };
+    let tls_socket = with_timeout(
+      self.timeouts.tls_handshake,
+      TlsAcceptor::from(route.tls_config.clone()).accept(socket),
+      ServerError::TlsTimeout,
+    )
+    .await?;
+    serve_h2(
+      tls_socket,
+      route.response_set.clone(),
+      Arc::new(request),
+      self.options.clone(),
+      &self.timeouts,
+      route.upstream.clone(),
+    )
+    .await
+  }
 }
@@ -0,0 +1,83 @@
+use super::error::ServerError;
+use std::str::FromStr;
+use tokio::net::TcpStream;
+use tracerbench_http_connect_proxy::HttpConnectError;
+use tracerbench_http_connect_proxy::HttpConnectHandshake;
+use tracerbench_socks_proxy::AllowConnect;
+use tracerbench_socks_proxy::Authentication;
+use tracerbench_socks_proxy::SocksError;
+use tracerbench_socks_proxy::SocksHandshake;
+use tracerbench_socks_proxy::SocksRequest;
+
+/// Proxy protocol a server accepts before the TLS handshake.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde_derive::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyProtocol {
+  /// SOCKS5 or SOCKS4/4a
+  #[default]
+  Socks,
+  /// HTTP/1.1 CONNECT
+  HttpConnect,
+  /// either, detected from the first byte the client sends
+  Auto,
+}
+
+impl FromStr for ProxyProtocol {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "socks" => Ok(ProxyProtocol::Socks),
+      "http_connect" => Ok(ProxyProtocol::HttpConnect),
+      "auto" => Ok(ProxyProtocol::Auto),
+      _ => Err(format!(
+        "unsupported proxy protocol {:?}, expected socks, http_connect or auto",
+        s
+      )),
+    }
+  }
+}
+
+/// Runs the proxy handshake, returns the socket ready for TLS
+/// and what the client dialed. Requests allow refuses are replied
+/// to with socks connection not allowed or HTTP 403 Forbidden.
+pub(super) async fn proxy_handshake(
+  socket: TcpStream,
+  protocol: ProxyProtocol,
+  authentication: &Authentication,
+  allow: &AllowConnect<'_>,
+  report_bound_addr: bool,
+) -> Result<(TcpStream, SocksRequest), ServerError> {
+  let protocol = match protocol {
+    ProxyProtocol::Auto => detect(&socket).await?,
+    protocol => protocol,
+  };
+  if protocol == ProxyProtocol::HttpConnect {
+    let handshake = HttpConnectHandshake::new(authentication).with_allow(allow);
+    return match handshake.accept(socket).await {
+      Ok(result) => Ok(result),
+      Err(HttpConnectError::ConnectionNotAllowed(request)) => Err(ServerError::NoRoute(request)),
+      Err(err) => Err(err.into()),
+    };
+  }
+  let mut handshake = SocksHandshake::new(authentication).with_allow(allow);
+  if report_bound_addr {
+    handshake = handshake.with_bound_addr(socket.local_addr()?);
+  }
+  match handshake.accept(socket).await {
+    Ok(result) => Ok(result),
+    Err(SocksError::ConnectionNotAllowed(request)) => Err(ServerError::NoRoute(request)),
+    Err(err) => Err(err.into()),
+  }
+}
+
+/// Socks starts with its version byte 4 or 5, anything else is
+/// taken for the method of an HTTP request line.
+async fn detect(socket: &TcpStream) -> Result<ProxyProtocol, ServerError> {
+  let mut first = [0u8; 1];
+  socket.peek(&mut first).await?;
+  match first[0] {
+    4 | 5 => Ok(ProxyProtocol::Socks),
+    _ => Ok(ProxyProtocol::HttpConnect),
+  }
+}
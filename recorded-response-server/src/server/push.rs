@@ -0,0 +1,51 @@
+use http::Uri;
+use std::collections::HashMap;
+
+/// Recorded responses pushed with a document (HTTP/2 server push),
+/// by set name and request key of the document.
+#[derive(Debug, Clone, Default)]
+pub struct PushRules {
+  sets: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+impl PushRules {
+  /// Push the resources when the named set serves the recorded
+  /// response for the request key. Resources are paths on the
+  /// authority of the document or https URLs.
+  pub fn with_rule(mut self, set_name: &str, key: &str, resources: Vec<String>) -> Self {
+    self
+      .sets
+      .entry(set_name.to_owned())
+      .or_default()
+      .insert(key.to_owned(), resources);
+    self
+  }
+
+  /// The URIs to push with the document, a resource that is not a
+  /// valid URI is skipped.
+  pub(super) fn uris_for(&self, set_name: &str, key: &str, document: &Uri) -> Vec<Uri> {
+    let resources = match self.sets.get(set_name).and_then(|keys| keys.get(key)) {
+      Some(resources) => resources,
+      None => return Vec::new(),
+    };
+    resources
+      .iter()
+      .filter_map(|resource| {
+        let uri = if resource.starts_with('/') {
+          let scheme = document.scheme_str().unwrap_or("https");
+          let authority = document.authority()?;
+          format!("{}://{}{}", scheme, authority, resource).parse()
+        } else {
+          resource.parse()
+        };
+        match uri {
+          Ok(uri) => Some(uri),
+          Err(err) => {
+            log::debug!("invalid push resource {:?} {}", resource, err);
+            None
+          }
+        }
+      })
+      .collect()
+  }
+}
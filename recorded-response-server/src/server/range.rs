@@ -0,0 +1,223 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use http::header::HeaderValue;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_RANGE;
+use http::header::CONTENT_TYPE;
+use http::Response;
+use http::StatusCode;
+
+/// More ranges than this in one request are ignored and the whole
+/// body is served, answering them costs more than the body.
+const MAX_RANGES: usize = 32;
+
+/// Fixed so replays are byte for byte the same.
+const BOUNDARY: &str = "tracerbench-byteranges-3b1f5a7c";
+
+/// A range of body bytes, end exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ByteRange {
+  start: usize,
+  end: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Ranges {
+  /// not a valid bytes range, the whole body is served
+  Ignored,
+  /// no range overlaps the body
+  Unsatisfiable,
+  Satisfiable(Vec<ByteRange>),
+}
+
+/// The 206 or 416 response for the Range header, or the response as
+/// it is when the header is not a bytes range this can answer.
+pub(super) fn respond_to_range(
+  range: &HeaderValue,
+  mut response: Response<()>,
+  body: Bytes,
+) -> (Response<()>, Option<Bytes>) {
+  let ranges = match range.to_str() {
+    Ok(range) => parse_ranges(range, body.len()),
+    Err(_) => Ranges::Ignored,
+  };
+  match ranges {
+    Ranges::Ignored => (response, Some(body)),
+    Ranges::Unsatisfiable => {
+      let mut response = Response::new(());
+      *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+      response
+        .headers_mut()
+        .insert(CONTENT_RANGE, content_range(None, body.len()));
+      (response, None)
+    }
+    Ranges::Satisfiable(ranges) => {
+      let part = if let [range] = ranges[..] {
+        response
+          .headers_mut()
+          .insert(CONTENT_RANGE, content_range(Some(range), body.len()));
+        body.slice(range.start..range.end)
+      } else {
+        let content_type = response.headers_mut().remove(CONTENT_TYPE);
+        let multipart_type = format!("multipart/byteranges; boundary={}", BOUNDARY);
+        response.headers_mut().insert(
+          CONTENT_TYPE,
+          HeaderValue::from_str(&multipart_type).unwrap(),
+        );
+        multipart(&ranges, content_type.as_ref(), &body)
+      };
+      *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+      response
+        .headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from(part.len()));
+      (response, Some(part))
+    }
+  }
+}
+
+fn content_range(range: Option<ByteRange>, len: usize) -> HeaderValue {
+  let value = match range {
+    Some(range) => format!("bytes {}-{}/{}", range.start, range.end - 1, len),
+    None => format!("bytes */{}", len),
+  };
+  HeaderValue::from_str(&value).unwrap()
+}
+
+fn multipart(ranges: &[ByteRange], content_type: Option<&HeaderValue>, body: &Bytes) -> Bytes {
+  let mut bytes = BytesMut::new();
+  for &range in ranges {
+    bytes.put_slice(b"--");
+    bytes.put_slice(BOUNDARY.as_bytes());
+    bytes.put_slice(b"\r\n");
+    if let Some(content_type) = content_type {
+      bytes.put_slice(b"content-type: ");
+      bytes.put_slice(content_type.as_bytes());
+      bytes.put_slice(b"\r\n");
+    }
+    bytes.put_slice(b"content-range: ");
+    bytes.put_slice(content_range(Some(range), body.len()).as_bytes());
+    bytes.put_slice(b"\r\n\r\n");
+    bytes.put_slice(&body[range.start..range.end]);
+    bytes.put_slice(b"\r\n");
+  }
+  bytes.put_slice(b"--");
+  bytes.put_slice(BOUNDARY.as_bytes());
+  bytes.put_slice(b"--\r\n");
+  bytes.freeze()
+}
+
+/// Parses a Range header against a body of len bytes, ranges past
+/// the end are dropped and ends past the end are clamped.
+fn parse_ranges(header: &str, len: usize) -> Ranges {
+  let specs = match header.trim().split_once('=') {
+    Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+    _ => return Ranges::Ignored,
+  };
+  let mut ranges = Vec::new();
+  let mut count = 0;
+  for spec in specs
+    .split(',')
+    .map(str::trim)
+    .filter(|spec| !spec.is_empty())
+  {
+    count += 1;
+    if count > MAX_RANGES {
+      return Ranges::Ignored;
+    }
+    let (first, last) = match spec.split_once('-') {
+      Some(bounds) => bounds,
+      None => return Ranges::Ignored,
+    };
+    let range = if first.is_empty() {
+      // the last n bytes
+      let suffix: usize = match last.parse() {
+        Ok(suffix) => suffix,
+        Err(_) => return Ranges::Ignored,
+      };
+      ByteRange {
+        start: len.saturating_sub(suffix),
+        end: len,
+      }
+    } else {
+      let start: usize = match first.parse() {
+        Ok(start) => start,
+        Err(_) => return Ranges::Ignored,
+      };
+      let end = if last.is_empty() {
+        len
+      } else {
+        match last.parse::<usize>() {
+          Ok(last) if last >= start => last.saturating_add(1).min(len),
+          _ => return Ranges::Ignored,
+        }
+      };
+      ByteRange { start, end }
+    };
+    if range.start < range.end {
+      ranges.push(range);
+    }
+  }
+  if count == 0 {
+    Ranges::Ignored
+  } else if ranges.is_empty() {
+    Ranges::Unsatisfiable
+  } else {
+    Ranges::Satisfiable(ranges)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn satisfiable(ranges: &[(usize, usize)]) -> Ranges {
+    Ranges::Satisfiable(
+      ranges
+        .iter()
+        .map(|&(start, end)| ByteRange { start, end })
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn test_parse_ranges() {
+    assert_eq!(parse_ranges("bytes=0-499", 1000), satisfiable(&[(0, 500)]));
+    assert_eq!(
+      parse_ranges("bytes=500-", 1000),
+      satisfiable(&[(500, 1000)])
+    );
+    assert_eq!(
+      parse_ranges("bytes=-200", 1000),
+      satisfiable(&[(800, 1000)])
+    );
+    assert_eq!(parse_ranges("bytes=-2000", 1000), satisfiable(&[(0, 1000)]));
+    assert_eq!(
+      parse_ranges("Bytes=0-0, 900-1999 ,", 1000),
+      satisfiable(&[(0, 1), (900, 1000)])
+    );
+  }
+
+  #[test]
+  fn test_unsatisfiable_ranges() {
+    assert_eq!(parse_ranges("bytes=1000-", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse_ranges("bytes=-0", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse_ranges("bytes=0-10", 0), Ranges::Unsatisfiable);
+    // a satisfiable range is enough
+    assert_eq!(
+      parse_ranges("bytes=2000-3000, 0-1", 1000),
+      satisfiable(&[(0, 2)])
+    );
+  }
+
+  #[test]
+  fn test_ignored_ranges() {
+    assert_eq!(parse_ranges("items=0-1", 1000), Ranges::Ignored);
+    assert_eq!(parse_ranges("bytes=5-1", 1000), Ranges::Ignored);
+    assert_eq!(parse_ranges("bytes=a-b", 1000), Ranges::Ignored);
+    assert_eq!(parse_ranges("bytes=", 1000), Ranges::Ignored);
+    let many = vec!["0-0"; MAX_RANGES + 1].join(",");
+    assert_eq!(
+      parse_ranges(&format!("bytes={}", many), 1000),
+      Ranges::Ignored
+    );
+  }
+}
@@ -0,0 +1,201 @@
+use super::Upstream;
+use serde::Deserialize;
+use serde::Deserializer;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_rustls::rustls;
+use tracerbench_recorded_response_set::RecordedResponseSet;
+use tracerbench_socks_proxy::SocksRequest;
+
+/// A response set served through a server and how to reach it
+/// when the server is shared by several sets.
+pub struct Route {
+  pub tls_config: Arc<rustls::ServerConfig>,
+  pub response_set: Arc<RecordedResponseSet>,
+  /// CONNECT destinations routed to this set, the set can always
+  /// be reached by its name as the socks username
+  pub destinations: Vec<DestinationPattern>,
+  /// server requests missing from the set are forwarded to
+  pub upstream: Option<Arc<Upstream>>,
+}
+
+/// Picks the route for a socks request.
+pub(super) struct Router {
+  name: String,
+  routes: Vec<Route>,
+  default_set: Option<String>,
+}
+
+impl Router {
+  /// Router for a server dedicated to a single set.
+  pub(super) fn single(route: Route) -> Self {
+    Router {
+      name: route.response_set.name().to_owned(),
+      default_set: Some(route.response_set.name().to_owned()),
+      routes: vec![route],
+    }
+  }
+
+  /// Router for a server shared by sets, the set is picked by socks
+  /// username if it names a set, then by CONNECT destination, then
+  /// the default set.
+  pub(super) fn shared(routes: Vec<Route>, default_set: Option<String>) -> Self {
+    let names: Vec<&str> = routes
+      .iter()
+      .map(|route| route.response_set.name())
+      .collect();
+    Router {
+      name: format!("{} (shared)", names.join(", ")),
+      routes,
+      default_set,
+    }
+  }
+
+  pub(super) fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub(super) fn route(&self, request: &SocksRequest) -> Option<&Route> {
+    // the username may just be a credential rather than a set name
+    if let Some(route) = request.username.as_ref().and_then(|name| self.find(name)) {
+      return Some(route);
+    }
+    let by_destination = self.routes.iter().find(|route| {
+      route
+        .destinations
+        .iter()
+        .any(|pattern| pattern.matches(request))
+    });
+    match (by_destination, &self.default_set) {
+      (Some(route), _) => Some(route),
+      (None, Some(default_set)) => self.find(default_set),
+      (None, None) => None,
+    }
+  }
+
+  fn find(&self, name: &str) -> Option<&Route> {
+    self
+      .routes
+      .iter()
+      .find(|route| route.response_set.name() == name)
+  }
+}
+
+/// Matches the CONNECT destination of a socks request, written as
+/// host, host:port or *:port with IPv6 hosts in brackets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DestinationPattern {
+  host: Option<String>,
+  port: Option<u16>,
+}
+
+impl DestinationPattern {
+  pub fn matches(&self, request: &SocksRequest) -> bool {
+    let host_matches = match &self.host {
+      Some(host) => host.eq_ignore_ascii_case(&request.destination.to_string()),
+      None => true,
+    };
+    let port_matches = match self.port {
+      Some(port) => port == request.port,
+      None => true,
+    };
+    host_matches && port_matches
+  }
+}
+
+impl FromStr for DestinationPattern {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || {
+      format!(
+        "invalid destination {:?}, expected host, host:port or *:port",
+        s
+      )
+    };
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+      // [ipv6] or [ipv6]:port
+      let end = rest.find(']').ok_or_else(invalid)?;
+      let port = match &rest[end + 1..] {
+        "" => None,
+        port => Some(port.strip_prefix(':').ok_or_else(invalid)?),
+      };
+      (&rest[..end], port)
+    } else {
+      match s.rfind(':') {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+      }
+    };
+    let port = match port {
+      Some(port) => Some(port.parse().map_err(|_| invalid())?),
+      None => None,
+    };
+    let host = match host {
+      "*" => None,
+      "" => return Err(invalid()),
+      host => Some(host.to_owned()),
+    };
+    Ok(DestinationPattern { host, port })
+  }
+}
+
+impl<'de> Deserialize<'de> for DestinationPattern {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tracerbench_socks_proxy::Destination;
+
+  fn request(destination: Destination, port: u16) -> SocksRequest {
+    SocksRequest {
+      username: None,
+      destination,
+      port,
+    }
+  }
+
+  #[test]
+  fn test_host_pattern() {
+    let pattern: DestinationPattern = "www.example.com".parse().unwrap();
+    let domain = Destination::DomainName(String::from("WWW.example.com"));
+    assert!(pattern.matches(&request(domain.clone(), 443)));
+    assert!(pattern.matches(&request(domain, 80)));
+    let other = Destination::DomainName(String::from("example.com"));
+    assert!(!pattern.matches(&request(other, 443)));
+  }
+
+  #[test]
+  fn test_host_and_port_pattern() {
+    let pattern: DestinationPattern = "10.0.0.1:8443".parse().unwrap();
+    let ip = Destination::Ipv4("10.0.0.1".parse().unwrap());
+    assert!(pattern.matches(&request(ip.clone(), 8443)));
+    assert!(!pattern.matches(&request(ip, 443)));
+  }
+
+  #[test]
+  fn test_any_host_pattern() {
+    let pattern: DestinationPattern = "*:9443".parse().unwrap();
+    let domain = Destination::DomainName(String::from("example.com"));
+    assert!(pattern.matches(&request(domain.clone(), 9443)));
+    assert!(!pattern.matches(&request(domain, 443)));
+  }
+
+  #[test]
+  fn test_ipv6_pattern() {
+    let pattern: DestinationPattern = "[::1]:443".parse().unwrap();
+    let ip = Destination::Ipv6("::1".parse().unwrap());
+    assert!(pattern.matches(&request(ip.clone(), 443)));
+    assert!(!pattern.matches(&request(ip, 80)));
+    assert!("[::1".parse::<DestinationPattern>().is_err());
+    assert!("example.com:https".parse::<DestinationPattern>().is_err());
+  }
+}
@@ -1,58 +1,233 @@
+use super::conditional::not_modified;
+use super::cors::CorsPolicy;
+use super::error::ServerError;
+use super::fallback::Fallbacks;
+use super::push::PushRules;
+use super::range::respond_to_range;
+use super::timeouts::with_timeout;
+use super::timeouts::Timeouts;
+use super::upstream::Upstream;
+use super::websocket::WebSocket;
 use bytes::Bytes;
+use bytes::BytesMut;
+use futures::future::join;
 use futures::future::poll_fn;
+use futures::future::select;
+use futures::future::try_join_all;
+use futures::future::Either;
+use h2::ext::Protocol;
 use h2::server;
+use h2::server::SendPushedResponse;
 use h2::server::SendResponse;
 use h2::RecvStream;
 use h2::SendStream;
 use http::header::HeaderName;
+use http::header::HeaderValue;
 use http::header::ACCEPT;
+use http::header::ACCEPT_RANGES;
+use http::header::CONTENT_LENGTH;
+use http::header::CONTENT_TYPE;
+use http::header::ETAG;
+use http::header::IF_RANGE;
+use http::header::LAST_MODIFIED;
+use http::header::RANGE;
+use http::header::SEC_WEBSOCKET_PROTOCOL;
 use http::request::Parts;
 use http::Method;
 use http::Request;
 use http::Response;
 use http::StatusCode;
 use http::Uri;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::sleep;
+use tokio::time::timeout_at;
+use tokio::time::Instant;
+use tracerbench_recorded_response_set::RecordedResponse;
 use tracerbench_recorded_response_set::RecordedResponseSet;
+use tracerbench_recorded_response_set::ServerSentEvent;
+use tracerbench_socks_proxy::Destination;
+use tracerbench_socks_proxy::SocksRequest;
+
+static EVENT_STREAM: &str = "text/event-stream";
+
+/// How requests are responded to, the same for every connection
+/// of a server.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ServeOptions {
+  /// respond 421 when :authority does not match the CONNECT destination
+  pub(super) check_authority: bool,
+  /// end event streams after the last recorded event instead of
+  /// keeping them open until the client resets them
+  pub(super) close_event_streams: bool,
+  /// answer Range requests for every recorded 200 response instead
+  /// of only those with accept-ranges: bytes
+  pub(super) range_requests: bool,
+  /// synthesize missing CORS preflights and add access-control headers
+  pub(super) cors: Option<CorsPolicy>,
+  /// responses for misses instead of 404
+  pub(super) fallbacks: Fallbacks,
+  /// recorded responses pushed with documents
+  pub(super) push_rules: PushRules,
+}
 
-static EVENT_STREAM: &[u8] = b"text/event-stream";
-
-/// Serves the H2 connection with the specified response set.
-pub(super) async fn serve_h2<S>(socket: S, set: Arc<RecordedResponseSet>) -> Result<(), h2::Error>
+/// Serves the H2 connection with the specified response set,
+/// socks_request is the CONNECT request the connection was made with
+/// and misses are forwarded to upstream if there is one.
+pub(super) async fn serve_h2<S>(
+  socket: S,
+  set: Arc<RecordedResponseSet>,
+  socks_request: Arc<SocksRequest>,
+  options: Arc<ServeOptions>,
+  timeouts: &Timeouts,
+  upstream: Option<Arc<Upstream>>,
+) -> Result<(), ServerError>
 where
   S: AsyncRead + AsyncWrite + Unpin,
 {
-  let mut connection = server::handshake(socket).await?;
-  log::debug!("{} HTTP2 connection bound", set.name());
-  while let Some(result) = connection.accept().await {
-    let (request, send_response) = result?;
-    spawn_accept_request(set.clone(), request, send_response);
+  // extended CONNECT for WebSockets over h2 (RFC 8441)
+  let mut connection = with_timeout(
+    timeouts.h2_preface,
+    server::Builder::new()
+      .enable_connect_protocol()
+      .handshake(socket),
+    ServerError::PrefaceTimeout,
+  )
+  .await?;
+  log::debug!(
+    "{} HTTP2 connection bound for CONNECT {}",
+    set.name(),
+    socks_request
+  );
+  let open_streams = Arc::new(OpenStreams::new());
+  loop {
+    let next = match timeouts.idle {
+      Some(idle) => match timeout_at(open_streams.idle_deadline(idle), connection.accept()).await {
+        Ok(next) => next,
+        // a stream may have been opened or closed since the deadline was set
+        Err(_) if open_streams.idle_deadline(idle) > Instant::now() => continue,
+        Err(_) => {
+          connection.graceful_shutdown();
+          // the client may hang up as soon as it sees the GOAWAY
+          if let Err(err) = poll_fn(|cx| connection.poll_closed(cx)).await {
+            log::debug!("{} error closing idle connection {}", set.name(), err);
+          }
+          return Err(ServerError::IdleTimeout(idle));
+        }
+      },
+      None => connection.accept().await,
+    };
+    let (request, send_response) = match next {
+      Some(result) => result?,
+      None => return Ok(()),
+    };
+    spawn_accept_request(
+      set.clone(),
+      socks_request.clone(),
+      options.clone(),
+      upstream.clone(),
+      open_streams.clone(),
+      request,
+      send_response,
+    );
   }
-  Ok(())
 }
 
 fn spawn_accept_request(
   response_set: Arc<RecordedResponseSet>,
+  socks_request: Arc<SocksRequest>,
+  options: Arc<ServeOptions>,
+  upstream: Option<Arc<Upstream>>,
+  open_streams: Arc<OpenStreams>,
   request: Request<RecvStream>,
   send_response: SendResponse<Bytes>,
 ) {
+  open_streams.open();
   tokio::spawn(async move {
     let (head, body) = request.into_parts();
-    RequestAcceptor::new(response_set, head)
+    RequestAcceptor::new(response_set, socks_request, options, upstream, head)
       .accept(body, send_response)
-      .await
+      .await;
+    open_streams.close();
   });
 }
 
+/// The streams of a connection still being answered, and when the last
+/// of them was closed.
+struct OpenStreams {
+  count: AtomicUsize,
+  idle_since: Mutex<Instant>,
+}
+
+impl OpenStreams {
+  fn new() -> Self {
+    OpenStreams {
+      count: AtomicUsize::new(0),
+      idle_since: Mutex::new(Instant::now()),
+    }
+  }
+
+  fn open(&self) {
+    self.count.fetch_add(1, Ordering::SeqCst);
+  }
+
+  fn close(&self) {
+    let mut idle_since = self.idle_since.lock().unwrap();
+    if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+      *idle_since = Instant::now();
+    }
+  }
+
+  /// When the connection will have been idle for the timeout, while
+  /// streams are open that is a full timeout from now.
+  fn idle_deadline(&self, idle: Duration) -> Instant {
+    let idle_since = self.idle_since.lock().unwrap();
+    if self.count.load(Ordering::SeqCst) > 0 {
+      Instant::now() + idle
+    } else {
+      *idle_since + idle
+    }
+  }
+}
+
+/// A promised response waiting to be sent.
+struct Pushed {
+  uri: Uri,
+  send_pushed: SendPushedResponse<Bytes>,
+  response: Response<()>,
+  maybe_body: Option<Bytes>,
+}
+
 struct RequestAcceptor {
   response_set: Arc<RecordedResponseSet>,
+  socks_request: Arc<SocksRequest>,
+  target: String,
+  options: Arc<ServeOptions>,
+  upstream: Option<Arc<Upstream>>,
   head: Parts,
 }
 
 impl RequestAcceptor {
-  fn new(response_set: Arc<RecordedResponseSet>, head: Parts) -> Self {
-    RequestAcceptor { head, response_set }
+  fn new(
+    response_set: Arc<RecordedResponseSet>,
+    socks_request: Arc<SocksRequest>,
+    options: Arc<ServeOptions>,
+    upstream: Option<Arc<Upstream>>,
+    head: Parts,
+  ) -> Self {
+    let target = socks_request.target();
+    RequestAcceptor {
+      head,
+      response_set,
+      socks_request,
+      target,
+      options,
+      upstream,
+    }
   }
 
   fn name(&self) -> &str {
@@ -83,8 +258,44 @@ impl RequestAcceptor {
     }
   }
 
+  fn is_web_socket(&self) -> bool {
+    self.head.method == Method::CONNECT
+      && matches!(
+        self.head.extensions.get::<Protocol>(),
+        Some(protocol) if protocol.as_str().eq_ignore_ascii_case("websocket")
+      )
+  }
+
   fn is_server_sent_events(&self) -> bool {
-    self.is_get() && self.header_equals(ACCEPT, EVENT_STREAM)
+    self.is_get() && self.header_equals(ACCEPT, EVENT_STREAM.as_bytes())
+  }
+
+  /// Whether :authority names the host and port dialed through the proxy,
+  /// a client that resolves names itself dials an IP and never matches.
+  fn authority_matches_target(&self) -> bool {
+    let authority = match self.uri().authority() {
+      Some(authority) => authority,
+      None => return true,
+    };
+    let host_matches = match self.socks_request.destination {
+      Destination::Ipv6(ref addr) => authority.host() == format!("[{}]", addr),
+      ref destination => authority
+        .host()
+        .eq_ignore_ascii_case(&destination.to_string()),
+    };
+    host_matches && authority.port_u16().unwrap_or(443) == self.socks_request.port
+  }
+
+  /// The request key, HEAD requests are keyed as GET.
+  fn key(&self) -> String {
+    let method = if self.is_head() {
+      &Method::GET
+    } else {
+      self.method()
+    };
+    self
+      .response_set
+      .key_for_target(method, self.uri(), Some(&self.target))
   }
 
   fn get_response(&self) -> Option<(Response<()>, Option<Bytes>)> {
@@ -93,7 +304,9 @@ impl RequestAcceptor {
     } else {
       self.method()
     };
-    self.response_set.response_for(method, self.uri())
+    self
+      .response_set
+      .response_for_target(method, self.uri(), Some(&self.target))
   }
 
   async fn accept(&self, body: RecvStream, send_response: SendResponse<Bytes>) {
@@ -114,47 +327,323 @@ impl RequestAcceptor {
     body: RecvStream,
     send_response: SendResponse<Bytes>,
   ) -> Result<(), h2::Error> {
-    // server-sent events request we just keep open
-    // until the client closes
-    // we currently dont support sending any events
+    if self.is_web_socket() {
+      return self.respond_with_web_socket(body, send_response).await;
+    }
+
+    // server-sent events requests get the recorded events and are
+    // then kept open until the client closes, unless configured to
+    // end after the last event
     if self.is_server_sent_events() {
       log::debug!("{} Server-Sent Events {}", self.name(), self.uri());
-      self.respond_and_wait_for_reset(send_response).await?;
+      let key = self
+        .response_set
+        .key_for_target(self.method(), self.uri(), Some(&self.target));
+      match self.response_set.get_event_stream(&key) {
+        Some(events) => {
+          self
+            .respond_with_events(send_response, &key, events)
+            .await?
+        }
+        None => self.respond_and_wait_for_reset(send_response).await?,
+      }
       return Ok(());
     }
 
-    // we want to consume the body before replying
-    // even though we don't currently use the body for
+    if self.options.check_authority && !self.authority_matches_target() {
+      self.read_body(body).await?;
+      return self.respond_with_misdirected(send_response);
+    }
+
+    // we want to consume the body before replying, it is
+    // only used when forwarding a miss upstream
     // (has not mattered for initial render benchmarking).
-    self.read_body(body).await?;
+    let body = self.read_body(body).await?;
 
-    self.respond(send_response).await?;
+    self.respond(send_response, body).await?;
 
     Ok(())
   }
 
-  async fn read_body(&self, mut body: RecvStream) -> Result<Option<usize>, h2::Error> {
-    let mut total = None;
+  async fn read_body(&self, mut body: RecvStream) -> Result<Bytes, h2::Error> {
+    let mut bytes = BytesMut::new();
     while let Some(result) = poll_fn(|cx| body.poll_data(cx)).await {
       let chunk = result?;
-      total = Some(total.unwrap_or(0) + chunk.len());
+      let _ = body.flow_control().release_capacity(chunk.len());
+      bytes.extend_from_slice(&chunk);
     }
-    Ok(total)
+    Ok(bytes.freeze())
   }
 
-  async fn respond(&self, send_response: SendResponse<bytes::Bytes>) -> Result<(), h2::Error> {
+  async fn respond(
+    &self,
+    mut send_response: SendResponse<bytes::Bytes>,
+    request_body: Bytes,
+  ) -> Result<(), h2::Error> {
     if let Some((response, maybe_body)) = self.get_response() {
-      if let Some(body) = maybe_body {
-        if !self.is_head() {
-          return self.respond_with_body(send_response, response, body).await;
+      let (mut response, maybe_body) = match self.not_modified(&response) {
+        Some(not_modified) => (not_modified, None),
+        None => self.apply_range(response, maybe_body),
+      };
+      self.add_cors_headers(&mut response);
+      let pushes = if self.is_get() && response.status() == StatusCode::OK {
+        self.push_promises(&mut send_response)
+      } else {
+        Vec::new()
+      };
+      // pushed responses are sent alongside the document, not after it
+      let (sent, pushed) = join(
+        self.respond_with_parts(send_response, response, maybe_body),
+        self.send_pushed_responses(pushes),
+      )
+      .await;
+      sent.and(pushed)
+    } else if let Some(preflight) = self.cors_preflight() {
+      self.respond_with_no_body(send_response, preflight)
+    } else if let Some((response, maybe_body)) = self.fallback() {
+      self
+        .respond_with_fallback(send_response, response, maybe_body)
+        .await
+    } else if let Some(upstream) = &self.upstream {
+      self
+        .respond_from_upstream(send_response, upstream, request_body)
+        .await
+    } else {
+      self.respond_with_not_found(send_response)
+    }
+  }
+
+  /// Promises the recorded responses the push rules have for the
+  /// request, promises are sent before the response.
+  fn push_promises(&self, send_response: &mut SendResponse<Bytes>) -> Vec<Pushed> {
+    let uris = self
+      .options
+      .push_rules
+      .uris_for(self.name(), &self.key(), self.uri());
+    let mut pushes = Vec::with_capacity(uris.len());
+    for uri in uris {
+      let (response, maybe_body) =
+        match self
+          .response_set
+          .response_for_target(&Method::GET, &uri, Some(&self.target))
+        {
+          Some(parts) => parts,
+          None => {
+            log::debug!("{} PUSH {} is not recorded", self.name(), uri);
+            continue;
+          }
+        };
+      let request = Request::get(uri.clone()).body(()).unwrap();
+      match send_response.push_request(request) {
+        Ok(send_pushed) => pushes.push(Pushed {
+          uri,
+          send_pushed,
+          response,
+          maybe_body,
+        }),
+        Err(err) => {
+          // the client may have disabled push
+          log::debug!("{} PUSH {} {}", self.name(), uri, err);
+          break;
         }
       }
-      self.respond_with_no_body(send_response, response)
+    }
+    pushes
+  }
+
+  /// Sends the promised responses concurrently with each other.
+  async fn send_pushed_responses(&self, pushes: Vec<Pushed>) -> Result<(), h2::Error> {
+    try_join_all(pushes.into_iter().map(|pushed| self.send_pushed(pushed))).await?;
+    Ok(())
+  }
+
+  async fn send_pushed(&self, mut pushed: Pushed) -> Result<(), h2::Error> {
+    let status = pushed.response.status().as_u16();
+    let send_stream = pushed
+      .send_pushed
+      .send_response(pushed.response, pushed.maybe_body.is_none())?;
+    let sent = match pushed.maybe_body {
+      Some(body) => Some(self.send_body(send_stream, body).await?),
+      None => None,
+    };
+    log::debug!("{} PUSH {} {} {:?}", self.name(), status, pushed.uri, sent);
+    Ok(())
+  }
+
+  /// The fallback response for a miss, fallbacks are checked before
+  /// the miss is forwarded upstream.
+  fn fallback(&self) -> Option<(Response<()>, Option<Bytes>)> {
+    self
+      .options
+      .fallbacks
+      .response_for(&self.response_set, &self.key())
+  }
+
+  /// Responds to the miss with the response of the fallback rule it matched.
+  async fn respond_with_fallback(
+    &self,
+    send_response: SendResponse<Bytes>,
+    mut response: Response<()>,
+    maybe_body: Option<Bytes>,
+  ) -> Result<(), h2::Error> {
+    log::debug!("{} FALLBACK {}", self.name(), self.key());
+    self.add_cors_headers(&mut response);
+    self
+      .respond_with_parts(send_response, response, maybe_body)
+      .await
+  }
+
+  fn add_cors_headers(&self, response: &mut Response<()>) {
+    if let Some(cors) = &self.options.cors {
+      cors.add_headers(&self.head.headers, response);
+    }
+  }
+
+  /// The synthesized response to a CORS preflight missing from the set.
+  fn cors_preflight(&self) -> Option<Response<()>> {
+    self
+      .options
+      .cors
+      .as_ref()?
+      .preflight(self.method(), &self.head.headers)
+  }
+
+  /// The 304 response to a revalidation the recorded response is still
+  /// valid for.
+  fn not_modified(&self, response: &Response<()>) -> Option<Response<()>> {
+    if self.is_get() || self.is_head() {
+      not_modified(&self.head.headers, response)
     } else {
-      self.respond_with_not_found(send_response)
+      None
+    }
+  }
+
+  /// Answers a Range request from the recorded body, for 200 responses
+  /// that accept byte ranges or all 200 responses if configured.
+  fn apply_range(
+    &self,
+    response: Response<()>,
+    maybe_body: Option<Bytes>,
+  ) -> (Response<()>, Option<Bytes>) {
+    let range = match self.head.headers.get(RANGE) {
+      Some(range) if self.is_get() || self.is_head() => range,
+      _ => return (response, maybe_body),
+    };
+    let accepts_ranges = response.status() == StatusCode::OK
+      && (self.options.range_requests
+        || matches!(
+          response.headers().get(ACCEPT_RANGES),
+          Some(value) if value.as_bytes().eq_ignore_ascii_case(b"bytes")
+        ));
+    match maybe_body {
+      Some(body) if accepts_ranges && self.if_range_matches(&response) => {
+        respond_to_range(range, response, body)
+      }
+      maybe_body => (response, maybe_body),
     }
   }
 
+  /// If-Range must be the strong ETag or the exact Last-Modified date
+  /// of the response for the range to apply.
+  fn if_range_matches(&self, response: &Response<()>) -> bool {
+    let if_range = match self.head.headers.get(IF_RANGE) {
+      Some(if_range) => if_range,
+      None => return true,
+    };
+    let validator = if if_range.as_bytes().starts_with(b"\"") {
+      ETAG
+    } else if if_range.as_bytes().starts_with(b"W/") {
+      return false;
+    } else {
+      LAST_MODIFIED
+    };
+    response.headers().get(validator) == Some(if_range)
+  }
+
+  async fn respond_with_parts(
+    &self,
+    send_response: SendResponse<Bytes>,
+    mut response: Response<()>,
+    maybe_body: Option<Bytes>,
+  ) -> Result<(), h2::Error> {
+    // HEAD gets the GET response with the length of the body it omits,
+    // 204 and 304 responses have no length to report
+    if self.is_head() {
+      let status = response.status();
+      if status != StatusCode::NO_CONTENT && status != StatusCode::NOT_MODIFIED {
+        let len = maybe_body.as_ref().map_or(0, Bytes::len);
+        response
+          .headers_mut()
+          .insert(CONTENT_LENGTH, HeaderValue::from(len));
+      }
+      return self.respond_with_no_body(send_response, response);
+    }
+    match maybe_body {
+      Some(body) => self.respond_with_body(send_response, response, body).await,
+      None => self.respond_with_no_body(send_response, response),
+    }
+  }
+
+  /// Forwards the miss upstream and serves what it responds, adding
+  /// it to the new recordings archive if there is one.
+  async fn respond_from_upstream(
+    &self,
+    send_response: SendResponse<Bytes>,
+    upstream: &Upstream,
+    request_body: Bytes,
+  ) -> Result<(), h2::Error> {
+    let upstream_addr = upstream.addr().unwrap_or(&self.target);
+    let (response, maybe_body) = match upstream
+      .forward(&self.head, request_body, &self.target)
+      .await
+    {
+      Ok(parts) => parts,
+      Err(err) => {
+        log::warn!(
+          "{} UPSTREAM {} {} {} failed: {}",
+          self.name(),
+          upstream_addr,
+          self.method(),
+          self.uri(),
+          err
+        );
+        let status = match err.kind() {
+          io::ErrorKind::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+          _ => StatusCode::BAD_GATEWAY,
+        };
+        return self.respond_with_status(send_response, status);
+      }
+    };
+
+    log::info!(
+      "{} UPSTREAM {} {} {} {}",
+      self.name(),
+      upstream_addr,
+      response.status().as_u16(),
+      self.method(),
+      self.uri()
+    );
+
+    if let Some(new_recordings) = upstream.new_recordings() {
+      // HEAD is served from the GET response, so it is not recorded
+      if !self.is_head() {
+        let key = self
+          .response_set
+          .key_for_target(self.method(), self.uri(), Some(&self.target));
+        let recorded = RecordedResponse::new(
+          response.status(),
+          Arc::new(response.headers().clone()),
+          maybe_body.clone(),
+        );
+        new_recordings.add(&self.response_set, &key, &recorded);
+      }
+    }
+
+    self
+      .respond_with_parts(send_response, response, maybe_body)
+      .await
+  }
+
   async fn respond_with_body(
     &self,
     mut respond: SendResponse<Bytes>,
@@ -198,17 +687,133 @@ impl RequestAcceptor {
     Ok(())
   }
 
+  fn respond_with_status(
+    &self,
+    mut respond: SendResponse<Bytes>,
+    status: StatusCode,
+  ) -> Result<(), h2::Error> {
+    let mut response = Response::new(());
+    *response.status_mut() = status;
+    respond.send_response(response, true)?;
+    Ok(())
+  }
+
   fn respond_with_not_found(&self, mut respond: SendResponse<Bytes>) -> Result<(), h2::Error> {
     let mut response = Response::new(());
     *response.status_mut() = StatusCode::NOT_FOUND;
 
     respond.send_response(response, true)?;
 
-    log::debug!("{} 404 {} {} None", self.name(), self.method(), self.uri());
+    log::debug!(
+      "{} 404 {} {} None CONNECT {}",
+      self.name(),
+      self.method(),
+      self.uri(),
+      self.target
+    );
+
+    Ok(())
+  }
+
+  fn respond_with_misdirected(&self, mut respond: SendResponse<Bytes>) -> Result<(), h2::Error> {
+    let mut response = Response::new(());
+    *response.status_mut() = StatusCode::MISDIRECTED_REQUEST;
+
+    respond.send_response(response, true)?;
+
+    log::warn!(
+      "{} 421 {} {} does not match CONNECT {}",
+      self.name(),
+      self.method(),
+      self.uri(),
+      self.target
+    );
 
     Ok(())
   }
 
+  /// Sends the recorded events with their recorded delays, with the
+  /// response recorded for the key or a bare event stream response.
+  async fn respond_with_events(
+    &self,
+    mut respond: SendResponse<Bytes>,
+    key: &str,
+    events: &[ServerSentEvent],
+  ) -> Result<(), h2::Error> {
+    let response = match self.response_set.get_response(key) {
+      Some(recorded) => recorded.to_parts().0,
+      None => {
+        let mut response = Response::new(());
+        response
+          .headers_mut()
+          .insert(CONTENT_TYPE, HeaderValue::from_static(EVENT_STREAM));
+        response
+      }
+    };
+    let close = self.options.close_event_streams;
+    let mut send_stream = respond.send_response(response, close && events.is_empty())?;
+    for (i, event) in events.iter().enumerate() {
+      let delay = Box::pin(sleep(event.delay()));
+      if let Either::Right((reason, _)) =
+        select(delay, poll_fn(|cx| send_stream.poll_reset(cx))).await
+      {
+        log::debug!(
+          "{} Server-Sent Events {} reset {:?}",
+          self.name(),
+          self.uri(),
+          reason?
+        );
+        return Ok(());
+      }
+      let end_of_stream = close && i + 1 == events.len();
+      send_stream.send_data(event.data().clone(), end_of_stream)?;
+    }
+    log::debug!(
+      "{} Server-Sent Events {} sent {} events",
+      self.name(),
+      self.uri(),
+      events.len()
+    );
+    if !close {
+      poll_fn(|cx| send_stream.poll_reset(cx)).await?;
+    }
+    Ok(())
+  }
+
+  /// Accepts the WebSocket and plays the script recorded for it, the
+  /// key uses GET as the method as an HTTP/1.1 upgrade would.
+  async fn respond_with_web_socket(
+    &self,
+    body: RecvStream,
+    mut respond: SendResponse<Bytes>,
+  ) -> Result<(), h2::Error> {
+    let key = self
+      .response_set
+      .key_for_target(&Method::GET, self.uri(), Some(&self.target));
+    let script = match self.response_set.get_web_socket_script(&key) {
+      Some(script) => script.clone(),
+      None => return self.respond_with_not_found(respond),
+    };
+    log::debug!(
+      "{} WebSocket {} {} steps",
+      self.name(),
+      self.uri(),
+      script.len()
+    );
+    let mut response = Response::new(());
+    // the recorded handshake response may have picked a subprotocol
+    if let Some(recorded) = self.response_set.get_response(&key) {
+      if let Some(protocol) = recorded.headers().get(SEC_WEBSOCKET_PROTOCOL) {
+        response
+          .headers_mut()
+          .insert(SEC_WEBSOCKET_PROTOCOL, protocol.clone());
+      }
+    }
+    let send_stream = respond.send_response(response, false)?;
+    let label = format!("{} {}", self.name(), self.uri());
+    WebSocket::new(send_stream, body, label).play(&script).await
+  }
+
   async fn respond_and_wait_for_reset(
     &self,
     mut respond: SendResponse<Bytes>,
@@ -0,0 +1,55 @@
+use super::error::ServerError;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Deadlines for the phases of a connection, None waits forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+  /// socks or HTTP CONNECT handshake
+  pub proxy_handshake: Option<Duration>,
+  /// TLS handshake after the proxy handshake
+  pub tls_handshake: Option<Duration>,
+  /// client connection preface and SETTINGS after the TLS handshake
+  pub h2_preface: Option<Duration>,
+  /// close the connection when it has had no open streams for this long
+  pub idle: Option<Duration>,
+  /// forwarding a miss upstream and reading its whole response
+  pub upstream: Option<Duration>,
+}
+
+impl Default for Timeouts {
+  /// A stalled handshake gives up after 10 seconds and an upstream
+  /// after 30, idle connections are kept open like a browser would
+  /// expect.
+  fn default() -> Self {
+    let handshake = Some(Duration::from_secs(10));
+    Timeouts {
+      proxy_handshake: handshake,
+      tls_handshake: handshake,
+      h2_preface: handshake,
+      idle: None,
+      upstream: Some(Duration::from_secs(30)),
+    }
+  }
+}
+
+/// Awaits the future with the deadline if there is one, the error
+/// variant for the phase is returned if the deadline passes first.
+pub(super) async fn with_timeout<F, T, E>(
+  duration: Option<Duration>,
+  future: F,
+  timed_out: fn(Duration) -> ServerError,
+) -> Result<T, ServerError>
+where
+  F: Future<Output = Result<T, E>>,
+  ServerError: From<E>,
+{
+  match duration {
+    Some(duration) => match timeout(duration, future).await {
+      Ok(result) => Ok(result?),
+      Err(_) => Err(timed_out(duration)),
+    },
+    None => Ok(future.await?),
+  }
+}
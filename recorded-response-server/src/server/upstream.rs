@@ -0,0 +1,452 @@
+use bytes::Bytes;
+use http::header::HeaderName;
+use http::header::HeaderValue;
+use http::header::CONTENT_LENGTH;
+use http::header::HOST;
+use http::header::TRANSFER_ENCODING;
+use http::request::Parts;
+use http::Method;
+use http::Response;
+use http::StatusCode;
+use httparse::Status;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio::time::timeout;
+use tokio_rustls::rustls;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+use tracerbench_recorded_response_set::is_connection_header;
+use tracerbench_recorded_response_set::RecordedResponse;
+use tracerbench_recorded_response_set::RecordedResponseSet;
+use tracerbench_recorded_response_set::RecordedResponseSetsBuilder;
+
+/// max headers in an upstream response
+const MAX_HEADERS: usize = 128;
+
+/// max bytes of an upstream response, head and body
+const MAX_RESPONSE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// HTTP/1.1 server requests missing from the set are forwarded to
+/// instead of responding 404, such as a local dev server or the real
+/// destination when recording.
+pub struct Upstream {
+  target: Target,
+  timeout: Option<Duration>,
+  new_recordings: Option<Arc<NewRecordings>>,
+}
+
+enum Target {
+  /// plain HTTP/1.1 server at host:port
+  Addr(String),
+  /// the host:port the client dialed through the proxy over TLS
+  Destination(TlsConnector),
+}
+
+impl Upstream {
+  /// Upstream at host:port, requests are sent with the :authority
+  /// of the request as the Host header.
+  pub fn new(addr: impl Into<String>) -> Self {
+    Upstream {
+      target: Target::Addr(addr.into()),
+      timeout: None,
+      new_recordings: None,
+    }
+  }
+
+  /// Upstream that is the CONNECT destination of each connection,
+  /// requests are sent over TLS with :authority as the SNI hostname
+  /// and verified against the web PKI roots.
+  pub fn destination() -> Self {
+    let mut client_config = rustls::ClientConfig::new();
+    client_config
+      .root_store
+      .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    Self::destination_with_client_config(Arc::new(client_config))
+  }
+
+  /// Destination upstream with a TLS client config of its own,
+  /// for example one trusting a local CA.
+  pub fn destination_with_client_config(mut client_config: Arc<rustls::ClientConfig>) -> Self {
+    // responses are read to the end of the connection
+    Arc::make_mut(&mut client_config).set_protocols(&[b"http/1.1".to_vec()]);
+    Upstream {
+      target: Target::Destination(TlsConnector::from(client_config)),
+      timeout: None,
+      new_recordings: None,
+    }
+  }
+
+  /// Give up on a forwarded request whose response has not been read
+  /// to the end within the timeout, None waits forever.
+  pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Add the forwarded responses to the new recordings archive.
+  pub fn with_new_recordings(mut self, new_recordings: Arc<NewRecordings>) -> Self {
+    self.new_recordings = Some(new_recordings);
+    self
+  }
+
+  /// host:port requests are forwarded to, None when it is the
+  /// CONNECT destination of each connection.
+  pub fn addr(&self) -> Option<&str> {
+    match &self.target {
+      Target::Addr(addr) => Some(addr),
+      Target::Destination(_) => None,
+    }
+  }
+
+  pub(super) fn new_recordings(&self) -> Option<&Arc<NewRecordings>> {
+    self.new_recordings.as_ref()
+  }
+
+  /// Forwards the request on a new connection and reads the response
+  /// to the end, hop-by-hop headers are dropped both ways. target is
+  /// the host:port the client dialed through the proxy. The error is
+  /// TimedOut if the timeout passes first.
+  pub(super) async fn forward(
+    &self,
+    head: &Parts,
+    body: Bytes,
+    target: &str,
+  ) -> io::Result<(Response<()>, Option<Bytes>)> {
+    let request = request_bytes(head, &body, self.new_recordings.is_some())?;
+    let response = match self.timeout {
+      Some(duration) => timeout(duration, self.send(head, &request, target))
+        .await
+        .map_err(|_| {
+          io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no complete response within {:?}", duration),
+          )
+        })??,
+      None => self.send(head, &request, target).await?,
+    };
+    parse_response(&response, head.method == Method::HEAD)
+  }
+
+  async fn send(&self, head: &Parts, request: &[u8], target: &str) -> io::Result<Vec<u8>> {
+    Ok(match &self.target {
+      Target::Addr(addr) => exchange(TcpStream::connect(addr).await?, request).await?,
+      Target::Destination(connector) => {
+        let host = match head.uri.host() {
+          Some(host) => host,
+          None => target_host(target),
+        };
+        let server_name = DNSNameRef::try_from_ascii_str(host)
+          .map_err(|_| invalid_input(format!("{:?} is not a valid TLS server name", host)))?;
+        let socket = TcpStream::connect(target).await?;
+        exchange(connector.connect(server_name, socket).await?, request).await?
+      }
+    })
+  }
+}
+
+async fn exchange<S>(mut socket: S, request: &[u8]) -> io::Result<Vec<u8>>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  socket.write_all(request).await?;
+  let mut response = Vec::new();
+  // one byte past the limit tells a response at the limit from a larger one
+  (&mut socket)
+    .take(MAX_RESPONSE_SIZE + 1)
+    .read_to_end(&mut response)
+    .await?;
+  if response.len() as u64 > MAX_RESPONSE_SIZE {
+    return Err(invalid_data(format!(
+      "upstream response larger than {} bytes",
+      MAX_RESPONSE_SIZE
+    )));
+  }
+  Ok(response)
+}
+
+/// The host of a host:port target.
+fn target_host(target: &str) -> &str {
+  match target.rfind(':') {
+    Some(i) => &target[..i],
+    None => target,
+  }
+}
+
+/// The HTTP/1.1 request, when recording conditional and range headers
+/// are dropped so the full response is recorded rather than a 304 or
+/// 206 that only makes sense to the cache of the recording client.
+fn request_bytes(head: &Parts, body: &[u8], recording: bool) -> io::Result<Vec<u8>> {
+  let path_and_query = head
+    .uri
+    .path_and_query()
+    .map_or("/", |path_and_query| path_and_query.as_str());
+  let mut bytes = Vec::with_capacity(1024 + body.len());
+  write!(bytes, "{} {} HTTP/1.1\r\n", head.method, path_and_query)?;
+  if let Some(authority) = head.uri.authority() {
+    write!(bytes, "host: {}\r\n", authority)?;
+  }
+  for (name, value) in head.headers.iter() {
+    if *name == HOST
+      || *name == CONTENT_LENGTH
+      || is_connection_header(name, value)
+      || (recording && is_conditional(name))
+    {
+      continue;
+    }
+    bytes.extend_from_slice(name.as_str().as_bytes());
+    bytes.extend_from_slice(b": ");
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.extend_from_slice(b"\r\n");
+  }
+  if !body.is_empty() {
+    write!(bytes, "content-length: {}\r\n", body.len())?;
+  }
+  // the response is read to the end of the connection
+  bytes.extend_from_slice(b"connection: close\r\n\r\n");
+  bytes.extend_from_slice(body);
+  Ok(bytes)
+}
+
+/// Parses the final response, interim 1xx responses before it such as
+/// 100 Continue are skipped.
+fn parse_response(mut bytes: &[u8], is_head: bool) -> io::Result<(Response<()>, Option<Bytes>)> {
+  let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+  let (parsed, head_len, status) = loop {
+    let mut parsed = httparse::Response::new(&mut headers);
+    let head_len = match parsed.parse(bytes).map_err(invalid_data)? {
+      Status::Complete(head_len) => head_len,
+      Status::Partial => return Err(invalid_data("incomplete upstream response")),
+    };
+    let status = StatusCode::from_u16(parsed.code.unwrap_or_default()).map_err(invalid_data)?;
+    if !status.is_informational() || status == StatusCode::SWITCHING_PROTOCOLS {
+      break (parsed, head_len, status);
+    }
+    bytes = &bytes[head_len..];
+  };
+
+  let mut response = Response::new(());
+  *response.status_mut() = status;
+  let mut chunked = false;
+  let mut content_length: Option<usize> = None;
+  for header in parsed.headers.iter() {
+    let name = HeaderName::from_bytes(header.name.as_bytes()).map_err(invalid_data)?;
+    let value = HeaderValue::from_bytes(header.value).map_err(invalid_data)?;
+    if name == TRANSFER_ENCODING {
+      chunked = value.as_bytes().eq_ignore_ascii_case(b"chunked");
+    } else if name == CONTENT_LENGTH {
+      content_length = value.to_str().ok().and_then(|len| len.parse().ok());
+    }
+    if !is_connection_header(&name, &value) {
+      response.headers_mut().append(name, value);
+    }
+  }
+
+  let rest = &bytes[head_len..];
+  let body = if is_head
+    || status.is_informational()
+    || status == StatusCode::NO_CONTENT
+    || status == StatusCode::NOT_MODIFIED
+  {
+    Vec::new()
+  } else if chunked {
+    dechunk(rest)?
+  } else if let Some(len) = content_length {
+    rest
+      .get(..len)
+      .ok_or_else(|| invalid_data("upstream response body shorter than content-length"))?
+      .to_vec()
+  } else {
+    rest.to_vec()
+  };
+
+  let body = if body.is_empty() {
+    None
+  } else {
+    Some(Bytes::from(body))
+  };
+  Ok((response, body))
+}
+
+/// Decodes a chunked body, trailers are dropped.
+fn dechunk(mut bytes: &[u8]) -> io::Result<Vec<u8>> {
+  let mut body = Vec::with_capacity(bytes.len());
+  loop {
+    let (start, size) = match httparse::parse_chunk_size(bytes)
+      .map_err(|_| invalid_data("invalid chunk size in upstream response"))?
+    {
+      Status::Complete(chunk) => chunk,
+      Status::Partial => return Err(invalid_data("incomplete chunked upstream response")),
+    };
+    if size == 0 {
+      return Ok(body);
+    }
+    let end = start + size as usize;
+    let chunk = bytes
+      .get(start..end)
+      .ok_or_else(|| invalid_data("incomplete chunked upstream response"))?;
+    body.extend_from_slice(chunk);
+    // CRLF after the chunk data
+    bytes = bytes.get(end + 2..).unwrap_or_default();
+  }
+}
+
+fn is_conditional(name: &HeaderName) -> bool {
+  matches!(
+    name.as_str(),
+    "if-match"
+      | "if-none-match"
+      | "if-modified-since"
+      | "if-unmodified-since"
+      | "if-range"
+      | "range"
+  )
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+  E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+  io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn invalid_input<E>(err: E) -> io::Error
+where
+  E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+  io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+/// Archive of responses forwarded from upstreams, in the same format
+/// as recorded archives. Responses are buffered and the archive is
+/// rewritten FLUSH_DELAY after the first response added since the last
+/// write, call flush before the server stops for the rest.
+pub struct NewRecordings {
+  path: PathBuf,
+  builder: Mutex<RecordedResponseSetsBuilder>,
+  /// a write is scheduled for the buffered responses
+  flush_scheduled: AtomicBool,
+  /// held while the archive is written
+  writing: Mutex<()>,
+}
+
+/// How long added responses are buffered before the archive is rewritten.
+const FLUSH_DELAY: Duration = Duration::from_secs(1);
+
+impl NewRecordings {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    NewRecordings {
+      path: path.into(),
+      builder: Mutex::new(RecordedResponseSetsBuilder::new()),
+      flush_scheduled: AtomicBool::new(false),
+      writing: Mutex::new(()),
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Rewrites the archive with the responses added so far, this blocks
+  /// on the file system. Nothing is written before the first response,
+  /// the result is whether the archive was written.
+  pub fn flush(&self) -> io::Result<bool> {
+    let _writing = self.writing.lock().unwrap();
+    let bytes = {
+      let builder = self.builder.lock().unwrap();
+      if builder.is_empty() {
+        return Ok(false);
+      }
+      serde_cbor::to_vec(&*builder).map_err(invalid_data)?
+    };
+    // replace the archive whole so it is never read half written
+    let tmp = self.path.with_extension("tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, &self.path)?;
+    Ok(true)
+  }
+
+  /// Adds the response under its request key to a set named like the
+  /// set it missed in and schedules a write of the archive. A set
+  /// without an entry key gets the key of its first response.
+  pub(super) fn add(
+    self: &Arc<Self>,
+    set: &RecordedResponseSet,
+    key: &str,
+    response: &RecordedResponse,
+  ) {
+    self.buffer(set, key, response);
+    if self.flush_scheduled.swap(true, Ordering::SeqCst) {
+      return;
+    }
+    let new_recordings = self.clone();
+    tokio::spawn(async move {
+      sleep(FLUSH_DELAY).await;
+      // responses added from here on schedule another write
+      new_recordings
+        .flush_scheduled
+        .store(false, Ordering::SeqCst);
+      let path = new_recordings.path.clone();
+      match tokio::task::spawn_blocking(move || new_recordings.flush()).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => log::warn!(
+          "failed to write new recordings to {}: {}",
+          path.display(),
+          err
+        ),
+        Err(err) => log::warn!(
+          "failed to write new recordings to {}: {}",
+          path.display(),
+          err
+        ),
+      }
+    });
+  }
+
+  fn buffer(&self, set: &RecordedResponseSet, key: &str, response: &RecordedResponse) {
+    let mut builder = self.builder.lock().unwrap();
+    if !builder.has_set(set.name()) {
+      builder.add_set(
+        set.socks_port(),
+        set.name(),
+        match set.entry_key() {
+          "" => key,
+          entry_key => entry_key,
+        },
+        set.request_key().clone(),
+      );
+    }
+    builder.add_response(set.name(), key, response);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_response_skips_informational() {
+    let bytes = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nlink: </app.js>; rel=preload\r\n\r\nHTTP/1.1 201 Created\r\ncontent-length: 2\r\n\r\nok";
+    let (response, body) = parse_response(bytes, false).unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert!(response.headers().get("link").is_none());
+    assert_eq!(body.unwrap().as_ref(), b"ok");
+  }
+
+  #[test]
+  fn test_parse_response_without_final_response() {
+    assert!(parse_response(b"HTTP/1.1 100 Continue\r\n\r\n", false).is_err());
+  }
+}
@@ -0,0 +1,336 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::future::poll_fn;
+use futures::future::select;
+use futures::future::Either;
+use h2::RecvStream;
+use h2::SendStream;
+use std::convert::TryInto;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracerbench_recorded_response_set::WebSocketMessage;
+use tracerbench_recorded_response_set::WebSocketStep;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// close status codes of RFC 6455
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_PAYLOAD: u16 = 1007;
+const POLICY_VIOLATION: u16 = 1008;
+const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// max size of a message from the client
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// A WebSocket over an extended CONNECT stream (RFC 8441) that plays
+/// a recorded message script to the client.
+pub(super) struct WebSocket {
+  send_stream: SendStream<Bytes>,
+  recv_stream: RecvStream,
+  buffer: BytesMut,
+  ended: bool,
+  /// set name and path for logs
+  label: String,
+}
+
+struct Frame {
+  fin: bool,
+  opcode: u8,
+  payload: Bytes,
+}
+
+impl WebSocket {
+  pub(super) fn new(
+    send_stream: SendStream<Bytes>,
+    recv_stream: RecvStream,
+    label: String,
+  ) -> Self {
+    WebSocket {
+      send_stream,
+      recv_stream,
+      buffer: BytesMut::new(),
+      ended: false,
+      label,
+    }
+  }
+
+  /// Plays the steps in order, a script without a close step leaves
+  /// the WebSocket open until the client closes it.
+  pub(super) async fn play(mut self, script: &[WebSocketStep]) -> Result<(), h2::Error> {
+    for step in script {
+      match step {
+        WebSocketStep::Send { delay, message } => {
+          if !self.wait(*delay).await? {
+            return Ok(());
+          }
+          let frame = match message {
+            WebSocketMessage::Text(text) => encode_frame(TEXT, text.as_bytes()),
+            WebSocketMessage::Binary(bytes) => encode_frame(BINARY, bytes),
+          };
+          self.send_stream.send_data(frame, false)?;
+        }
+        WebSocketStep::Receive { expected } => {
+          let message = match self.receive().await? {
+            Some(message) => message,
+            None => return Ok(()),
+          };
+          if let Some(expected) = expected {
+            if *expected != message {
+              log::warn!(
+                "{} WebSocket expected {:?} got {:?}",
+                self.label,
+                expected,
+                message
+              );
+              return self.close(POLICY_VIOLATION);
+            }
+          }
+        }
+        WebSocketStep::Close { delay, code } => {
+          if !self.wait(*delay).await? {
+            return Ok(());
+          }
+          return self.close(*code);
+        }
+      }
+    }
+    log::debug!("{} WebSocket script done", self.label);
+    // answer pings until the client closes
+    while self.receive().await?.is_some() {}
+    Ok(())
+  }
+
+  /// Waits for the delay, false if the client reset the stream meanwhile.
+  async fn wait(&mut self, delay: Duration) -> Result<bool, h2::Error> {
+    if delay == Duration::from_secs(0) {
+      return Ok(true);
+    }
+    let send_stream = &mut self.send_stream;
+    match select(
+      Box::pin(sleep(delay)),
+      poll_fn(|cx| send_stream.poll_reset(cx)),
+    )
+    .await
+    {
+      Either::Left(_) => Ok(true),
+      Either::Right((reason, _)) => {
+        log::debug!("{} WebSocket reset {:?}", self.label, reason?);
+        Ok(false)
+      }
+    }
+  }
+
+  /// The next message from the client, pings are answered on the way.
+  /// None once the WebSocket is closed.
+  async fn receive(&mut self) -> Result<Option<WebSocketMessage>, h2::Error> {
+    let mut message: Option<(u8, BytesMut)> = None;
+    loop {
+      let frame = match self.read_frame().await? {
+        Some(frame) => frame,
+        None => return Ok(None),
+      };
+      match (frame.opcode, &mut message) {
+        (PING, _) => self
+          .send_stream
+          .send_data(encode_frame(PONG, &frame.payload), false)?,
+        (PONG, _) => (),
+        (CLOSE, _) => {
+          // echo the status code
+          let code = frame.payload.get(..2).unwrap_or_default();
+          self.ended = true;
+          self
+            .send_stream
+            .send_data(encode_frame(CLOSE, code), true)?;
+          return Ok(None);
+        }
+        (TEXT, None) | (BINARY, None) => {
+          message = Some((frame.opcode, BytesMut::from(&frame.payload[..])));
+        }
+        (CONTINUATION, Some((_, data))) => {
+          if data.len() + frame.payload.len() > MAX_MESSAGE_LEN {
+            self.close(MESSAGE_TOO_BIG)?;
+            return Ok(None);
+          }
+          data.extend_from_slice(&frame.payload);
+        }
+        _ => {
+          log::debug!(
+            "{} WebSocket unexpected opcode {}",
+            self.label,
+            frame.opcode
+          );
+          self.close(PROTOCOL_ERROR)?;
+          return Ok(None);
+        }
+      }
+      if !frame.fin || frame.opcode >= CLOSE {
+        continue;
+      }
+      if let Some((opcode, data)) = message.take() {
+        if opcode == BINARY {
+          return Ok(Some(WebSocketMessage::Binary(data.freeze())));
+        }
+        match String::from_utf8(data.to_vec()) {
+          Ok(text) => return Ok(Some(WebSocketMessage::Text(text))),
+          Err(_) => {
+            self.close(INVALID_PAYLOAD)?;
+            return Ok(None);
+          }
+        }
+      }
+    }
+  }
+
+  async fn read_frame(&mut self) -> Result<Option<Frame>, h2::Error> {
+    loop {
+      match parse_frame(&mut self.buffer) {
+        Ok(Some(frame)) => return Ok(Some(frame)),
+        Ok(None) => (),
+        Err(err) => {
+          log::debug!("{} WebSocket {}", self.label, err);
+          self.close(PROTOCOL_ERROR)?;
+          return Ok(None);
+        }
+      }
+      let recv_stream = &mut self.recv_stream;
+      match poll_fn(|cx| recv_stream.poll_data(cx)).await {
+        Some(chunk) => {
+          let chunk = chunk?;
+          let _ = recv_stream.flow_control().release_capacity(chunk.len());
+          self.buffer.extend_from_slice(&chunk);
+        }
+        None => {
+          // the client ended its side without a close frame
+          if !self.ended {
+            self.ended = true;
+            self.send_stream.send_data(Bytes::new(), true)?;
+          }
+          return Ok(None);
+        }
+      }
+    }
+  }
+
+  /// Sends a close frame and ends the stream.
+  fn close(&mut self, code: u16) -> Result<(), h2::Error> {
+    log::debug!("{} WebSocket close {}", self.label, code);
+    self.ended = true;
+    self
+      .send_stream
+      .send_data(encode_frame(CLOSE, &code.to_be_bytes()), true)
+  }
+}
+
+/// An unmasked final frame, as servers send them.
+fn encode_frame(opcode: u8, payload: &[u8]) -> Bytes {
+  let mut frame = BytesMut::with_capacity(payload.len() + 10);
+  frame.put_u8(0x80 | opcode);
+  match payload.len() {
+    len if len < 126 => frame.put_u8(len as u8),
+    len if len <= u16::MAX as usize => {
+      frame.put_u8(126);
+      frame.put_u16(len as u16);
+    }
+    len => {
+      frame.put_u8(127);
+      frame.put_u64(len as u64);
+    }
+  }
+  frame.extend_from_slice(payload);
+  frame.freeze()
+}
+
+/// Takes a frame off the front of the buffer, None until the whole
+/// frame is buffered. Client frames are masked, the payload is
+/// returned unmasked.
+fn parse_frame(buffer: &mut BytesMut) -> Result<Option<Frame>, &'static str> {
+  if buffer.len() < 2 {
+    return Ok(None);
+  }
+  if buffer[0] & 0x70 != 0 {
+    return Err("reserved bits are set");
+  }
+  let fin = buffer[0] & 0x80 != 0;
+  let opcode = buffer[0] & 0x0f;
+  let masked = buffer[1] & 0x80 != 0;
+  let (len, mut header_len) = match buffer[1] & 0x7f {
+    126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+    127 if buffer.len() >= 10 => (u64::from_be_bytes(buffer[2..10].try_into().unwrap()), 10),
+    126 | 127 => return Ok(None),
+    len => (len as u64, 2),
+  };
+  if len > MAX_MESSAGE_LEN as u64 {
+    return Err("frame is too large");
+  }
+  let len = len as usize;
+  let mask_start = header_len;
+  if masked {
+    header_len += 4;
+  }
+  if buffer.len() < header_len + len {
+    return Ok(None);
+  }
+  let mut frame = buffer.split_to(header_len + len);
+  let mask: Option<[u8; 4]> = if masked {
+    Some(frame[mask_start..header_len].try_into().unwrap())
+  } else {
+    None
+  };
+  let mut payload = frame.split_off(header_len);
+  if let Some(mask) = mask {
+    for (i, byte) in payload.iter_mut().enumerate() {
+      *byte ^= mask[i % 4];
+    }
+  }
+  Ok(Some(Frame {
+    fin,
+    opcode,
+    payload: payload.freeze(),
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_masked_frame() {
+    let mask = [1, 2, 3, 4];
+    let mut bytes = BytesMut::from(&[0x81, 0x85][..]);
+    bytes.extend_from_slice(&mask);
+    for (i, byte) in b"hello".iter().enumerate() {
+      bytes.put_u8(byte ^ mask[i % 4]);
+    }
+    bytes.extend_from_slice(&[0x89]);
+    let frame = parse_frame(&mut bytes).unwrap().unwrap();
+    assert!(frame.fin);
+    assert_eq!(frame.opcode, TEXT);
+    assert_eq!(frame.payload, "hello");
+    // the start of the next frame stays buffered
+    assert_eq!(&bytes[..], &[0x89]);
+    assert!(parse_frame(&mut bytes).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_parse_partial_frame() {
+    let encoded = encode_frame(BINARY, &[7u8; 300]);
+    assert_eq!(&encoded[..4], &[0x82, 126, 1, 44]);
+    let mut bytes = BytesMut::from(&encoded[..100]);
+    assert!(parse_frame(&mut bytes).unwrap().is_none());
+    bytes.extend_from_slice(&encoded[100..]);
+    let frame = parse_frame(&mut bytes).unwrap().unwrap();
+    assert_eq!(frame.opcode, BINARY);
+    assert_eq!(frame.payload.len(), 300);
+    assert!(bytes.is_empty());
+  }
+
+  #[test]
+  fn test_reserved_bits() {
+    let mut bytes = BytesMut::from(&[0xc1, 0x00][..]);
+    assert!(parse_frame(&mut bytes).is_err());
+  }
+}
@@ -1,19 +1,27 @@
 use super::Config;
+use super::NewRecordings;
+use super::Route;
 use super::Server;
+use super::Upstream;
 use futures::future::try_join_all;
 use std::io;
+use std::net::SocketAddr;
 use std::ops::Deref;
 use std::sync::Arc;
 use tokio_rustls::rustls;
 use tracerbench_recorded_response_set::RecordedResponseSets;
+use tracerbench_socks_proxy::Authentication;
 
-pub struct Servers(Vec<Server>);
+pub struct Servers {
+  servers: Vec<Server>,
+  new_recordings: Option<Arc<NewRecordings>>,
+}
 
 impl Deref for Servers {
   type Target = Vec<Server>;
 
   fn deref(&self) -> &Self::Target {
-    &self.0
+    &self.servers
   }
 }
 
@@ -32,11 +40,100 @@ impl Servers {
     for response_set in response_sets.drain(..) {
       servers.push(Server::new(tls_config.clone(), response_set));
     }
-    Servers(servers)
+    Servers {
+      servers,
+      new_recordings: None,
+    }
   }
 
+  /// Panics if a response set's port is out of range, which
+  /// Config::validate_ports reports as an error.
   pub fn from_config(config: Config) -> Self {
-    Self::from_parts(config.tls_config, config.response_sets)
+    if let Some(port) = config.shared_port {
+      return Self::shared_from_config(&config, port);
+    }
+    let mut servers: Vec<Server> = Vec::with_capacity(config.response_sets.len());
+    let allowed_peers = Arc::new(config.allowed_peers.clone());
+    let authentication = authentication(&config);
+    let new_recordings = new_recordings(&config);
+    for response_set in config.response_sets.iter() {
+      let mut server = Server::from_route(Route {
+        tls_config: config.tls_config_for(response_set.name()),
+        response_set: response_set.clone(),
+        destinations: Vec::new(),
+        upstream: upstream(&config, response_set.name(), &new_recordings),
+      })
+      .with_addr(
+        config
+          .addr_for(response_set)
+          .unwrap_or_else(|err| panic!("{}", err)),
+      )
+      .with_dual_stack(config.dual_stack)
+      .with_allowed_peers(allowed_peers.clone())
+      .with_proxy_protocol(config.proxy_protocol_for(response_set.name()))
+      .with_check_authority(config.check_authority)
+      .with_close_event_streams(config.close_event_streams)
+      .with_range_requests(config.range_requests)
+      .with_cors(config.cors.clone())
+      .with_fallbacks(config.fallbacks.clone())
+      .with_push_rules(config.push_rules.clone())
+      .with_report_bound_addr(config.report_bound_addr)
+      .with_timeouts(config.timeouts);
+      if let Some(authentication) = &authentication {
+        server = server.with_authentication(authentication.clone());
+      }
+      servers.push(server);
+    }
+    Servers {
+      servers,
+      new_recordings,
+    }
+  }
+
+  fn shared_from_config(config: &Config, port: u16) -> Self {
+    let mut routes: Vec<Route> = Vec::with_capacity(config.response_sets.len());
+    let new_recordings = new_recordings(config);
+    for response_set in config.response_sets.iter() {
+      routes.push(Route {
+        tls_config: config.tls_config_for(response_set.name()),
+        response_set: response_set.clone(),
+        destinations: config
+          .set_destinations
+          .get(response_set.name())
+          .cloned()
+          .unwrap_or_default(),
+        upstream: upstream(config, response_set.name(), &new_recordings),
+      });
+    }
+    let mut server = Server::shared(
+      SocketAddr::new(config.bind_addr, port),
+      routes,
+      config.shared_default_set.clone(),
+    )
+    .with_dual_stack(config.dual_stack)
+    .with_allowed_peers(Arc::new(config.allowed_peers.clone()))
+    .with_proxy_protocol(config.proxy_protocol)
+    .with_check_authority(config.check_authority)
+    .with_close_event_streams(config.close_event_streams)
+    .with_range_requests(config.range_requests)
+    .with_cors(config.cors.clone())
+    .with_fallbacks(config.fallbacks.clone())
+    .with_push_rules(config.push_rules.clone())
+    .with_report_bound_addr(config.report_bound_addr)
+    .with_timeouts(config.timeouts);
+    if let Some(authentication) = authentication(config) {
+      server = server.with_authentication(authentication);
+    }
+    Servers {
+      servers: vec![server],
+      new_recordings,
+    }
+  }
+
+  /// The archive misses forwarded upstream are recorded to, flush it
+  /// before exiting so the last responses are not lost.
+  pub fn new_recordings(&self) -> Option<&Arc<NewRecordings>> {
+    self.new_recordings.as_ref()
   }
 
   pub async fn start(&self) -> Result<(), io::Error> {
@@ -48,3 +145,38 @@ impl Servers {
     Ok(())
   }
 }
+
+/// Authentication required by the config, None keeps the server default.
+fn authentication(config: &Config) -> Option<Arc<Authentication>> {
+  if config.socks_credentials.is_empty() {
+    return None;
+  }
+  Some(Arc::new(Authentication::Credentials(Arc::new(
+    config.socks_credentials.clone(),
+  ))))
+}
+
+/// The new recordings archive shared by the upstreams of all sets.
+fn new_recordings(config: &Config) -> Option<Arc<NewRecordings>> {
+  config
+    .new_recordings
+    .as_ref()
+    .map(|path| Arc::new(NewRecordings::new(path)))
+}
+
+fn upstream(
+  config: &Config,
+  set_name: &str,
+  new_recordings: &Option<Arc<NewRecordings>>,
+) -> Option<Arc<Upstream>> {
+  let mut upstream = match config.upstream_for(set_name) {
+    Some(addr) => Upstream::new(addr),
+    None if config.forward_to_destination => Upstream::destination(),
+    None => return None,
+  }
+  .with_timeout(config.timeouts.upstream);
+  if let Some(new_recordings) = new_recordings {
+    upstream = upstream.with_new_recordings(new_recordings.clone());
+  }
+  Some(Arc::new(upstream))
+}
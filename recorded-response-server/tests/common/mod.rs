@@ -0,0 +1,335 @@
+// each test crate uses some of the helpers
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use futures::future::poll_fn;
+use h2::client::SendRequest;
+use h2::RecvStream;
+use http::HeaderMap;
+use http::Method;
+use http::Request;
+use http::StatusCode;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{
+  Certificate, ClientConfig, NoClientAuth, PrivateKey, RootCertStore, ServerConfig,
+};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+use tracerbench_recorded_response_server::{Config, Server, Servers};
+use tracerbench_recorded_response_set::RecordedResponseSet;
+use tracerbench_recorded_response_set::RecordedResponseSets;
+
+pub static HOSTNAME: &str = "localhost";
+
+/// Builds a recorded response set archive with a single set.
+pub struct ArchiveBuilder {
+  bodies: Vec<Value>,
+  header_names: Vec<Value>,
+  header_values: Vec<Value>,
+  headers: Vec<Value>,
+  responses: Vec<Value>,
+  request_key_map: BTreeMap<Value, Value>,
+  event_streams: BTreeMap<Value, Value>,
+  web_socket_scripts: Vec<Value>,
+  web_socket_map: BTreeMap<Value, Value>,
+}
+
+impl ArchiveBuilder {
+  pub fn new() -> ArchiveBuilder {
+    ArchiveBuilder {
+      bodies: Vec::new(),
+      header_names: Vec::new(),
+      header_values: Vec::new(),
+      headers: Vec::new(),
+      responses: Vec::new(),
+      request_key_map: BTreeMap::new(),
+      event_streams: BTreeMap::new(),
+      web_socket_scripts: Vec::new(),
+      web_socket_map: BTreeMap::new(),
+    }
+  }
+
+  /// Adds a response for the request key "METHOD authority path".
+  pub fn response(
+    &mut self,
+    key: &str,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+  ) -> &mut Self {
+    let mut pairs = Vec::with_capacity(headers.len());
+    for (name, value) in headers {
+      pairs.push(Value::Array(vec![
+        index(&mut self.header_names, text(name)),
+        index(&mut self.header_values, text(value)),
+      ]));
+    }
+    let headers_index = index(&mut self.headers, Value::Array(pairs));
+    let body_index = match body {
+      Some(body) => index(&mut self.bodies, Value::Bytes(body.to_vec())),
+      None => Value::Null,
+    };
+    let response_index = index(
+      &mut self.responses,
+      Value::Array(vec![
+        Value::Integer(status.into()),
+        headers_index,
+        body_index,
+      ]),
+    );
+    self.request_key_map.insert(text(key), response_index);
+    self
+  }
+
+  /// Adds server-sent events as (milliseconds since the previous event, event).
+  pub fn events(&mut self, key: &str, events: &[(u64, &str)]) -> &mut Self {
+    let events = events
+      .iter()
+      .map(|(delay, event)| {
+        Value::Array(vec![
+          Value::Integer((*delay).into()),
+          Value::Bytes(event.as_bytes().to_vec()),
+        ])
+      })
+      .collect();
+    self.event_streams.insert(text(key), Value::Array(events));
+    self
+  }
+
+  /// Adds a WebSocket script of [kind, delay, message or code] steps.
+  pub fn web_socket(&mut self, key: &str, steps: Vec<Value>) -> &mut Self {
+    let script_index = index(&mut self.web_socket_scripts, Value::Array(steps));
+    self.web_socket_map.insert(text(key), script_index);
+    self
+  }
+
+  pub fn to_bytes(&self, name: &str, socks_port: u16) -> Vec<u8> {
+    let mut set: BTreeMap<Value, Value> = BTreeMap::new();
+    set.insert(text("socksPort"), Value::Integer(socks_port.into()));
+    set.insert(text("name"), text(name));
+    set.insert(text("entryKey"), text("GET localhost /"));
+    set.insert(
+      text("requestKeyProgram"),
+      Value::Array(vec![Value::Array(Vec::new()), Value::Bytes(Vec::new())]),
+    );
+    set.insert(
+      text("requestKeyMap"),
+      Value::Map(self.request_key_map.clone()),
+    );
+    if !self.event_streams.is_empty() {
+      set.insert(text("eventStreams"), Value::Map(self.event_streams.clone()));
+    }
+    if !self.web_socket_map.is_empty() {
+      set.insert(
+        text("webSocketMap"),
+        Value::Map(self.web_socket_map.clone()),
+      );
+    }
+    let mut archive = vec![
+      Value::Array(self.bodies.clone()),
+      Value::Array(self.header_names.clone()),
+      Value::Array(self.header_values.clone()),
+      Value::Array(self.headers.clone()),
+      Value::Array(self.responses.clone()),
+      Value::Array(vec![Value::Map(set)]),
+    ];
+    if !self.web_socket_scripts.is_empty() {
+      archive.push(Value::Array(self.web_socket_scripts.clone()));
+    }
+    let archive = Value::Array(archive);
+    serde_cbor::to_vec(&archive).unwrap()
+  }
+
+  pub fn build(&self, name: &str) -> Arc<RecordedResponseSet> {
+    self.build_sets(name).remove(0)
+  }
+
+  pub fn build_sets(&self, name: &str) -> RecordedResponseSets {
+    let bytes = self.to_bytes(name, 0);
+    serde_cbor::from_slice(&bytes).unwrap()
+  }
+}
+
+fn text(s: &str) -> Value {
+  Value::Text(s.to_owned())
+}
+
+fn index(table: &mut Vec<Value>, value: Value) -> Value {
+  table.push(value);
+  Value::Integer((table.len() - 1) as i128)
+}
+
+/// A self signed certificate for localhost.
+pub struct TestCert {
+  pub cert: Certificate,
+  pub key: PrivateKey,
+}
+
+impl TestCert {
+  pub fn new() -> TestCert {
+    let generated = rcgen::generate_simple_self_signed(vec![HOSTNAME.to_owned()]).unwrap();
+    TestCert {
+      cert: Certificate(generated.serialize_der().unwrap()),
+      key: PrivateKey(generated.serialize_private_key_der()),
+    }
+  }
+
+  pub fn server_config(&self) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+      .set_single_cert(vec![self.cert.clone()], self.key.clone())
+      .unwrap();
+    config.set_protocols(&[b"h2".to_vec()]);
+    Arc::new(config)
+  }
+
+  pub fn client_config(&self) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(&self.cert).unwrap();
+    let mut config = ClientConfig::new();
+    config.root_store = roots;
+    config.set_protocols(&[b"h2".to_vec()]);
+    Arc::new(config)
+  }
+}
+
+/// Accepts one connection on a local port and hands it to the server,
+/// returns the address to connect to and the handle of the connection task.
+pub async fn serve_one(
+  server: Server,
+) -> (
+  std::net::SocketAddr,
+  tokio::task::JoinHandle<Result<(), tracerbench_recorded_response_server::ServerError>>,
+) {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let handle = tokio::spawn(async move {
+    let (socket, _) = listener.accept().await.unwrap();
+    server.handle(socket).await
+  });
+  (addr, handle)
+}
+
+/// Starts the servers of a config with a shared port of 0 and returns
+/// the address the shared listener is bound to.
+pub async fn serve_shared(config: Config) -> std::net::SocketAddr {
+  assert_eq!(config.shared_port, Some(0));
+  let servers = Servers::from_config(config);
+  let listener = servers[0].listen().await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  tokio::spawn(async move { servers[0].serve(listener).await });
+  addr
+}
+
+/// Waits for an archive written in the background and reads it.
+pub async fn read_archive(path: &Path) -> RecordedResponseSets {
+  for _ in 0..500 {
+    if let Ok(bytes) = std::fs::read(path) {
+      return serde_cbor::from_slice(&bytes).unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+  panic!("{} was not written", path.display());
+}
+
+/// socks 5 CONNECT to localhost:443 without authentication.
+pub async fn socks_connect(socket: &mut TcpStream) {
+  socks_connect_to(socket, 443).await
+}
+
+/// socks 5 CONNECT to localhost:port without authentication.
+pub async fn socks_connect_to(socket: &mut TcpStream, port: u16) {
+  socket.write_all(b"\x05\x01\x00").await.unwrap();
+  let mut reply = [0u8; 2];
+  socket.read_exact(&mut reply).await.unwrap();
+  assert_eq!(reply, [5, 0]);
+  let mut request = vec![5, 1, 0, 3, HOSTNAME.len() as u8];
+  request.extend_from_slice(HOSTNAME.as_bytes());
+  request.extend_from_slice(&port.to_be_bytes());
+  socket.write_all(&request).await.unwrap();
+  let mut reply = [0u8; 10];
+  socket.read_exact(&mut reply).await.unwrap();
+  assert_eq!(reply[1], 0);
+}
+
+/// TLS handshake over the proxied socket.
+pub async fn tls_connect(cert: &TestCert, socket: TcpStream) -> TlsStream<TcpStream> {
+  TlsConnector::from(cert.client_config())
+    .connect(DNSNameRef::try_from_ascii_str(HOSTNAME).unwrap(), socket)
+    .await
+    .unwrap()
+}
+
+/// socks, TLS and h2 handshakes with the server at addr.
+pub async fn h2_connect(cert: &TestCert, addr: std::net::SocketAddr) -> SendRequest<Bytes> {
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+  let tls = tls_connect(cert, socket).await;
+  let (client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+  client
+}
+
+/// Sends the request and reads the whole response body.
+pub async fn send(client: &SendRequest<Bytes>, request: Request<()>) -> (StatusCode, Bytes) {
+  let (status, _, body) = send_request(client, request).await;
+  (status, body)
+}
+
+/// Sends a request for https://localhost{path} with the headers and
+/// reads the whole response body.
+pub async fn send_with(
+  client: &SendRequest<Bytes>,
+  method: Method,
+  path: &str,
+  headers: &[(&str, &str)],
+) -> (StatusCode, HeaderMap, Bytes) {
+  let mut request = Request::builder()
+    .method(method)
+    .uri(format!("https://{}{}", HOSTNAME, path));
+  for (name, value) in headers {
+    request = request.header(*name, *value);
+  }
+  send_request(client, request.body(()).unwrap()).await
+}
+
+async fn send_request(
+  client: &SendRequest<Bytes>,
+  request: Request<()>,
+) -> (StatusCode, HeaderMap, Bytes) {
+  let (response, _) = client
+    .clone()
+    .ready()
+    .await
+    .unwrap()
+    .send_request(request, true)
+    .unwrap();
+  let (parts, body) = response.await.unwrap().into_parts();
+  (parts.status, parts.headers, read_body(body).await)
+}
+
+/// Reads a response body to the end, releasing flow control capacity.
+pub async fn read_body(mut body: RecvStream) -> Bytes {
+  let mut bytes = Vec::new();
+  while let Some(chunk) = poll_fn(|cx| body.poll_data(cx)).await {
+    let chunk = chunk.unwrap();
+    let _ = body.flow_control().release_capacity(chunk.len());
+    bytes.extend_from_slice(&chunk);
+  }
+  Bytes::from(bytes)
+}
+
+/// GET https://localhost{path}
+pub async fn get(client: &SendRequest<Bytes>, path: &str) -> (StatusCode, Bytes) {
+  let request = Request::get(format!("https://{}{}", HOSTNAME, path))
+    .body(())
+    .unwrap();
+  send(client, request).await
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::{h2_connect, send_with, serve_one, ArchiveBuilder, TestCert};
+use http::{Method, StatusCode};
+use tracerbench_recorded_response_server::Server;
+
+const BODY: &[u8] = b"console.log('cached');";
+const LAST_MODIFIED: &str = "Tue, 15 Nov 1994 12:45:26 GMT";
+
+fn server(cert: &TestCert) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response(
+      "GET localhost /app.js",
+      200,
+      &[
+        ("cache-control", "max-age=0"),
+        ("content-type", "text/javascript"),
+        ("etag", "W/\"abc\""),
+        ("last-modified", LAST_MODIFIED),
+      ],
+      Some(BODY),
+    )
+    .response(
+      "GET localhost /dated.js",
+      200,
+      &[
+        ("content-type", "text/javascript"),
+        ("last-modified", LAST_MODIFIED),
+      ],
+      Some(BODY),
+    )
+    .response(
+      "GET localhost /missing.js",
+      404,
+      &[("etag", "\"abc\"")],
+      Some(b"not found"),
+    )
+    .build("control");
+  Server::new(cert.server_config(), response_set)
+}
+
+#[tokio::test]
+async fn test_if_none_match() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) = send_with(
+    &client,
+    Method::GET,
+    "/app.js",
+    &[("if-none-match", "\"xyz\", \"abc\"")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::NOT_MODIFIED);
+  assert!(body.is_empty());
+  assert_eq!(headers["etag"], "W/\"abc\"");
+  assert_eq!(headers["cache-control"], "max-age=0");
+  assert_eq!(headers["last-modified"], LAST_MODIFIED);
+  assert!(headers.get("content-type").is_none());
+
+  let (status, _, body) = send_with(
+    &client,
+    Method::GET,
+    "/app.js",
+    &[("if-none-match", "\"xyz\"")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(!body.is_empty());
+
+  // If-None-Match takes precedence over If-Modified-Since
+  let (status, _, body) = send_with(
+    &client,
+    Method::GET,
+    "/app.js",
+    &[
+      ("if-none-match", "\"xyz\""),
+      ("if-modified-since", LAST_MODIFIED),
+    ],
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, BODY);
+}
+
+#[tokio::test]
+async fn test_if_modified_since() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) = send_with(
+    &client,
+    Method::GET,
+    "/dated.js",
+    &[("if-modified-since", "Wed, 16 Nov 1994 00:00:00 GMT")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::NOT_MODIFIED);
+  assert!(body.is_empty());
+  assert_eq!(headers["last-modified"], LAST_MODIFIED);
+
+  let (status, _, body) = send_with(
+    &client,
+    Method::GET,
+    "/dated.js",
+    &[("if-modified-since", "Mon, 14 Nov 1994 00:00:00 GMT")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(!body.is_empty());
+}
+
+#[tokio::test]
+async fn test_only_ok_responses_are_not_modified() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, _, body) = send_with(
+    &client,
+    Method::GET,
+    "/missing.js",
+    &[("if-none-match", "\"abc\"")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert!(!body.is_empty());
+}
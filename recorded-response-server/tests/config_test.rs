@@ -0,0 +1,105 @@
+mod common;
+
+use common::{ArchiveBuilder, TestCert};
+use rcgen::{BasicConstraints, CertificateParams, IsCa};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use tracerbench_recorded_response_server::{Config, TlsSettings};
+use tracerbench_recorded_response_set::{RecordedResponseSet, RecordedResponseSets};
+
+fn response_set(name: &str, socks_port: u16) -> Arc<RecordedResponseSet> {
+  let bytes = ArchiveBuilder::new()
+    .response("GET localhost /", 200, &[], Some(b"ok"))
+    .to_bytes(name, socks_port);
+  let mut response_sets: RecordedResponseSets = serde_cbor::from_slice(&bytes).unwrap();
+  response_sets.remove(0)
+}
+
+fn config(response_sets: Vec<Arc<RecordedResponseSet>>) -> Config {
+  let cert = TestCert::new();
+  Config::new(String::new(), cert.server_config(), response_sets.into())
+}
+
+#[test]
+fn test_port_out_of_range() {
+  let mut config = config(vec![response_set("control", 65000)]);
+  config.port_offset = 1000;
+  let err = config
+    .addr_for(&config.response_sets[0])
+    .unwrap_err()
+    .to_string();
+  assert!(err.contains("out of range"), "{}", err);
+  assert!(config.validate_ports().is_err());
+
+  config.port_offset = 500;
+  let addr = config.addr_for(&config.response_sets[0]).unwrap();
+  assert_eq!(addr.port(), 65500);
+  assert!(config.validate_ports().is_ok());
+}
+
+#[test]
+fn test_port_collisions_are_per_address() {
+  let mut config = config(vec![
+    response_set("control", 8000),
+    response_set("experiment", 8000),
+  ]);
+  let err = config.validate_ports().unwrap_err().to_string();
+  assert!(err.contains("same port"), "{}", err);
+
+  // the same port on two addresses binds
+  config
+    .set_bind_addrs
+    .insert("experiment".to_owned(), "127.0.0.2".parse().unwrap());
+  assert!(config.validate_ports().is_ok());
+
+  // an unspecified address takes the port on every address
+  config
+    .set_bind_addrs
+    .insert("experiment".to_owned(), "0.0.0.0".parse().unwrap());
+  assert!(config.validate_ports().is_err());
+  config
+    .set_bind_addrs
+    .insert("experiment".to_owned(), "::".parse().unwrap());
+  assert!(config.validate_ports().is_ok());
+  config.dual_stack = true;
+  assert!(config.validate_ports().is_err());
+
+  config.set_ports.insert("experiment".to_owned(), 8001);
+  assert!(config.validate_ports().is_ok());
+}
+
+#[test]
+fn test_set_certificate_has_its_own_spki_digest() {
+  let mut params = CertificateParams::new(Vec::new());
+  params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+  let ca = rcgen::Certificate::from_params(params).unwrap();
+  let dir = std::env::temp_dir();
+  let cert = dir.join(format!("set-ca-{}.pem", std::process::id()));
+  let key = dir.join(format!("set-ca-key-{}.pem", std::process::id()));
+  fs::write(&cert, ca.serialize_pem().unwrap()).unwrap();
+  fs::write(&key, ca.serialize_private_key_pem()).unwrap();
+
+  let mut settings = HashMap::new();
+  settings.insert(
+    "control".to_owned(),
+    TlsSettings {
+      cert: Some(cert.clone()),
+      key: Some(key.clone()),
+      ca: Some(true),
+      ..TlsSettings::default()
+    },
+  );
+  let config = config(vec![
+    response_set("control", 8000),
+    response_set("experiment", 8001),
+  ])
+  .with_tls_settings(&settings);
+  fs::remove_file(cert).unwrap();
+  fs::remove_file(key).unwrap();
+
+  let config = config.unwrap();
+  // base64 of a SHA-256 digest
+  assert_eq!(config.set_spki_digests["control"].len(), 44);
+  assert!(!config.set_spki_digests.contains_key("experiment"));
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use bytes::Bytes;
+use common::{h2_connect, send_with, serve_one, serve_shared, ArchiveBuilder, TestCert};
+use h2::client::SendRequest;
+use http::{HeaderMap, Method, StatusCode};
+use tracerbench_recorded_response_server::{Config, CorsPolicy, CorsSettings, Server};
+
+const ORIGIN: &str = "https://app.example.com";
+
+fn archive() -> ArchiveBuilder {
+  let mut archive = ArchiveBuilder::new();
+  archive
+    .response(
+      "GET localhost /api",
+      200,
+      &[("content-type", "application/json"), ("vary", "accept")],
+      Some(b"{}"),
+    )
+    .response(
+      "GET localhost /public",
+      200,
+      &[("access-control-allow-origin", "*")],
+      Some(b"{}"),
+    )
+    .response(
+      "OPTIONS localhost /recorded",
+      200,
+      &[(
+        "access-control-allow-origin",
+        "https://recorded.example.com",
+      )],
+      None,
+    );
+  archive
+}
+
+fn server(cert: &TestCert) -> Server {
+  Server::new(cert.server_config(), archive().build("control"))
+}
+
+async fn preflight(client: &SendRequest<Bytes>, path: &str) -> (StatusCode, HeaderMap) {
+  let (status, headers, _) = send_with(
+    client,
+    Method::OPTIONS,
+    path,
+    &[
+      ("origin", ORIGIN),
+      ("access-control-request-method", "PUT"),
+      ("access-control-request-headers", "content-type, x-token"),
+    ],
+  )
+  .await;
+  (status, headers)
+}
+
+#[tokio::test]
+async fn test_preflight_echoes_request() {
+  let cert = TestCert::new();
+  let server = server(&cert).with_cors(Some(CorsPolicy::default()));
+  let (addr, _handle) = serve_one(server).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers) = preflight(&client, "/api").await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  assert_eq!(headers["access-control-allow-origin"], ORIGIN);
+  assert_eq!(headers["access-control-allow-methods"], "PUT");
+  assert_eq!(
+    headers["access-control-allow-headers"],
+    "content-type, x-token"
+  );
+  assert_eq!(headers["access-control-allow-credentials"], "true");
+  assert_eq!(headers["vary"], "origin");
+
+  // a recorded preflight is served as recorded
+  let (status, headers) = preflight(&client, "/recorded").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    headers["access-control-allow-origin"],
+    "https://recorded.example.com"
+  );
+}
+
+#[tokio::test]
+async fn test_preflight_with_policy() {
+  let cert = TestCert::new();
+  let policy = CorsSettings {
+    allow_origin: Some("*".to_owned()),
+    allow_methods: Some(vec!["GET".to_owned(), "PUT".to_owned()]),
+    allow_headers: Some(vec!["content-type".to_owned()]),
+    max_age: Some(600),
+    ..CorsSettings::default()
+  }
+  .policy()
+  .unwrap();
+  let (addr, _handle) = serve_one(server(&cert).with_cors(Some(policy))).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers) = preflight(&client, "/api").await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  assert_eq!(headers["access-control-allow-origin"], "*");
+  assert_eq!(headers["access-control-allow-methods"], "GET, PUT");
+  assert_eq!(headers["access-control-allow-headers"], "content-type");
+  assert_eq!(headers["access-control-max-age"], "600");
+  assert!(headers.get("access-control-allow-credentials").is_none());
+  assert!(headers.get("vary").is_none());
+}
+
+#[tokio::test]
+async fn test_headers_added_to_recorded_responses() {
+  let cert = TestCert::new();
+  let policy = CorsPolicy {
+    expose_headers: vec!["x-request-id".to_owned()],
+    ..CorsPolicy::default()
+  };
+  let (addr, _handle) = serve_one(server(&cert).with_cors(Some(policy))).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, _) = send_with(&client, Method::GET, "/api", &[("origin", ORIGIN)]).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(headers["access-control-allow-origin"], ORIGIN);
+  assert_eq!(headers["access-control-allow-credentials"], "true");
+  assert_eq!(headers["access-control-expose-headers"], "x-request-id");
+  let vary: Vec<_> = headers.get_all("vary").iter().collect();
+  assert_eq!(vary, ["accept", "origin"]);
+
+  // recorded access-control headers are kept
+  let (_, headers, _) = send_with(&client, Method::GET, "/public", &[("origin", ORIGIN)]).await;
+  assert_eq!(headers["access-control-allow-origin"], "*");
+  assert!(headers.get("access-control-allow-credentials").is_none());
+
+  // same-origin requests are left alone
+  let (_, headers, _) = send_with(&client, Method::GET, "/api", &[]).await;
+  assert!(headers.get("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn test_shared_listener_applies_cors() {
+  let cert = TestCert::new();
+  let mut config = Config::new(
+    String::new(),
+    cert.server_config(),
+    archive().build_sets("control"),
+  );
+  config.shared_port = Some(0);
+  config.shared_default_set = Some("control".to_owned());
+  config.cors = Some(CorsPolicy::default());
+  let addr = serve_shared(config).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers) = preflight(&client, "/api").await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  assert_eq!(headers["access-control-allow-origin"], ORIGIN);
+  let (status, headers, _) = send_with(&client, Method::GET, "/api", &[("origin", ORIGIN)]).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(headers["access-control-allow-origin"], ORIGIN);
+}
+
+#[tokio::test]
+async fn test_preflight_misses_without_cors() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, _) = preflight(&client, "/api").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_any_origin_with_credentials_is_invalid() {
+  let settings = CorsSettings {
+    allow_origin: Some("*".to_owned()),
+    allow_credentials: Some(true),
+    ..CorsSettings::default()
+  };
+  assert!(settings.policy().is_err());
+  assert!(CorsSettings::default().policy().unwrap().allow_credentials);
+}
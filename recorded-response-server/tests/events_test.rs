@@ -0,0 +1,92 @@
+mod common;
+
+use bytes::Bytes;
+use common::{h2_connect, serve_one, ArchiveBuilder, TestCert, HOSTNAME};
+use futures::future::poll_fn;
+use h2::client::SendRequest;
+use h2::RecvStream;
+use http::Request;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+use tracerbench_recorded_response_server::Server;
+
+fn server(cert: &TestCert) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response(
+      "GET localhost /events",
+      200,
+      &[("content-type", "text/event-stream"), ("x-recorded", "1")],
+      None,
+    )
+    .events(
+      "GET localhost /events",
+      &[
+        (0, "data: first\n\n"),
+        (100, "event: update\ndata: second\n\n"),
+      ],
+    )
+    .events("GET localhost /bare", &[(0, "data: bare\n\n")])
+    .build("control");
+  Server::new(cert.server_config(), response_set)
+}
+
+async fn subscribe(client: &SendRequest<Bytes>, path: &str) -> (http::HeaderMap, RecvStream) {
+  let request = Request::get(format!("https://{}{}", HOSTNAME, path))
+    .header("accept", "text/event-stream")
+    .body(())
+    .unwrap();
+  let (response, _) = client
+    .clone()
+    .ready()
+    .await
+    .unwrap()
+    .send_request(request, true)
+    .unwrap();
+  let (parts, body) = response.await.unwrap().into_parts();
+  assert_eq!(parts.status, 200);
+  (parts.headers, body)
+}
+
+async fn next_event(body: &mut RecvStream) -> Option<Bytes> {
+  let chunk = poll_fn(|cx| body.poll_data(cx)).await?.unwrap();
+  let _ = body.flow_control().release_capacity(chunk.len());
+  Some(chunk)
+}
+
+#[tokio::test]
+async fn test_events_are_replayed_with_delays() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let start = Instant::now();
+  let (headers, mut body) = subscribe(&client, "/events").await;
+  assert_eq!(headers["x-recorded"], "1");
+  assert_eq!(next_event(&mut body).await.unwrap(), "data: first\n\n");
+  assert_eq!(
+    next_event(&mut body).await.unwrap(),
+    "event: update\ndata: second\n\n"
+  );
+  assert!(start.elapsed() >= Duration::from_millis(100));
+
+  // the stream is kept open after the last event
+  assert!(timeout(Duration::from_millis(200), next_event(&mut body))
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_event_stream_closed_after_last_event() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert).with_close_event_streams(true)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  // without a recorded response the events get a bare event stream response
+  let (headers, mut body) = subscribe(&client, "/bare").await;
+  assert_eq!(headers["content-type"], "text/event-stream");
+  assert_eq!(next_event(&mut body).await.unwrap(), "data: bare\n\n");
+  assert!(timeout(Duration::from_secs(1), next_event(&mut body))
+    .await
+    .unwrap()
+    .is_none());
+}
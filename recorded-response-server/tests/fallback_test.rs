@@ -0,0 +1,197 @@
+mod common;
+
+use common::{get, h2_connect, send, serve_one, serve_shared, ArchiveBuilder, TestCert, HOSTNAME};
+use http::{Request, StatusCode};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracerbench_recorded_response_server::{
+  Config, FallbackResponse, FallbackRule, FallbackSettings, Fallbacks, Route, Server, Upstream,
+};
+
+fn archive() -> ArchiveBuilder {
+  let mut archive = ArchiveBuilder::new();
+  archive.response(
+    "GET localhost /manifest.json",
+    200,
+    &[("content-type", "application/manifest+json")],
+    Some(b"{\"name\":\"app\"}"),
+  );
+  archive
+}
+
+fn server(cert: &TestCert, fallbacks: Fallbacks) -> Server {
+  Server::new(cert.server_config(), archive().build("control")).with_fallbacks(fallbacks)
+}
+
+fn fallbacks() -> Fallbacks {
+  let beacon = FallbackSettings {
+    key: "POST * /beacon*".to_owned(),
+    status: Some(204),
+    ..FallbackSettings::default()
+  };
+  let telemetry = FallbackSettings {
+    key: "GET * /api/telemetry*".to_owned(),
+    headers: Some(
+      vec![("Content-Type".to_owned(), "application/json".to_owned())]
+        .into_iter()
+        .collect::<HashMap<_, _>>(),
+    ),
+    body: Some("{}".to_owned()),
+    ..FallbackSettings::default()
+  };
+  let manifest = FallbackSettings {
+    key: "GET * /manifest-v*.json".to_owned(),
+    response: Some("GET localhost /manifest.json".to_owned()),
+    ..FallbackSettings::default()
+  };
+  let not_found = FallbackSettings {
+    key: "GET * /api/*".to_owned(),
+    status: Some(404),
+    body: Some("{\"error\":\"not found\"}".to_owned()),
+    ..FallbackSettings::default()
+  };
+  Fallbacks::default()
+    .with_rule(beacon.rule().unwrap())
+    .with_rule(not_found.rule().unwrap())
+    .with_set_rule("control", telemetry.rule().unwrap())
+    .with_set_rule("control", manifest.rule().unwrap())
+    .with_set_rule(
+      "other",
+      FallbackRule::new(
+        "*",
+        FallbackResponse::Recorded("GET localhost /manifest.json".to_owned()),
+      ),
+    )
+}
+
+#[tokio::test]
+async fn test_inline_fallbacks() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert, fallbacks())).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let request = Request::post(format!("https://{}/beacon?e=load", HOSTNAME))
+    .body(())
+    .unwrap();
+  let (status, body) = send(&client, request).await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  assert!(body.is_empty());
+
+  // the rules of the set come first
+  let (status, body) = get(&client, "/api/telemetry?v=2").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "{}");
+
+  let (status, body) = get(&client, "/api/users").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(body, "{\"error\":\"not found\"}");
+}
+
+#[tokio::test]
+async fn test_recorded_fallback() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert, fallbacks())).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, body) = get(&client, "/manifest-v2.json").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "{\"name\":\"app\"}");
+}
+
+#[tokio::test]
+async fn test_unmatched_miss_is_not_found() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert, fallbacks())).await;
+  let client = h2_connect(&cert, addr).await;
+
+  // the rules of other sets do not apply
+  let (status, body) = get(&client, "/index.html").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert!(body.is_empty());
+}
+
+#[tokio::test]
+async fn test_fallbacks_come_before_upstream() {
+  let cert = TestCert::new();
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let upstream_addr = listener.local_addr().unwrap().to_string();
+  let upstream = tokio::spawn(async move {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+      head.push(socket.read_u8().await.unwrap());
+    }
+    socket
+      .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 8\r\nconnection: close\r\n\r\nupstream")
+      .await
+      .unwrap();
+    socket.shutdown().await.unwrap();
+    String::from_utf8(head).unwrap()
+  });
+  let server = Server::from_route(Route {
+    tls_config: cert.server_config(),
+    response_set: archive().build("control"),
+    destinations: Vec::new(),
+    upstream: Some(Arc::new(Upstream::new(upstream_addr))),
+  })
+  .with_fallbacks(fallbacks());
+  let (addr, _handle) = serve_one(server).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, body) = get(&client, "/api/telemetry?v=2").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "{}");
+  let (status, body) = get(&client, "/manifest-v2.json").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "{\"name\":\"app\"}");
+
+  // only misses without a matching rule are forwarded
+  let (status, body) = get(&client, "/index.html").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "upstream");
+  let head = upstream.await.unwrap();
+  assert!(head.starts_with("GET /index.html "), "{}", head);
+}
+
+#[tokio::test]
+async fn test_shared_listener_applies_fallbacks() {
+  let cert = TestCert::new();
+  let mut config = Config::new(
+    String::new(),
+    cert.server_config(),
+    archive().build_sets("control"),
+  );
+  config.shared_port = Some(0);
+  config.shared_default_set = Some("control".to_owned());
+  config.fallbacks = fallbacks();
+  let addr = serve_shared(config).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, body) = get(&client, "/api/users").await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(body, "{\"error\":\"not found\"}");
+  let (status, body) = get(&client, "/manifest-v2.json").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "{\"name\":\"app\"}");
+}
+
+#[test]
+fn test_invalid_fallback_settings() {
+  let both = FallbackSettings {
+    key: "GET *".to_owned(),
+    status: Some(204),
+    response: Some("GET localhost /".to_owned()),
+    ..FallbackSettings::default()
+  };
+  assert!(both.rule().is_err());
+  let no_key = FallbackSettings::default();
+  assert!(no_key.rule().is_err());
+  let bad_status = FallbackSettings {
+    key: "GET *".to_owned(),
+    status: Some(1000),
+    ..FallbackSettings::default()
+  };
+  assert!(bad_status.rule().is_err());
+}
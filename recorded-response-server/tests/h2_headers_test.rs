@@ -0,0 +1,70 @@
+mod common;
+
+use common::{get, h2_connect, serve_one, ArchiveBuilder, TestCert};
+use http::StatusCode;
+use std::sync::Arc;
+use tracerbench_recorded_response_server::Server;
+
+fn archive() -> ArchiveBuilder {
+  let mut archive = ArchiveBuilder::new();
+  archive
+    .response(
+      "GET localhost /",
+      200,
+      &[
+        ("content-type", "text/html"),
+        ("upgrade", "h2c"),
+        ("proxy-connection", "keep-alive"),
+      ],
+      Some(b"ok"),
+    )
+    .response(
+      "GET localhost /te",
+      200,
+      &[("te", "gzip"), ("x-kept", "1")],
+      Some(b"ok"),
+    )
+    .response("GET localhost /trailers", 200, &[("te", "trailers")], None);
+  archive
+}
+
+#[test]
+fn test_invalid_h2_headers_are_reported() {
+  let response_set = archive().build("control");
+  let invalid: Vec<(&str, &str)> = response_set
+    .invalid_h2_headers()
+    .into_iter()
+    .map(|(key, name)| (key, name.as_str()))
+    .collect();
+  assert_eq!(
+    invalid,
+    [
+      ("GET localhost /", "proxy-connection"),
+      ("GET localhost /", "upgrade"),
+      ("GET localhost /te", "te"),
+    ]
+  );
+}
+
+#[tokio::test]
+async fn test_stripped_responses_are_served() {
+  let mut response_set = archive().build("control");
+  Arc::get_mut(&mut response_set)
+    .unwrap()
+    .strip_invalid_h2_headers();
+  assert!(response_set.invalid_h2_headers().is_empty());
+  let headers = response_set
+    .get_response("GET localhost /te")
+    .unwrap()
+    .headers();
+  assert_eq!(headers["x-kept"], "1");
+
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(Server::new(cert.server_config(), response_set)).await;
+  let client = h2_connect(&cert, addr).await;
+  let (status, body) = get(&client, "/").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "ok");
+  let (status, _) = get(&client, "/te").await;
+  assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{h2_connect, send_with, serve_one, ArchiveBuilder, TestCert};
+use http::{Method, StatusCode};
+use tracerbench_recorded_response_server::Server;
+
+const BODY: &[u8] = b"<!doctype html><title>app</title>";
+
+fn server(cert: &TestCert) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response(
+      "GET localhost /",
+      200,
+      &[
+        ("content-type", "text/html"),
+        ("transfer-encoding", "chunked"),
+        ("connection", "keep-alive"),
+        ("keep-alive", "timeout=5"),
+      ],
+      Some(BODY),
+    )
+    .response(
+      "GET localhost /stale",
+      200,
+      &[("content-type", "text/html"), ("content-length", "4")],
+      Some(BODY),
+    )
+    .response("GET localhost /empty", 200, &[], None)
+    .response("GET localhost /blank", 200, &[], Some(b""))
+    .response("GET localhost /no-content", 204, &[], None)
+    .build("control");
+  Server::new(cert.server_config(), response_set)
+}
+
+#[tokio::test]
+async fn test_connection_headers_are_dropped() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) = send_with(&client, Method::GET, "/", &[]).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, BODY);
+  assert_eq!(headers["content-type"], "text/html");
+  assert!(headers.get("transfer-encoding").is_none());
+  assert!(headers.get("connection").is_none());
+  assert!(headers.get("keep-alive").is_none());
+}
+
+#[tokio::test]
+async fn test_head_has_body_length() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) = send_with(&client, Method::HEAD, "/", &[]).await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(body.is_empty());
+  assert_eq!(headers["content-type"], "text/html");
+  assert_eq!(headers["content-length"], BODY.len().to_string().as_str());
+  assert!(headers.get("transfer-encoding").is_none());
+
+  // a recorded length that does not match the body is corrected
+  let (_, headers, _) = send_with(&client, Method::HEAD, "/stale", &[]).await;
+  assert_eq!(headers["content-length"], BODY.len().to_string().as_str());
+}
+
+#[tokio::test]
+async fn test_head_without_body_has_zero_length() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  for path in &["/empty", "/blank"] {
+    let (status, headers, body) = send_with(&client, Method::HEAD, path, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.is_empty());
+    assert_eq!(headers["content-length"], "0");
+  }
+
+  let (status, headers, _) = send_with(&client, Method::HEAD, "/no-content", &[]).await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  assert!(headers.get("content-length").is_none());
+}
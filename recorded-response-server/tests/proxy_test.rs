@@ -0,0 +1,41 @@
+mod common;
+
+use common::{serve_shared, ArchiveBuilder, TestCert};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracerbench_recorded_response_server::{Config, ProxyProtocol};
+
+async fn http_connect(addr: std::net::SocketAddr, target: &str) -> String {
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socket
+    .write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target).as_bytes())
+    .await
+    .unwrap();
+  let mut head = Vec::new();
+  while !head.ends_with(b"\r\n\r\n") {
+    head.push(socket.read_u8().await.unwrap());
+  }
+  String::from_utf8(head).unwrap()
+}
+
+#[tokio::test]
+async fn test_http_connect_refuses_unrouted_destination() {
+  let cert = TestCert::new();
+  let response_sets = ArchiveBuilder::new()
+    .response("GET localhost /", 200, &[], Some(b"ok"))
+    .build_sets("control");
+  let mut config = Config::new(String::new(), cert.server_config(), response_sets);
+  config.shared_port = Some(0);
+  config.proxy_protocol = ProxyProtocol::HttpConnect;
+  config
+    .set_destinations
+    .insert("control".to_owned(), vec!["*:443".parse().unwrap()]);
+  let addr = serve_shared(config).await;
+
+  let head = http_connect(addr, "localhost:443").await;
+  assert!(head.starts_with("HTTP/1.1 200 "), "{}", head);
+
+  // refused before the 200, no set routes the destination
+  let head = http_connect(addr, "localhost:8443").await;
+  assert!(head.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{}", head);
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use bytes::Bytes;
+use common::{
+  read_body, serve_one, serve_shared, socks_connect, tls_connect, ArchiveBuilder, TestCert,
+  HOSTNAME,
+};
+use futures::future::poll_fn;
+use h2::client::SendRequest;
+use http::{Method, Request, StatusCode};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracerbench_recorded_response_server::{Config, PushRules, Server};
+
+fn archive() -> ArchiveBuilder {
+  let mut archive = ArchiveBuilder::new();
+  archive
+    .response(
+      "GET localhost /",
+      200,
+      &[("content-type", "text/html")],
+      Some(b"<script src=/app.js></script>"),
+    )
+    .response(
+      "GET localhost /app.js",
+      200,
+      &[("content-type", "text/javascript")],
+      Some(b"app()"),
+    )
+    .response(
+      "GET localhost /app.css",
+      200,
+      &[("content-type", "text/css")],
+      Some(b"body{}"),
+    );
+  archive
+}
+
+fn push_rules() -> PushRules {
+  PushRules::default()
+    .with_rule(
+      "control",
+      "GET localhost /",
+      vec![
+        "/app.js".to_owned(),
+        "/missing.js".to_owned(),
+        format!("https://{}/app.css", HOSTNAME),
+      ],
+    )
+    .with_rule(
+      "other",
+      "GET localhost /app.js",
+      vec!["/app.css".to_owned()],
+    )
+}
+
+fn server(cert: &TestCert) -> Server {
+  Server::new(cert.server_config(), archive().build("control")).with_push_rules(push_rules())
+}
+
+/// socks, TLS and h2 handshakes with the server at addr. Like browsers
+/// the client advertises a stream limit, without one the server does not
+/// open pushed streams.
+async fn connect(cert: &TestCert, addr: SocketAddr, enable_push: bool) -> SendRequest<Bytes> {
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+  let tls = tls_connect(cert, socket).await;
+  let (client, connection) = h2::client::Builder::new()
+    .enable_push(enable_push)
+    .max_concurrent_streams(100)
+    .handshake(tls)
+    .await
+    .unwrap();
+  tokio::spawn(connection);
+  client
+}
+
+/// The response body and the pushed (path, body) pairs.
+async fn request_with_pushes(
+  client: &SendRequest<Bytes>,
+  method: Method,
+  path: &str,
+) -> (StatusCode, Bytes, Vec<(String, Bytes)>) {
+  let request = Request::builder()
+    .method(method)
+    .uri(format!("https://{}{}", HOSTNAME, path))
+    .body(())
+    .unwrap();
+  let (mut response, _) = client
+    .clone()
+    .ready()
+    .await
+    .unwrap()
+    .send_request(request, true)
+    .unwrap();
+  let mut push_promises = response.push_promises();
+  let (parts, body) = response.await.unwrap().into_parts();
+  let body = read_body(body).await;
+  let mut pushed = Vec::new();
+  while let Ok(Some(promise)) = timeout(
+    Duration::from_millis(200),
+    poll_fn(|cx| push_promises.poll_push_promise(cx)),
+  )
+  .await
+  {
+    let (request, response) = promise.unwrap().into_parts();
+    let response = response.await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    pushed.push((
+      request.uri().path().to_owned(),
+      read_body(response.into_body()).await,
+    ));
+  }
+  (parts.status, body, pushed)
+}
+
+#[tokio::test]
+async fn test_push_with_document() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = connect(&cert, addr, true).await;
+
+  let (status, body, pushed) = request_with_pushes(&client, Method::GET, "/").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "<script src=/app.js></script>");
+  // resources missing from the set are not pushed
+  assert_eq!(
+    pushed,
+    [
+      ("/app.js".to_owned(), Bytes::from("app()")),
+      ("/app.css".to_owned(), Bytes::from("body{}")),
+    ]
+  );
+}
+
+#[tokio::test]
+async fn test_push_alongside_document_body() {
+  let cert = TestCert::new();
+  // larger than the initial flow control window
+  let document = vec![b'x'; 256 * 1024];
+  let response_set = archive()
+    .response(
+      "GET localhost /large",
+      200,
+      &[("content-type", "text/html")],
+      Some(&document),
+    )
+    .build("control");
+  let push_rules = PushRules::default().with_rule(
+    "control",
+    "GET localhost /large",
+    vec!["/app.js".to_owned()],
+  );
+  let server = Server::new(cert.server_config(), response_set).with_push_rules(push_rules);
+  let (addr, _handle) = serve_one(server).await;
+  let client = connect(&cert, addr, true).await;
+
+  let request = Request::get(format!("https://{}/large", HOSTNAME))
+    .body(())
+    .unwrap();
+  let (mut response, _) = client
+    .clone()
+    .ready()
+    .await
+    .unwrap()
+    .send_request(request, true)
+    .unwrap();
+  let mut push_promises = response.push_promises();
+  let (_, body) = response.await.unwrap().into_parts();
+  // the pushed response starts while the document body waits for window
+  let promise = poll_fn(|cx| push_promises.poll_push_promise(cx))
+    .await
+    .unwrap()
+    .unwrap();
+  let pushed = timeout(Duration::from_secs(5), promise.into_parts().1)
+    .await
+    .expect("pushed response is sent before the document body")
+    .unwrap();
+  assert_eq!(pushed.status(), StatusCode::OK);
+  // the document holds the connection window until it is read
+  assert_eq!(read_body(body).await.len(), document.len());
+  assert_eq!(read_body(pushed.into_body()).await, "app()");
+}
+
+#[tokio::test]
+async fn test_shared_listener_pushes() {
+  let cert = TestCert::new();
+  let mut config = Config::new(
+    String::new(),
+    cert.server_config(),
+    archive().build_sets("control"),
+  );
+  config.shared_port = Some(0);
+  config.shared_default_set = Some("control".to_owned());
+  config.push_rules = push_rules();
+  let addr = serve_shared(config).await;
+  let client = connect(&cert, addr, true).await;
+
+  let (status, _, pushed) = request_with_pushes(&client, Method::GET, "/").await;
+  assert_eq!(status, StatusCode::OK);
+  let paths: Vec<&str> = pushed.iter().map(|(path, _)| path.as_str()).collect();
+  assert_eq!(paths, ["/app.js", "/app.css"]);
+}
+
+#[tokio::test]
+async fn test_no_push_without_rule() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = connect(&cert, addr, true).await;
+
+  // the rule is for another set
+  let (status, _, pushed) = request_with_pushes(&client, Method::GET, "/app.js").await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(pushed.is_empty());
+
+  let (status, _, pushed) = request_with_pushes(&client, Method::HEAD, "/").await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(pushed.is_empty());
+}
+
+#[tokio::test]
+async fn test_client_with_push_disabled() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = connect(&cert, addr, false).await;
+
+  let (status, body, pushed) = request_with_pushes(&client, Method::GET, "/").await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, "<script src=/app.js></script>");
+  assert!(pushed.is_empty());
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::{h2_connect, send_with, serve_one, ArchiveBuilder, TestCert};
+use http::{Method, StatusCode};
+use tracerbench_recorded_response_server::Server;
+
+const BODY: &[u8] = b"0123456789abcdefghij";
+
+fn server(cert: &TestCert) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response(
+      "GET localhost /ranges",
+      200,
+      &[
+        ("accept-ranges", "bytes"),
+        ("content-type", "text/plain"),
+        ("etag", "\"v1\""),
+      ],
+      Some(BODY),
+    )
+    .response(
+      "GET localhost /plain",
+      200,
+      &[("content-type", "text/plain")],
+      Some(BODY),
+    )
+    .build("control");
+  Server::new(cert.server_config(), response_set)
+}
+
+#[tokio::test]
+async fn test_single_range() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) =
+    send_with(&client, Method::GET, "/ranges", &[("range", "bytes=2-5")]).await;
+  assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+  assert_eq!(headers["content-range"], "bytes 2-5/20");
+  assert_eq!(headers["content-length"], "4");
+  assert_eq!(headers["content-type"], "text/plain");
+  assert_eq!(body, "2345");
+
+  let (status, headers, body) =
+    send_with(&client, Method::GET, "/ranges", &[("range", "bytes=-3")]).await;
+  assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+  assert_eq!(headers["content-range"], "bytes 17-19/20");
+  assert_eq!(body, "hij");
+}
+
+#[tokio::test]
+async fn test_multiple_ranges() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) = send_with(
+    &client,
+    Method::GET,
+    "/ranges",
+    &[("range", "bytes=0-1,10-")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+  let content_type = headers["content-type"].to_str().unwrap();
+  let boundary = content_type
+    .strip_prefix("multipart/byteranges; boundary=")
+    .unwrap();
+  assert!(headers.get("content-range").is_none());
+  assert_eq!(headers["content-length"], body.len().to_string().as_str());
+  let expected = format!(
+    "--{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 0-1/20\r\n\r\n01\r\n\
+     --{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 10-19/20\r\n\r\nabcdefghij\r\n\
+     --{b}--\r\n",
+    b = boundary
+  );
+  assert_eq!(body, expected);
+}
+
+#[tokio::test]
+async fn test_unsatisfiable_range() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) =
+    send_with(&client, Method::GET, "/ranges", &[("range", "bytes=20-")]).await;
+  assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+  assert_eq!(headers["content-range"], "bytes */20");
+  assert!(body.is_empty());
+}
+
+#[tokio::test]
+async fn test_range_needs_accept_ranges() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, _, body) =
+    send_with(&client, Method::GET, "/plain", &[("range", "bytes=2-5")]).await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, BODY);
+
+  // a stale If-Range gets the whole body
+  let (status, _, body) = send_with(
+    &client,
+    Method::GET,
+    "/ranges",
+    &[("range", "bytes=2-5"), ("if-range", "\"v0\"")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, BODY);
+
+  let (status, _, body) = send_with(
+    &client,
+    Method::GET,
+    "/ranges",
+    &[("range", "bytes=2-5"), ("if-range", "\"v1\"")],
+  )
+  .await;
+  assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+  assert_eq!(body, "2345");
+}
+
+#[tokio::test]
+async fn test_range_requests_for_every_response() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert).with_range_requests(true)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, body) =
+    send_with(&client, Method::GET, "/plain", &[("range", "bytes=2-5")]).await;
+  assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+  assert_eq!(headers["content-range"], "bytes 2-5/20");
+  assert_eq!(body, "2345");
+}
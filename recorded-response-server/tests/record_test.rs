@@ -0,0 +1,169 @@
+mod common;
+
+use common::{
+  get, h2_connect, read_archive, send, serve_one, socks_connect_to, tls_connect, TestCert, HOSTNAME,
+};
+use http::Request;
+use serde_cbor::Value;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracerbench_recorded_response_server::{NewRecordings, Route, Server, Upstream};
+use tracerbench_recorded_response_set::RecordedResponseSet;
+use tracerbench_request_key::RequestKey;
+
+/// An HTTPS HTTP/1.1 destination that answers one request, returns its
+/// port and the request head it read.
+async fn destination_once(cert: &TestCert) -> (u16, oneshot::Receiver<String>) {
+  let mut config = ServerConfig::new(NoClientAuth::new());
+  config
+    .set_single_cert(vec![cert.cert.clone()], cert.key.clone())
+    .unwrap();
+  let acceptor = TlsAcceptor::from(Arc::new(config));
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let (sender, receiver) = oneshot::channel();
+  tokio::spawn(async move {
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut socket = acceptor.accept(socket).await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+      head.push(socket.read_u8().await.unwrap());
+    }
+    socket
+      .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 4\r\n\r\npage")
+      .await
+      .unwrap();
+    socket.shutdown().await.unwrap();
+    let _ = sender.send(String::from_utf8(head).unwrap());
+  });
+  (port, receiver)
+}
+
+/// The program that keys requests as "METHOD authority path".
+fn request_key() -> RequestKey {
+  let program = Value::Array(vec![Value::Array(Vec::new()), Value::Bytes(Vec::new())]);
+  serde_cbor::from_slice(&serde_cbor::to_vec(&program).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_record_then_replay() {
+  let cert = TestCert::new();
+  let (port, _) = destination_once(&cert).await;
+  let path = std::env::temp_dir().join(format!("recording-{}.cbor", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+
+  let upstream = Upstream::destination_with_client_config(cert.client_config())
+    .with_new_recordings(Arc::new(NewRecordings::new(&path)));
+  let recorder = Server::from_route(Route {
+    tls_config: cert.server_config(),
+    response_set: Arc::new(RecordedResponseSet::new(
+      9000,
+      "recording",
+      "",
+      request_key(),
+    )),
+    destinations: Vec::new(),
+    upstream: Some(Arc::new(upstream)),
+  });
+  let (addr, _handle) = serve_one(recorder).await;
+
+  // the client dials the destination through the recorder
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect_to(&mut socket, port).await;
+  let tls = tls_connect(&cert, socket).await;
+  let (client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+  let url = format!("https://{}:{}/page", HOSTNAME, port);
+  let (status, body) = send(&client, Request::get(&url).body(()).unwrap()).await;
+  assert_eq!(status, 200);
+  assert_eq!(body, "page");
+
+  // the archive is written in the background
+  let mut sets = read_archive(&path).await;
+  std::fs::remove_file(&path).unwrap();
+
+  let set = sets.remove(0);
+  let key = format!("GET {}:{} /page", HOSTNAME, port);
+  assert_eq!(set.name(), "recording");
+  assert_eq!(set.socks_port(), 9000);
+  assert_eq!(set.entry_key(), key);
+  assert!(set.get_response(&key).is_some());
+
+  // the destination is gone, the recording is replayed
+  let (addr, _handle) = serve_one(Server::new(cert.server_config(), set)).await;
+  let client = h2_connect(&cert, addr).await;
+  let (status, body) = get(&client, &format!(":{}/page", port)).await;
+  assert_eq!(status, 200);
+  assert_eq!(body, "page");
+}
+
+#[tokio::test]
+async fn test_conditional_headers_are_not_recorded() {
+  let cert = TestCert::new();
+  let (port, destination_head) = destination_once(&cert).await;
+  let path = std::env::temp_dir().join(format!("conditional-{}.cbor", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+
+  let upstream = Upstream::destination_with_client_config(cert.client_config())
+    .with_new_recordings(Arc::new(NewRecordings::new(&path)));
+  let recorder = Server::from_route(Route {
+    tls_config: cert.server_config(),
+    response_set: Arc::new(RecordedResponseSet::new(0, "recording", "", request_key())),
+    destinations: Vec::new(),
+    upstream: Some(Arc::new(upstream)),
+  });
+  let (addr, _handle) = serve_one(recorder).await;
+
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect_to(&mut socket, port).await;
+  let tls = tls_connect(&cert, socket).await;
+  let (client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+  // a warm browser cache revalidates and resumes
+  let request = Request::get(format!("https://{}:{}/page", HOSTNAME, port))
+    .header("if-none-match", "\"v1\"")
+    .header("if-modified-since", "Mon, 13 Jan 2020 00:00:00 GMT")
+    .header("range", "bytes=2-")
+    .header("if-range", "\"v1\"")
+    .header("accept", "text/html")
+    .body(())
+    .unwrap();
+  let (status, body) = send(&client, request).await;
+  assert_eq!(status, 200);
+  assert_eq!(body, "page");
+
+  // the destination sends the full response to record
+  let head = destination_head.await.unwrap();
+  assert!(head.contains("accept: text/html\r\n"));
+  for name in &["if-none-match", "if-modified-since", "range", "if-range"] {
+    assert!(!head.contains(&format!("{}:", name)), "{} forwarded", name);
+  }
+  let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn test_destination_must_be_trusted() {
+  let cert = TestCert::new();
+  let (port, _) = destination_once(&cert).await;
+  // the test certificate is not signed by a web PKI root
+  let recorder = Server::from_route(Route {
+    tls_config: cert.server_config(),
+    response_set: Arc::new(RecordedResponseSet::new(0, "recording", "", request_key())),
+    destinations: Vec::new(),
+    upstream: Some(Arc::new(Upstream::destination())),
+  });
+  let (addr, _handle) = serve_one(recorder).await;
+
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect_to(&mut socket, port).await;
+  let tls = tls_connect(&cert, socket).await;
+  let (client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+  let url = format!("https://{}:{}/page", HOSTNAME, port);
+  let (status, _) = send(&client, Request::get(&url).body(()).unwrap()).await;
+  assert_eq!(status, 502);
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{read_body, serve_one, socks_connect, tls_connect, ArchiveBuilder, TestCert};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracerbench_recorded_response_server::Server;
+use tracerbench_recorded_response_server::ServerError;
+use tracerbench_recorded_response_server::Timeouts;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+fn server(cert: &TestCert) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response(
+      "GET localhost /",
+      200,
+      &[("content-type", "text/plain")],
+      Some(b"hello"),
+    )
+    .events("GET localhost /slow-events", &[(250, "data: done\n\n")])
+    .build("control");
+  Server::new(cert.server_config(), response_set).with_timeouts(Timeouts {
+    proxy_handshake: Some(TIMEOUT),
+    tls_handshake: Some(TIMEOUT),
+    h2_preface: Some(TIMEOUT),
+    idle: Some(TIMEOUT),
+    ..Timeouts::default()
+  })
+}
+
+#[tokio::test]
+async fn test_proxy_handshake_timeout() {
+  let cert = TestCert::new();
+  let (addr, handle) = serve_one(server(&cert)).await;
+
+  // connect and never send the socks greeting
+  let _socket = TcpStream::connect(addr).await.unwrap();
+
+  let result = handle.await.unwrap();
+  assert!(matches!(result, Err(ServerError::ProxyTimeout(TIMEOUT))));
+}
+
+#[tokio::test]
+async fn test_tls_handshake_timeout() {
+  let cert = TestCert::new();
+  let (addr, handle) = serve_one(server(&cert)).await;
+
+  // complete the socks handshake and never send a ClientHello
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+
+  let result = handle.await.unwrap();
+  assert!(matches!(result, Err(ServerError::TlsTimeout(TIMEOUT))));
+}
+
+#[tokio::test]
+async fn test_h2_preface_timeout() {
+  let cert = TestCert::new();
+  let (addr, handle) = serve_one(server(&cert)).await;
+
+  // complete the TLS handshake and never send the connection preface
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+  let _tls = tls_connect(&cert, socket).await;
+
+  let result = handle.await.unwrap();
+  assert!(matches!(result, Err(ServerError::PrefaceTimeout(TIMEOUT))));
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+  let cert = TestCert::new();
+  let (addr, handle) = serve_one(server(&cert)).await;
+
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+  let tls = tls_connect(&cert, socket).await;
+  let (_client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+
+  let result = handle.await.unwrap();
+  assert!(matches!(result, Err(ServerError::IdleTimeout(TIMEOUT))));
+}
+
+#[tokio::test]
+async fn test_open_stream_is_not_idle() {
+  let cert = TestCert::new();
+  let (addr, handle) = serve_one(server(&cert)).await;
+
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+  let tls = tls_connect(&cert, socket).await;
+  let (client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+
+  // server-sent events are kept open until the client resets them
+  let request = http::Request::get("https://localhost/events")
+    .header("accept", "text/event-stream")
+    .body(())
+    .unwrap();
+  let (response, _send) = client
+    .ready()
+    .await
+    .unwrap()
+    .send_request(request, true)
+    .unwrap();
+  let response = response.await.unwrap();
+  assert_eq!(response.status(), 200);
+
+  // several idle periods pass without the connection closing
+  let mut handle = handle;
+  assert!(timeout(TIMEOUT * 3, &mut handle).await.is_err());
+}
+
+#[tokio::test]
+async fn test_idle_period_starts_when_last_stream_closes() {
+  let cert = TestCert::new();
+  let server = server(&cert).with_close_event_streams(true);
+  let (addr, handle) = serve_one(server).await;
+
+  let mut socket = TcpStream::connect(addr).await.unwrap();
+  socks_connect(&mut socket).await;
+  let tls = tls_connect(&cert, socket).await;
+  let (client, connection) = h2::client::handshake(tls).await.unwrap();
+  tokio::spawn(connection);
+
+  // the stream stays open for two and a half idle periods
+  let request = http::Request::get("https://localhost/slow-events")
+    .header("accept", "text/event-stream")
+    .body(())
+    .unwrap();
+  let (response, _) = client
+    .clone()
+    .ready()
+    .await
+    .unwrap()
+    .send_request(request, true)
+    .unwrap();
+  let response = response.await.unwrap();
+  assert_eq!(read_body(response.into_body()).await, "data: done\n\n");
+
+  // a full idle period must pass after the stream closes
+  let mut handle = handle;
+  assert!(timeout(TIMEOUT * 3 / 4, &mut handle).await.is_err());
+  let result = timeout(TIMEOUT, handle).await.unwrap().unwrap();
+  assert!(matches!(result, Err(ServerError::IdleTimeout(TIMEOUT))));
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::{get, h2_connect, read_archive, serve_one, ArchiveBuilder, TestCert};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tracerbench_recorded_response_server::{NewRecordings, Route, Server, Upstream};
+
+/// An HTTP/1.1 upstream that answers one request with a chunked
+/// response, returns its address and the request head it read.
+async fn upstream_once() -> (String, oneshot::Receiver<String>) {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap().to_string();
+  let (sender, receiver) = oneshot::channel();
+  tokio::spawn(async move {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+      head.push(socket.read_u8().await.unwrap());
+    }
+    socket
+      .write_all(
+        b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n5\r\nhello\r\n9\r\n upstream\r\n0\r\n\r\n",
+      )
+      .await
+      .unwrap();
+    socket.shutdown().await.unwrap();
+    let _ = sender.send(String::from_utf8(head).unwrap());
+  });
+  (addr, receiver)
+}
+
+fn server(cert: &TestCert, upstream: Upstream) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response("GET localhost /", 200, &[], Some(b"recorded"))
+    .build("control");
+  Server::from_route(Route {
+    tls_config: cert.server_config(),
+    response_set,
+    destinations: Vec::new(),
+    upstream: Some(Arc::new(upstream)),
+  })
+}
+
+fn temp_path(name: &str) -> PathBuf {
+  std::env::temp_dir().join(format!("{}-{}.cbor", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn test_miss_is_forwarded_and_recorded() {
+  let cert = TestCert::new();
+  let (upstream_addr, upstream_head) = upstream_once().await;
+  let path = temp_path("new-recordings");
+  let _ = std::fs::remove_file(&path);
+  let upstream =
+    Upstream::new(upstream_addr).with_new_recordings(Arc::new(NewRecordings::new(&path)));
+  let (addr, _handle) = serve_one(server(&cert, upstream)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  // recorded responses are still served from the set
+  let (status, body) = get(&client, "/").await;
+  assert_eq!(status, 200);
+  assert_eq!(body, "recorded");
+
+  let (status, body) = get(&client, "/missing?q=1").await;
+  assert_eq!(status, 200);
+  assert_eq!(body, "hello upstream");
+
+  let head = upstream_head.await.unwrap();
+  assert!(head.starts_with("GET /missing?q=1 HTTP/1.1\r\n"));
+  assert!(head.contains("host: localhost\r\n"));
+  assert!(head.contains("connection: close\r\n"));
+
+  // the archive is written in the background
+  let sets = read_archive(&path).await;
+  std::fs::remove_file(&path).unwrap();
+
+  assert_eq!(sets.len(), 1);
+  assert_eq!(sets[0].name(), "control");
+  let recorded = sets[0].get_response("GET localhost /missing?q=1").unwrap();
+  assert_eq!(recorded.status_code(), 200);
+  assert_eq!(recorded.headers()["content-type"], "text/plain");
+  assert!(recorded.headers().get("transfer-encoding").is_none());
+  assert_eq!(recorded.body().unwrap().as_ref(), b"hello upstream");
+}
+
+#[tokio::test]
+async fn test_stalled_upstream_is_gateway_timeout() {
+  let cert = TestCert::new();
+  // accepts and reads the request but never responds
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let upstream_addr = listener.local_addr().unwrap().to_string();
+  tokio::spawn(async move {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut buf = Vec::new();
+    let _ = socket.read_to_end(&mut buf).await;
+  });
+
+  let upstream = Upstream::new(upstream_addr).with_timeout(Some(Duration::from_millis(200)));
+  let (addr, _handle) = serve_one(server(&cert, upstream)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, _) = get(&client, "/missing").await;
+  assert_eq!(status, 504);
+}
+
+#[tokio::test]
+async fn test_unreachable_upstream_is_bad_gateway() {
+  let cert = TestCert::new();
+  // a port nothing listens on
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let upstream_addr = listener.local_addr().unwrap().to_string();
+  drop(listener);
+
+  let (addr, _handle) = serve_one(server(&cert, Upstream::new(upstream_addr))).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, _) = get(&client, "/missing").await;
+  assert_eq!(status, 502);
+}
+
+#[test]
+fn test_nothing_is_written_without_recordings() {
+  let path = temp_path("no-recordings");
+  let _ = std::fs::remove_file(&path);
+  assert!(!NewRecordings::new(&path).flush().unwrap());
+  assert!(!path.exists());
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use common::{h2_connect, serve_one, ArchiveBuilder, TestCert, HOSTNAME};
+use futures::future::poll_fn;
+use h2::client::SendRequest;
+use h2::ext::Protocol;
+use h2::{Reason, RecvStream, SendStream};
+use http::{Method, Request, StatusCode};
+use serde_cbor::Value;
+use std::time::{Duration, Instant};
+use tracerbench_recorded_response_server::Server;
+
+fn step(kind: &str, delay: u64, message: Value) -> Value {
+  Value::Array(vec![
+    Value::Text(kind.to_owned()),
+    Value::Integer(delay.into()),
+    message,
+  ])
+}
+
+fn server(cert: &TestCert) -> Server {
+  let response_set = ArchiveBuilder::new()
+    .response(
+      "GET localhost /socket",
+      101,
+      &[("sec-websocket-protocol", "chat")],
+      None,
+    )
+    .web_socket(
+      "GET localhost /socket",
+      vec![
+        step("send", 0, Value::Text("hello".to_owned())),
+        step("receive", 0, Value::Text("subscribe".to_owned())),
+        step("send", 100, Value::Bytes(vec![1, 2, 3])),
+        step("receive", 0, Value::Null),
+        step("close", 0, Value::Integer(1000)),
+      ],
+    )
+    .web_socket(
+      "GET localhost /open",
+      vec![step("send", 0, Value::Text("welcome".to_owned()))],
+    )
+    .build("control");
+  Server::new(cert.server_config(), response_set)
+}
+
+/// Extended CONNECT once the server settings allow it.
+async fn connect(
+  client: &SendRequest<Bytes>,
+  path: &str,
+) -> (StatusCode, http::HeaderMap, SendStream<Bytes>, RecvStream) {
+  let mut client = client.clone().ready().await.unwrap();
+  for _ in 0..100 {
+    if client.is_extended_connect_protocol_enabled() {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+  let mut request = Request::builder()
+    .method(Method::CONNECT)
+    .uri(format!("https://{}{}", HOSTNAME, path))
+    .header("sec-websocket-version", "13")
+    .body(())
+    .unwrap();
+  request
+    .extensions_mut()
+    .insert(Protocol::from_static("websocket"));
+  let (response, send_stream) = client.send_request(request, false).unwrap();
+  let (parts, recv_stream) = response.await.unwrap().into_parts();
+  (parts.status, parts.headers, send_stream, recv_stream)
+}
+
+/// A masked client frame.
+fn client_frame(opcode: u8, payload: &[u8]) -> Bytes {
+  let mask = [0x12, 0x34, 0x56, 0x78];
+  let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+  frame.extend_from_slice(&mask);
+  frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+  Bytes::from(frame)
+}
+
+/// Reads an unmasked server frame with a short payload, None at the end
+/// of the stream. The server may reset the stream with NO_ERROR once
+/// its side is done.
+async fn server_frame(recv_stream: &mut RecvStream, buffer: &mut BytesMut) -> Option<(u8, Bytes)> {
+  loop {
+    if buffer.len() >= 2 && buffer.len() >= 2 + (buffer[1] & 0x7f) as usize {
+      assert!(buffer[1] & 0x7f < 126);
+      let frame = buffer.split_to(2 + (buffer[1] & 0x7f) as usize);
+      return Some((frame[0] & 0x0f, Bytes::copy_from_slice(&frame[2..])));
+    }
+    let chunk = match poll_fn(|cx| recv_stream.poll_data(cx)).await? {
+      Ok(chunk) => chunk,
+      Err(err) if err.reason() == Some(Reason::NO_ERROR) => return None,
+      Err(err) => panic!("{}", err),
+    };
+    let _ = recv_stream.flow_control().release_capacity(chunk.len());
+    buffer.extend_from_slice(&chunk);
+  }
+}
+
+#[tokio::test]
+async fn test_script_is_played() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, headers, mut send_stream, mut recv_stream) = connect(&client, "/socket").await;
+  assert_eq!(status, 200);
+  assert_eq!(headers["sec-websocket-protocol"], "chat");
+  let mut buffer = BytesMut::new();
+
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0x1, Bytes::from("hello")));
+
+  let start = Instant::now();
+  send_stream
+    .send_data(client_frame(0x1, b"subscribe"), false)
+    .unwrap();
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0x2, Bytes::from(vec![1, 2, 3])));
+  assert!(start.elapsed() >= Duration::from_millis(100));
+
+  // pings are answered while the script waits for a message
+  send_stream
+    .send_data(client_frame(0x9, b"p"), false)
+    .unwrap();
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0xa, Bytes::from("p")));
+
+  send_stream
+    .send_data(client_frame(0x2, b"anything"), false)
+    .unwrap();
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0x8, Bytes::from(1000u16.to_be_bytes().to_vec())));
+  assert!(server_frame(&mut recv_stream, &mut buffer).await.is_none());
+}
+
+#[tokio::test]
+async fn test_unexpected_message_closes() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (_, _, mut send_stream, mut recv_stream) = connect(&client, "/socket").await;
+  let mut buffer = BytesMut::new();
+  server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  send_stream
+    .send_data(client_frame(0x1, b"unsubscribe"), false)
+    .unwrap();
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0x8, Bytes::from(1008u16.to_be_bytes().to_vec())));
+}
+
+#[tokio::test]
+async fn test_open_until_client_closes() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (_, headers, mut send_stream, mut recv_stream) = connect(&client, "/open").await;
+  assert!(headers.get("sec-websocket-protocol").is_none());
+  let mut buffer = BytesMut::new();
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0x1, Bytes::from("welcome")));
+  send_stream
+    .send_data(client_frame(0x8, &1001u16.to_be_bytes()), false)
+    .unwrap();
+  let frame = server_frame(&mut recv_stream, &mut buffer).await.unwrap();
+  assert_eq!(frame, (0x8, Bytes::from(1001u16.to_be_bytes().to_vec())));
+  assert!(server_frame(&mut recv_stream, &mut buffer).await.is_none());
+}
+
+#[tokio::test]
+async fn test_unrecorded_web_socket_is_not_found() {
+  let cert = TestCert::new();
+  let (addr, _handle) = serve_one(server(&cert)).await;
+  let client = h2_connect(&cert, addr).await;
+
+  let (status, _, _, _) = connect(&client, "/missing").await;
+  assert_eq!(status, 404);
+}
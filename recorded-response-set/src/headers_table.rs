@@ -2,6 +2,9 @@ use super::util::BuilderVisitor;
 use super::util::SequenceBuilder;
 use super::HeaderNameTable;
 use super::HeaderValueTable;
+use http::header::HeaderName;
+use http::header::HeaderValue;
+use http::header::TE;
 use http::HeaderMap;
 use serde::de::DeserializeSeed;
 use serde::de::SeqAccess;
@@ -108,11 +111,39 @@ impl<'de> SequenceBuilder<'de> for HeaderMapBuilder<'_> {
     S: SeqAccess<'de>,
   {
     while let Some((name_index, value_index)) = seq.next_element::<(usize, usize)>()? {
-      output.append(
-        self.name_table[name_index].clone(),
-        self.value_table[value_index].clone(),
-      );
+      let name = &self.name_table[name_index];
+      if is_framing_header(name) {
+        continue;
+      }
+      output.append(name.clone(), self.value_table[value_index].clone());
     }
     Ok(())
   }
 }
+
+/// Connection-specific headers, which HTTP/2 forbids, paired with
+/// whether they only describe the framing of the recorded HTTP/1.1
+/// connection, those are dropped on load and the rest are reported.
+const CONNECTION_HEADERS: [(&str, bool); 6] = [
+  ("connection", true),
+  ("keep-alive", true),
+  ("proxy-connection", false),
+  ("te", false),
+  ("transfer-encoding", true),
+  ("upgrade", false),
+];
+
+/// Whether the header is connection-specific, te is only allowed as
+/// te: trailers.
+pub fn is_connection_header(name: &HeaderName, value: &HeaderValue) -> bool {
+  CONNECTION_HEADERS
+    .iter()
+    .any(|(connection_header, _)| name.as_str() == *connection_header)
+    && !(*name == TE && value == "trailers")
+}
+
+fn is_framing_header(name: &HeaderName) -> bool {
+  CONNECTION_HEADERS
+    .iter()
+    .any(|(connection_header, framing)| *framing && name.as_str() == *connection_header)
+}
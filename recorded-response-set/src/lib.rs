@@ -7,16 +7,24 @@ mod header_value_table;
 mod headers_table;
 mod recorded_response;
 mod recorded_response_set;
+mod recorded_response_sets_builder;
 mod response_table;
+mod server_sent_event;
 mod util;
+mod web_socket_script;
 
 use body_table::BodyTable;
 use header_name_table::HeaderNameTable;
 use header_value_table::HeaderValueTable;
+pub use headers_table::is_connection_header;
 use headers_table::HeadersTable;
 use headers_table::HeadersTableBuilder;
 pub use recorded_response::RecordedResponse;
 pub use recorded_response_set::RecordedResponseSet;
 pub use recorded_response_set::RecordedResponseSets;
+pub use recorded_response_sets_builder::RecordedResponseSetsBuilder;
 use response_table::ResponseTable;
 use response_table::ResponseTableBuilder;
+pub use server_sent_event::ServerSentEvent;
+pub use web_socket_script::WebSocketMessage;
+pub use web_socket_script::WebSocketStep;
@@ -1,3 +1,4 @@
+use super::is_connection_header;
 use super::BodyTable;
 use super::HeaderNameTable;
 use super::HeaderValueTable;
@@ -5,7 +6,11 @@ use super::HeadersTableBuilder;
 use super::RecordedResponse;
 use super::ResponseTable;
 use super::ResponseTableBuilder;
+use super::ServerSentEvent;
+use super::WebSocketStep;
 use bytes::Bytes;
+use http::header::HeaderName;
+use http::HeaderMap;
 use http::Method;
 use http::Response;
 use http::Uri;
@@ -30,9 +35,24 @@ pub struct RecordedResponseSet {
   entry_key: String,
   request_key: RequestKey,
   response_map: HashMap<String, RecordedResponse>,
+  event_streams: HashMap<String, Arc<[ServerSentEvent]>>,
+  web_socket_scripts: HashMap<String, Arc<[WebSocketStep]>>,
 }
 
 impl RecordedResponseSet {
+  /// An empty set to record responses into.
+  pub fn new(socks_port: u16, name: &str, entry_key: &str, request_key: RequestKey) -> Self {
+    RecordedResponseSet {
+      socks_port,
+      name: name.to_owned(),
+      entry_key: entry_key.to_owned(),
+      request_key,
+      response_map: HashMap::new(),
+      event_streams: HashMap::new(),
+      web_socket_scripts: HashMap::new(),
+    }
+  }
+
   pub fn socks_port(&self) -> u16 {
     self.socks_port
   }
@@ -50,12 +70,28 @@ impl RecordedResponseSet {
   }
 
   pub fn response_for(&self, method: &Method, uri: &Uri) -> Option<(Response<()>, Option<Bytes>)> {
-    let key = self.key_for(method, uri);
+    self.response_for_target(method, uri, None)
+  }
+
+  /// Response for a request made through the proxy to the target host:port.
+  pub fn response_for_target(
+    &self,
+    method: &Method,
+    uri: &Uri,
+    target: Option<&str>,
+  ) -> Option<(Response<()>, Option<Bytes>)> {
+    let key = self.key_for_target(method, uri, target);
     let recorded_response = self.response_map.get(&key);
     recorded_response.map(|recorded_response| recorded_response.to_parts())
   }
 
   pub fn key_for(&self, method: &Method, uri: &Uri) -> String {
+    self.key_for_target(method, uri, None)
+  }
+
+  /// Key for a request made through the proxy to the target host:port,
+  /// the request key program can use the target instead of :authority.
+  pub fn key_for_target(&self, method: &Method, uri: &Uri, target: Option<&str>) -> String {
     let authority = match uri.authority() {
       Some(authority) => authority.as_str(),
       None => "*", // should never happen in h2
@@ -66,7 +102,7 @@ impl RecordedResponseSet {
     };
     self
       .request_key
-      .key_for(method.as_str(), authority, path_and_query)
+      .key_for_target(method.as_str(), authority, path_and_query, target)
   }
 
   pub fn requests(&self) -> hash_map::Iter<'_, String, RecordedResponse> {
@@ -77,7 +113,64 @@ impl RecordedResponseSet {
     self.response_map.get(key)
   }
 
-  fn from_raw(raw_set: RawResponseSet<'_>, response_table: &ResponseTable) -> Self {
+  /// The recorded headers HTTP/2 forbids as (request key, name), h2
+  /// fails the stream of a response with one of them.
+  pub fn invalid_h2_headers(&self) -> Vec<(&str, &HeaderName)> {
+    let mut invalid: Vec<(&str, &HeaderName)> = self
+      .response_map
+      .iter()
+      .flat_map(|(key, response)| {
+        response
+          .headers()
+          .iter()
+          .filter(|(name, value)| is_connection_header(name, value))
+          .map(move |(name, _)| (key.as_str(), name))
+      })
+      .collect();
+    invalid.sort_by(|a, b| (a.0, a.1.as_str()).cmp(&(b.0, b.1.as_str())));
+    invalid.dedup();
+    invalid
+  }
+
+  /// Removes the headers HTTP/2 forbids from the recorded responses.
+  pub fn strip_invalid_h2_headers(&mut self) {
+    for response in self.response_map.values_mut() {
+      let headers = response.headers();
+      if !headers
+        .iter()
+        .any(|(name, value)| is_connection_header(name, value))
+      {
+        continue;
+      }
+      let mut stripped = HeaderMap::with_capacity(headers.len());
+      for (name, value) in headers.iter() {
+        if !is_connection_header(name, value) {
+          stripped.append(name.clone(), value.clone());
+        }
+      }
+      *response = RecordedResponse::new(
+        response.status_code(),
+        Arc::new(stripped),
+        response.body().cloned(),
+      );
+    }
+  }
+
+  /// Server-sent events recorded for the request key.
+  pub fn get_event_stream(&self, key: &str) -> Option<&Arc<[ServerSentEvent]>> {
+    self.event_streams.get(key)
+  }
+
+  /// WebSocket message script recorded for the request key.
+  pub fn get_web_socket_script(&self, key: &str) -> Option<&Arc<[WebSocketStep]>> {
+    self.web_socket_scripts.get(key)
+  }
+
+  fn from_raw(
+    raw_set: RawResponseSet<'_>,
+    response_table: &ResponseTable,
+    script_table: &[Arc<[WebSocketStep]>],
+  ) -> Result<Self, String> {
     let raw_map = raw_set.request_key_map;
     let mut response_map: HashMap<String, RecordedResponse> = HashMap::with_capacity(raw_map.len());
 
@@ -85,13 +178,33 @@ impl RecordedResponseSet {
       response_map.insert((*key).to_owned(), response_table[*index].clone());
     }
 
-    RecordedResponseSet {
+    let event_streams = raw_set
+      .event_streams
+      .into_iter()
+      .map(|(key, events)| (key.to_owned(), events.into()))
+      .collect();
+
+    let mut web_socket_scripts = HashMap::with_capacity(raw_set.web_socket_map.len());
+    for (key, index) in raw_set.web_socket_map.iter() {
+      let name = raw_set.name;
+      let script = script_table.get(*index).ok_or_else(|| {
+        format!(
+          "response set {:?} web socket script index {} is out of range",
+          name, index
+        )
+      })?;
+      web_socket_scripts.insert((*key).to_owned(), script.clone());
+    }
+
+    Ok(RecordedResponseSet {
       socks_port: raw_set.socks_port,
       name: raw_set.name.to_owned(),
       entry_key: raw_set.entry_key.to_owned(),
       request_key: raw_set.request_key_program,
       response_map,
-    }
+      event_streams,
+      web_socket_scripts,
+    })
   }
 }
 
@@ -103,11 +216,21 @@ struct RawResponseSet<'a> {
   entry_key: &'a str,
   request_key_program: RequestKey,
   request_key_map: HashMap<&'a str, usize>,
+  #[serde(default)]
+  event_streams: HashMap<&'a str, Vec<ServerSentEvent>>,
+  #[serde(default)]
+  web_socket_map: HashMap<&'a str, usize>,
 }
 
 #[derive(Debug)]
 pub struct RecordedResponseSets(Vec<Arc<RecordedResponseSet>>);
 
+impl From<Vec<Arc<RecordedResponseSet>>> for RecordedResponseSets {
+  fn from(sets: Vec<Arc<RecordedResponseSet>>) -> Self {
+    RecordedResponseSets(sets)
+  }
+}
+
 impl<'a> Deref for RecordedResponseSets {
   type Target = Vec<Arc<RecordedResponseSet>>;
 
@@ -128,7 +251,9 @@ impl<'de> Visitor<'de> for RecordedResponseSetsVisitor {
   type Value = RecordedResponseSets;
 
   fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-    formatter.write_str("sequence of bodies, header names, header values, headers, responses, sets")
+    formatter.write_str(
+      "sequence of bodies, header names, header values, headers, responses, sets and optionally web socket scripts",
+    )
   }
 
   fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
@@ -163,13 +288,21 @@ impl<'de> Visitor<'de> for RecordedResponseSetsVisitor {
       .next_element()?
       .ok_or_else(|| S::Error::custom("expected 6th element to be a response set sequence"))?;
 
+    // archives without web sockets may leave the table out
+    let script_table: Vec<Arc<[WebSocketStep]>> = seq
+      .next_element::<Vec<Vec<WebSocketStep>>>()?
+      .unwrap_or_default()
+      .into_iter()
+      .map(|script| script.into())
+      .collect();
+
     let mut sets = Vec::with_capacity(raw_sets.len());
 
     for raw_set in raw_sets.drain(..) {
-      sets.push(Arc::new(RecordedResponseSet::from_raw(
-        raw_set,
-        &response_table,
-      )));
+      sets.push(Arc::new(
+        RecordedResponseSet::from_raw(raw_set, &response_table, &script_table)
+          .map_err(S::Error::custom)?,
+      ));
     }
 
     Ok(RecordedResponseSets(sets))
@@ -0,0 +1,182 @@
+use super::RecordedResponse;
+use bytes::Bytes;
+use serde::ser::SerializeSeq;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use tracerbench_request_key::RequestKey;
+
+/// Builds recorded response sets and serializes them in the format
+/// RecordedResponseSets deserializes, bodies, header names, values,
+/// headers and responses are deduplicated across sets.
+#[derive(Default)]
+pub struct RecordedResponseSetsBuilder {
+  bodies: Table<Bytes>,
+  header_names: Table<String>,
+  header_values: Table<String>,
+  headers: Table<Vec<(usize, usize)>>,
+  responses: Table<(u16, usize, Option<usize>)>,
+  sets: Vec<SetBuilder>,
+}
+
+struct SetBuilder {
+  socks_port: u16,
+  name: String,
+  entry_key: String,
+  request_key: RequestKey,
+  request_key_map: BTreeMap<String, usize>,
+}
+
+impl RecordedResponseSetsBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn has_set(&self, name: &str) -> bool {
+    self.sets.iter().any(|set| set.name == name)
+  }
+
+  /// Adds an empty set, responses are added to it by name.
+  pub fn add_set(&mut self, socks_port: u16, name: &str, entry_key: &str, request_key: RequestKey) {
+    self.sets.push(SetBuilder {
+      socks_port,
+      name: name.to_owned(),
+      entry_key: entry_key.to_owned(),
+      request_key,
+      request_key_map: BTreeMap::new(),
+    });
+  }
+
+  /// Adds the response for the request key to the named set, replacing
+  /// a response already added for the key. Returns false if there is no
+  /// such set.
+  pub fn add_response(&mut self, set_name: &str, key: &str, response: &RecordedResponse) -> bool {
+    let set_index = match self.sets.iter().position(|set| set.name == set_name) {
+      Some(set_index) => set_index,
+      None => return false,
+    };
+    let response_index = self.intern_response(response);
+    self.sets[set_index]
+      .request_key_map
+      .insert(key.to_owned(), response_index);
+    true
+  }
+
+  /// Number of responses added to all sets.
+  pub fn len(&self) -> usize {
+    self.sets.iter().map(|set| set.request_key_map.len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  fn intern_response(&mut self, response: &RecordedResponse) -> usize {
+    let mut headers = Vec::with_capacity(response.headers().len());
+    for (name, value) in response.headers() {
+      let name_index = self.header_names.intern(name.as_str().to_owned());
+      let value_index = self
+        .header_values
+        .intern(String::from_utf8_lossy(value.as_bytes()).into_owned());
+      headers.push((name_index, value_index));
+    }
+    let headers_index = self.headers.intern(headers);
+    let body_index = response.body().map(|body| self.bodies.intern(body.clone()));
+    self
+      .responses
+      .intern((response.status_code().as_u16(), headers_index, body_index))
+  }
+}
+
+impl Serialize for RecordedResponseSetsBuilder {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    let mut seq = serializer.serialize_seq(Some(6))?;
+    seq.serialize_element(&BodyTable(&self.bodies.items))?;
+    seq.serialize_element(&self.header_names.items)?;
+    seq.serialize_element(&self.header_values.items)?;
+    seq.serialize_element(&self.headers.items)?;
+    seq.serialize_element(&self.responses.items)?;
+    let sets: Vec<RawResponseSet<'_>> = self
+      .sets
+      .iter()
+      .map(|set| RawResponseSet {
+        socks_port: set.socks_port,
+        name: &set.name,
+        entry_key: &set.entry_key,
+        request_key_program: &set.request_key,
+        request_key_map: &set.request_key_map,
+      })
+      .collect();
+    seq.serialize_element(&sets)?;
+    seq.end()
+  }
+}
+
+#[derive(serde_derive::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RawResponseSet<'a> {
+  socks_port: u16,
+  name: &'a str,
+  entry_key: &'a str,
+  request_key_program: &'a RequestKey,
+  request_key_map: &'a BTreeMap<String, usize>,
+}
+
+/// bodies serialize as byte strings rather than sequences of u8
+struct BodyTable<'a>(&'a [Bytes]);
+
+impl Serialize for BodyTable<'_> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    struct Body<'a>(&'a [u8]);
+
+    impl Serialize for Body<'_> {
+      fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+      where
+        S: Serializer,
+      {
+        serializer.serialize_bytes(self.0)
+      }
+    }
+
+    let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+    for body in self.0 {
+      seq.serialize_element(&Body(body))?;
+    }
+    seq.end()
+  }
+}
+
+/// items in insertion order, each stored once
+struct Table<T> {
+  items: Vec<T>,
+  index: HashMap<T, usize>,
+}
+
+impl<T> Default for Table<T> {
+  fn default() -> Self {
+    Table {
+      items: Vec::new(),
+      index: HashMap::new(),
+    }
+  }
+}
+
+impl<T: Clone + Eq + Hash> Table<T> {
+  fn intern(&mut self, item: T) -> usize {
+    if let Some(&i) = self.index.get(&item) {
+      return i;
+    }
+    let i = self.items.len();
+    self.items.push(item.clone());
+    self.index.insert(item, i);
+    i
+  }
+}
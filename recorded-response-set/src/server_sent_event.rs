@@ -0,0 +1,42 @@
+use super::util::BytesWrapper;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Deserializer;
+use std::time::Duration;
+
+/// An event of a recorded event stream, data is the event as it was
+/// sent including the blank line that ends it.
+#[derive(Debug, Clone)]
+pub struct ServerSentEvent {
+  delay: Duration,
+  data: Bytes,
+}
+
+impl ServerSentEvent {
+  pub fn new(delay: Duration, data: Bytes) -> Self {
+    ServerSentEvent { delay, data }
+  }
+
+  /// Time since the previous event, or since the response for the
+  /// first event.
+  pub fn delay(&self) -> Duration {
+    self.delay
+  }
+
+  pub fn data(&self) -> &Bytes {
+    &self.data
+  }
+}
+
+impl<'de> Deserialize<'de> for ServerSentEvent {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let (delay_ms, data) = <(u64, BytesWrapper)>::deserialize(deserializer)?;
+    Ok(ServerSentEvent::new(
+      Duration::from_millis(delay_ms),
+      data.0,
+    ))
+  }
+}
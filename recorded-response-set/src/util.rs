@@ -62,7 +62,7 @@ where
 
 struct BytesVisitor;
 
-struct BytesWrapper(Bytes);
+pub(super) struct BytesWrapper(pub(super) Bytes);
 
 impl Into<Bytes> for BytesWrapper {
   fn into(self) -> Bytes {
@@ -0,0 +1,135 @@
+use bytes::Bytes;
+use serde::de::Error;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde::Deserialize;
+use serde::Deserializer;
+use std::fmt;
+use std::time::Duration;
+
+/// A step of a recorded WebSocket message script, the server plays
+/// the steps of a script in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebSocketStep {
+  /// the server sends the message after the delay
+  Send {
+    delay: Duration,
+    message: WebSocketMessage,
+  },
+  /// the server waits for a message from the client, which must be
+  /// the expected message if there is one
+  Receive { expected: Option<WebSocketMessage> },
+  /// the server closes the WebSocket with the status code after the delay
+  Close { delay: Duration, code: u16 },
+}
+
+/// A complete WebSocket message, CBOR text is a text message and
+/// CBOR bytes a binary message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebSocketMessage {
+  Text(String),
+  Binary(Bytes),
+}
+
+impl<'de> Deserialize<'de> for WebSocketMessage {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(WebSocketMessageVisitor)
+  }
+}
+
+struct WebSocketMessageVisitor;
+
+impl<'de> Visitor<'de> for WebSocketMessageVisitor {
+  type Value = WebSocketMessage;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+    formatter.write_str("text or bytes")
+  }
+
+  fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(WebSocketMessage::Text(v.to_owned()))
+  }
+
+  fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(WebSocketMessage::Text(v))
+  }
+
+  fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(WebSocketMessage::Binary(Bytes::copy_from_slice(v)))
+  }
+
+  fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+  where
+    E: Error,
+  {
+    Ok(WebSocketMessage::Binary(v.into()))
+  }
+}
+
+impl<'de> Deserialize<'de> for WebSocketStep {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_seq(WebSocketStepVisitor)
+  }
+}
+
+struct WebSocketStepVisitor;
+
+impl<'de> Visitor<'de> for WebSocketStepVisitor {
+  type Value = WebSocketStep;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+    formatter.write_str("sequence of step kind, delay and message or close code")
+  }
+
+  fn visit_seq<S>(self, mut seq: S) -> Result<Self::Value, S::Error>
+  where
+    S: SeqAccess<'de>,
+  {
+    let kind: &str = seq
+      .next_element()?
+      .ok_or_else(|| S::Error::invalid_length(0, &self))?;
+    let delay_ms: u64 = seq
+      .next_element()?
+      .ok_or_else(|| S::Error::invalid_length(1, &self))?;
+    let delay = Duration::from_millis(delay_ms);
+    match kind {
+      "send" => {
+        let message = seq
+          .next_element()?
+          .ok_or_else(|| S::Error::invalid_length(2, &self))?;
+        Ok(WebSocketStep::Send { delay, message })
+      }
+      "receive" => {
+        let expected = seq
+          .next_element()?
+          .ok_or_else(|| S::Error::invalid_length(2, &self))?;
+        Ok(WebSocketStep::Receive { expected })
+      }
+      "close" => {
+        let code = seq
+          .next_element()?
+          .ok_or_else(|| S::Error::invalid_length(2, &self))?;
+        Ok(WebSocketStep::Close { delay, code })
+      }
+      _ => Err(S::Error::unknown_variant(
+        kind,
+        &["send", "receive", "close"],
+      )),
+    }
+  }
+}
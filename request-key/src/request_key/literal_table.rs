@@ -1,7 +1,7 @@
 use super::regex_replace::RegexReplace;
 use super::regex_test::RegexTest;
 
-#[derive(serde_derive::Deserialize)]
+#[derive(serde_derive::Deserialize, serde_derive::Serialize)]
 #[serde(tag = "type", content = "content")]
 pub enum Literal {
   String(String),
@@ -34,7 +34,7 @@ impl Literal {
   }
 }
 
-#[derive(serde_derive::Deserialize)]
+#[derive(serde_derive::Deserialize, serde_derive::Serialize)]
 pub struct LiteralTable(Vec<Literal>);
 
 impl LiteralTable {
@@ -9,11 +9,17 @@ mod state;
 use program::Program;
 use serde::Deserialize;
 use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
 use state::State;
 use std::fmt;
+use std::sync::Arc;
 
+/// Compiled request key program, cheap to clone. It serializes back
+/// to the program it was deserialized from.
+#[derive(Clone)]
 pub struct RequestKey {
-  program: Program,
+  program: Arc<Program>,
 }
 
 impl fmt::Debug for RequestKey {
@@ -24,11 +30,25 @@ impl fmt::Debug for RequestKey {
 
 impl RequestKey {
   fn new(program: Program) -> RequestKey {
-    RequestKey { program }
+    RequestKey {
+      program: Arc::new(program),
+    }
   }
 
   pub fn key_for(&self, method: &str, authority: &str, path_and_query: &str) -> String {
-    let mut state = State::new(method, authority, path_and_query);
+    self.key_for_target(method, authority, path_and_query, None)
+  }
+
+  /// Key for a request that was made through a proxy to the target
+  /// host:port, the program can read the target as a request part.
+  pub fn key_for_target(
+    &self,
+    method: &str,
+    authority: &str,
+    path_and_query: &str,
+    target: Option<&str>,
+  ) -> String {
+    let mut state = State::new(method, authority, path_and_query, target);
     self.program.exec(&mut state);
     state.key()
   }
@@ -43,3 +63,12 @@ impl<'de> Deserialize<'de> for RequestKey {
     Ok(RequestKey::new(program))
   }
 }
+
+impl Serialize for RequestKey {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    self.program.serialize(serializer)
+  }
+}
@@ -3,6 +3,8 @@ use super::request_parts::RequestPart;
 use super::state::State;
 use serde::Deserialize;
 use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
 use std::fmt;
 use std::ops::Deref;
 
@@ -11,7 +13,8 @@ pub(super) trait Op {
   fn fmt(&self, table: &LiteralTable, f: &mut fmt::Formatter<'_>) -> fmt::Result;
 }
 
-pub(super) struct Opcodes(Vec<Opcode>);
+/// decoded opcodes and the bytecode they were decoded from
+pub(super) struct Opcodes(Vec<Opcode>, Vec<u8>);
 
 impl Deref for Opcodes {
   type Target = [Opcode];
@@ -39,7 +42,16 @@ impl<'de: 'a, 'a> Deserialize<'de> for Opcodes {
     for chunk in bytes.chunks_exact(4) {
       opcodes.push(chunk.into());
     }
-    Ok(Opcodes(opcodes))
+    Ok(Opcodes(opcodes, bytes.to_vec()))
+  }
+}
+
+impl Serialize for Opcodes {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_bytes(&self.1)
   }
 }
 
@@ -5,7 +5,7 @@ use super::opcode::Opcodes;
 use super::state::State;
 use std::fmt;
 
-#[derive(serde_derive::Deserialize)]
+#[derive(serde_derive::Deserialize, serde_derive::Serialize)]
 pub struct Program(LiteralTable, Opcodes);
 
 impl Program {
@@ -1,10 +1,14 @@
 use regex::Regex;
 use serde::Deserialize;
 use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
 use std::borrow::Cow;
 use std::fmt;
 
-pub struct RegexReplace(Regex, String);
+/// regex, replacement fixed for the regex crate and the original
+/// JS replacement text
+pub struct RegexReplace(Regex, String, String);
 
 impl fmt::Debug for RegexReplace {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -17,7 +21,7 @@ impl<'a> From<(&'a str, &'a str)> for RegexReplace {
     let (pattern, replacement_text) = tuple;
     let regex = Regex::new(pattern).unwrap();
     let fixed = fix_js_replacement(replacement_text, regex.captures_len());
-    RegexReplace(regex, fixed.to_string())
+    RegexReplace(regex, fixed.to_string(), replacement_text.to_owned())
   }
 }
 
@@ -31,6 +35,15 @@ impl<'de> Deserialize<'de> for RegexReplace {
   }
 }
 
+impl Serialize for RegexReplace {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    (self.0.as_str(), self.2.as_str()).serialize(serializer)
+  }
+}
+
 impl RegexReplace {
   pub fn replace<'b>(&self, text: &'b str) -> Cow<'b, str> {
     self.0.replace(text, self.1.as_str())
@@ -1,6 +1,8 @@
 use regex::Regex;
 use serde::Deserialize;
 use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
 use std::fmt;
 
 pub struct RegexTest(Regex);
@@ -39,6 +41,15 @@ impl<'de: 'a, 'a> Deserialize<'de> for RegexTest {
   }
 }
 
+impl Serialize for RegexTest {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    serializer.serialize_str(self.0.as_str())
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
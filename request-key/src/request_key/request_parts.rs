@@ -7,6 +7,7 @@ pub(super) struct RequestParts<'a> {
   authority: Cow<'a, str>,
   path_and_query: Cow<'a, str>,
   query_index: Option<usize>,
+  target: Option<Cow<'a, str>>,
 }
 
 impl<'a> RequestParts<'a> {
@@ -14,12 +15,14 @@ impl<'a> RequestParts<'a> {
     method: &'a str,
     authority: &'a str,
     path_and_query: &'a str,
+    target: Option<&'a str>,
   ) -> RequestParts<'a> {
     RequestParts {
       method: Cow::Borrowed(method),
       authority: Cow::Borrowed(authority),
       path_and_query: Cow::Borrowed(path_and_query),
       query_index: path_and_query.find('?'),
+      target: target.map(Cow::Borrowed),
     }
   }
 
@@ -30,6 +33,7 @@ impl<'a> RequestParts<'a> {
       PathAndQuery => Some(&self.path_and_query),
       Path => Some(self.get_path()),
       Query => self.get_query(),
+      Target => self.target.as_deref(),
     }
   }
 
@@ -40,6 +44,7 @@ impl<'a> RequestParts<'a> {
       PathAndQuery => self.set_path_and_query(value),
       Path => self.set_path(value),
       Query => self.set_query(value),
+      Target => self.target = value.map(|target| Cow::Owned(target.to_owned())),
     }
   }
 
@@ -123,6 +128,9 @@ pub(super) enum RequestPart {
   PathAndQuery,
   Path,
   Query,
+  /// host:port the client connected to through the proxy, it is not
+  /// part of the key but can be moved into the authority
+  Target,
 }
 
 impl From<usize> for RequestPart {
@@ -133,6 +141,7 @@ impl From<usize> for RequestPart {
       2 => RequestPart::PathAndQuery,
       3 => RequestPart::Path,
       4 => RequestPart::Query,
+      5 => RequestPart::Target,
       _ => panic!("invalid RequestPart {}", part),
     }
   }
@@ -144,7 +153,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query() {
-    let parts = RequestParts::new("POST", "www.blah.com", "/path/to/something?query=params");
+    let parts = RequestParts::new(
+      "POST",
+      "www.blah.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     assert_eq!(parts.get_part(RequestPart::Method), Some("POST"));
     assert_eq!(parts.get_part(RequestPart::Authority), Some("www.blah.com"));
@@ -166,7 +180,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_path_and_query_with_none() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(RequestPart::PathAndQuery, None);
 
@@ -179,7 +198,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_path_and_query_with_path_only() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(RequestPart::PathAndQuery, Some("/something/else"));
 
@@ -195,7 +219,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_path_and_query_with_path_and_query() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(
       RequestPart::PathAndQuery,
@@ -217,7 +246,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_path_with_none() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(RequestPart::Path, None);
 
@@ -233,7 +267,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_path_with_path() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(RequestPart::Path, Some("/something/else"));
 
@@ -252,7 +291,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_query_with_none() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(RequestPart::Query, None);
 
@@ -270,7 +314,12 @@ mod tests {
 
   #[test]
   fn test_path_and_query_replace_query_with_query() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something?query=params");
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path/to/something?query=params",
+      None,
+    );
 
     parts.set_part(RequestPart::Query, Some("another=query"));
 
@@ -292,7 +341,7 @@ mod tests {
 
   #[test]
   fn test_path_only() {
-    let parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     assert_eq!(parts.get_part(RequestPart::Method), Some("GET"));
     assert_eq!(
@@ -314,7 +363,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_path_and_query_with_none() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(RequestPart::PathAndQuery, None);
 
@@ -325,7 +374,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_path_and_query_with_path_only() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(RequestPart::PathAndQuery, Some("/something/else"));
 
@@ -341,7 +390,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_path_and_query_with_path_and_query() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(
       RequestPart::PathAndQuery,
@@ -363,7 +412,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_path_with_none() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(RequestPart::Path, None);
 
@@ -376,7 +425,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_path_with_path() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(RequestPart::Path, Some("/something/else"));
 
@@ -392,7 +441,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_query_with_none() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(RequestPart::Query, None);
 
@@ -411,7 +460,7 @@ mod tests {
 
   #[test]
   fn test_path_only_replace_query_with_query() {
-    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something");
+    let mut parts = RequestParts::new("GET", "www.example.com", "/path/to/something", None);
 
     parts.set_part(RequestPart::Query, Some("another=query"));
 
@@ -430,4 +479,24 @@ mod tests {
       "GET www.example.com /path/to/something?another=query"
     );
   }
+
+  #[test]
+  fn test_target() {
+    let mut parts = RequestParts::new(
+      "GET",
+      "www.example.com",
+      "/path",
+      Some("www.example.com:8443"),
+    );
+
+    assert_eq!(
+      parts.get_part(RequestPart::Target),
+      Some("www.example.com:8443")
+    );
+    assert_eq!(parts.key(), "GET www.example.com /path");
+
+    parts = RequestParts::new("GET", "www.example.com", "/path", None);
+
+    assert_eq!(parts.get_part(RequestPart::Target), None);
+  }
 }
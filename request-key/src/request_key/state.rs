@@ -19,9 +19,14 @@ pub(super) struct State<'a> {
 }
 
 impl<'a> State<'a> {
-  pub(super) fn new(method: &'a str, authority: &'a str, path_and_query: &'a str) -> State<'a> {
+  pub(super) fn new(
+    method: &'a str,
+    authority: &'a str,
+    path_and_query: &'a str,
+    target: Option<&'a str>,
+  ) -> State<'a> {
     State {
-      request_parts: RequestParts::new(method, authority, path_and_query),
+      request_parts: RequestParts::new(method, authority, path_and_query, target),
       ip: 0,
       len: 0,
       test: false,
@@ -71,7 +76,8 @@ impl<'a> State<'a> {
       Some(Value::Mutated(ref text)) => (self.request_parts.set_part(part, Some(text))),
       Some(Value::Part(value_part)) => {
         if value_part != part {
-          panic!("moving a part to another part is not supported");
+          let text = self.request_parts.get_part(value_part).map(str::to_owned);
+          self.request_parts.set_part(part, text.as_deref());
         }
       }
       None => self.request_parts.set_part(part, None),
@@ -155,7 +161,7 @@ impl<'a> State<'a> {
 
 #[test]
 fn test_state_drop_path() {
-  let mut state = State::new("GET", "www.example.com", "/path/to/something?foo=bar");
+  let mut state = State::new("GET", "www.example.com", "/path/to/something?foo=bar", None);
 
   state.clear_value();
 
@@ -166,7 +172,7 @@ fn test_state_drop_path() {
 
 #[test]
 fn test_state_drop_query() {
-  let mut state = State::new("GET", "www.example.com", "/path/to/something?foo=bar");
+  let mut state = State::new("GET", "www.example.com", "/path/to/something?foo=bar", None);
 
   state.clear_value();
 
@@ -177,7 +183,7 @@ fn test_state_drop_query() {
 
 #[test]
 fn test_state_drop_query_and_replace() {
-  let mut state = State::new("GET", "www.example.com", "/path/to/something?foo=bar");
+  let mut state = State::new("GET", "www.example.com", "/path/to/something?foo=bar", None);
 
   state.clear_value();
 
@@ -198,3 +204,19 @@ fn test_state_drop_query_and_replace() {
     "GET www.example.com /another/path?another=query"
   );
 }
+
+#[test]
+fn test_state_move_target_to_authority() {
+  let mut state = State::new(
+    "GET",
+    "www.example.com",
+    "/path",
+    Some("www.example.com:8443"),
+  );
+
+  state.move_part_to_value(RequestPart::Target);
+
+  state.move_value_to_part(RequestPart::Authority);
+
+  assert_eq!(state.key(), "GET www.example.com:8443 /path");
+}
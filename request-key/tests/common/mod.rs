@@ -53,6 +53,11 @@ impl ProgramBuilder {
     self.move_value_to_part(part);
   }
 
+  pub fn replace_part_with_part(&mut self, part: RequestPart, source: RequestPart) {
+    self.move_part_to_value(source);
+    self.move_value_to_part(part);
+  }
+
   pub fn replace_part_with_string(&mut self, part: RequestPart, literal: &str) {
     self.move_string_to_value(literal.to_owned());
     self.move_value_to_part(part);
@@ -245,4 +250,5 @@ pub enum RequestPart {
   PathAndQuery,
   Path,
   Query,
+  Target,
 }
@@ -139,3 +139,77 @@ fn test_includes_and_replace_all() {
     String::from("GET example.com /one/two/one/and/two")
   );
 }
+
+#[test]
+fn test_target_as_authority() {
+  let mut builder = ProgramBuilder::new();
+  builder.if_part(
+    RequestPart::Target,
+    TestType::EndsWith,
+    ":8443",
+    |builder| {
+      builder.replace_part_with_part(RequestPart::Authority, RequestPart::Target);
+      builder.stop();
+    },
+  );
+
+  let bytes = builder.to_bytes();
+  let request_key: RequestKey = serde_cbor::from_slice(&bytes).unwrap();
+
+  assert_eq!(
+    request_key.key_for_target("GET", "example.com", "/", Some("example.com:8443")),
+    String::from("GET example.com:8443 /")
+  );
+
+  assert_eq!(
+    request_key.key_for_target("GET", "example.com", "/", Some("example.com:443")),
+    String::from("GET example.com /")
+  );
+
+  assert_eq!(
+    request_key.key_for("GET", "example.com", "/"),
+    String::from("GET example.com /")
+  );
+}
+
+#[test]
+fn test_serialize_round_trip() {
+  let mut builder = ProgramBuilder::new();
+  builder.if_part(
+    RequestPart::Path,
+    TestType::Matches,
+    "(one|two)",
+    |builder| {
+      builder.regex_replace_part(
+        RequestPart::PathAndQuery,
+        "([^\\d])\\d{13}\\b",
+        "$11546300800000",
+        true,
+      );
+      builder.stop();
+    },
+  );
+  builder.if_part(RequestPart::Method, TestType::Equals, "GET", |builder| {
+    builder.drop_part(RequestPart::Query);
+  });
+
+  let bytes = builder.to_bytes();
+  let request_key: RequestKey = serde_cbor::from_slice(&bytes).unwrap();
+  let serialized = serde_cbor::to_vec(&request_key).unwrap();
+  let round_trip: RequestKey = serde_cbor::from_slice(&serialized).unwrap();
+
+  for (method, path_and_query) in &[
+    ("GET", "/one/foo?t=1575322162000"),
+    ("GET", "/three?q=1"),
+    ("POST", "/three?q=1"),
+  ] {
+    assert_eq!(
+      round_trip.key_for(method, "example.com", path_and_query),
+      request_key.key_for(method, "example.com", path_and_query)
+    );
+  }
+  assert_eq!(
+    round_trip.key_for("GET", "example.com", "/one/foo?t=1575322162000"),
+    String::from("GET example.com /one/foo?t=1546300800000")
+  );
+}
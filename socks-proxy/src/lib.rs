@@ -2,16 +2,27 @@
 #![warn(clippy::all)]
 
 use log::debug;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
 use std::io;
 use std::net;
+use std::sync::Arc;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 
 const SOCKS_VERSION: u8 = b'\x05';
+const SOCKS4_VERSION: u8 = b'\x04';
+const SOCKS4_REPLY_VERSION: u8 = b'\x00';
+const SOCKS4_GRANTED: u8 = b'\x5A';
+const SOCKS4_REJECTED: u8 = b'\x5B';
 const NO_AUTHENTICATION_REQUIRED: u8 = b'\x00';
+const USERNAME_PASSWORD: u8 = b'\x02';
+const USERNAME_PASSWORD_VERSION: u8 = b'\x01';
 const NO_ACCEPTABLE_METHODS: u8 = b'\xFF';
+const SUCCEEDED: u8 = b'\x00';
+const GENERAL_FAILURE: u8 = b'\x01';
+const CONNECTION_NOT_ALLOWED: u8 = b'\x02';
 const COMMAND_NOT_SUPPORTED: u8 = b'\x07';
 const ADDRESS_TYPE_NOT_SUPPORTED: u8 = b'\x08';
 const CONNECT_COMMAND: u8 = b'\x01';
@@ -19,75 +30,232 @@ const ADDRESS_TYPE_IPV4: u8 = b'\x01';
 const ADDRESS_TYPE_DOMAIN_NAME: u8 = b'\x03';
 const ADDRESS_TYPE_IPV6: u8 = b'\x04';
 
+// max possible socks 5 message is the username/password request
+// 1 version 1 username length 255 username 1 password length 255 password
+// and it bounds the userid and domain name of socks 4a
+const BUFFER_LEN: usize = 513;
+
 // +----+--------+
 // |VER | METHOD |
 // +----+--------+
 // | 1  |   1    |
 // +----+--------+
 static NO_AUTHENTICATION_REQUIRED_REPLY: [u8; 2] = [SOCKS_VERSION, NO_AUTHENTICATION_REQUIRED];
+static USERNAME_PASSWORD_REPLY: [u8; 2] = [SOCKS_VERSION, USERNAME_PASSWORD];
 static NO_ACCEPTABLE_METHODS_REPLY: [u8; 2] = [SOCKS_VERSION, NO_ACCEPTABLE_METHODS];
 
+// +----+--------+
+// |VER | STATUS |
+// +----+--------+
+// | 1  |   1    |
+// +----+--------+
+static USERNAME_PASSWORD_SUCCESS_REPLY: [u8; 2] = [USERNAME_PASSWORD_VERSION, 0];
+static USERNAME_PASSWORD_FAILURE_REPLY: [u8; 2] = [USERNAME_PASSWORD_VERSION, 1];
+
+// +----+----+----------+----------+
+// | VN | CD | DSTPORT  |  DSTIP   |
+// +----+----+----------+----------+
+// | 1  | 1  |    2     |    4     |
+// +----+----+----------+----------+
+static SOCKS4_GRANTED_REPLY: [u8; 8] = [SOCKS4_REPLY_VERSION, SOCKS4_GRANTED, 0, 0, 0, 0, 0, 0];
+static SOCKS4_REJECTED_REPLY: [u8; 8] = [SOCKS4_REPLY_VERSION, SOCKS4_REJECTED, 0, 0, 0, 0, 0, 0];
+
 // +----+-----+-------+------+----------+----------+
 // |VER | REP |  RSV  | ATYP | BND.ADDR | BND.PORT |
 // +----+-----+-------+------+----------+----------+
 // | 1  |  1  | X'00' |  1   | Variable |    2     |
 // +----+-----+-------+------+----------+----------+
-static CONNECT_REPLY: [u8; 10] = [SOCKS_VERSION, 0, 0, ADDRESS_TYPE_IPV4, 0, 0, 0, 0, 0, 0];
-static COMMAND_NOT_SUPPORTED_REPLY: [u8; 2] = [SOCKS_VERSION, COMMAND_NOT_SUPPORTED];
-static ADDRESS_TYPE_NOT_SUPPORTED_REPLY: [u8; 2] = [SOCKS_VERSION, ADDRESS_TYPE_NOT_SUPPORTED];
+/// Reply to a socks 5 request, BND is 0.0.0.0:0 unless a bound
+/// address is given.
+fn reply(reply: u8, bound_addr: Option<net::SocketAddr>) -> Vec<u8> {
+  let bound_addr = bound_addr.unwrap_or_else(|| (net::Ipv4Addr::UNSPECIFIED, 0).into());
+  let mut bytes = Vec::with_capacity(4 + 16 + 2);
+  bytes.extend_from_slice(&[SOCKS_VERSION, reply, 0]);
+  match bound_addr.ip() {
+    net::IpAddr::V4(ip) => {
+      bytes.push(ADDRESS_TYPE_IPV4);
+      bytes.extend_from_slice(&ip.octets());
+    }
+    net::IpAddr::V6(ip) => {
+      bytes.push(ADDRESS_TYPE_IPV6);
+      bytes.extend_from_slice(&ip.octets());
+    }
+  }
+  bytes.extend_from_slice(&bound_addr.port().to_be_bytes());
+  bytes
+}
+
+/// The CONNECT request of a completed handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocksRequest {
+  /// username if the client authenticated with username/password
+  pub username: Option<String>,
+  pub destination: Destination,
+  pub port: u16,
+}
+
+/// DST.ADDR of the CONNECT request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Destination {
+  Ipv4(net::Ipv4Addr),
+  Ipv6(net::Ipv6Addr),
+  DomainName(String),
+}
+
+/// Checks a username and password, returns whether they are accepted.
+pub type CheckCredentials = dyn Fn(&str, &str) -> bool + Send + Sync;
+
+/// Decides whether a CONNECT request is allowed once it is read,
+/// a refused request is replied to with connection not allowed.
+pub type AllowConnect<'a> = dyn Fn(&SocksRequest) -> bool + Send + Sync + 'a;
+
+/// Socks handshake options beyond the authentication, the free
+/// handshake functions use the defaults.
+pub struct SocksHandshake<'a> {
+  auth: &'a Authentication,
+  bound_addr: Option<net::SocketAddr>,
+  allow: Option<&'a AllowConnect<'a>>,
+}
+
+/// Which socks 5 authentication methods the handshake accepts.
+#[derive(Clone, Default)]
+pub enum Authentication {
+  /// no authentication only
+  #[default]
+  None,
+  /// no authentication, or username/password with any credentials
+  /// so the client can identify itself with the username
+  AnyUsername,
+  /// username/password required and checked against username -> password
+  Credentials(Arc<HashMap<String, String>>),
+  /// username/password required and checked by the callback
+  Callback(Arc<CheckCredentials>),
+}
+
+impl Authentication {
+  /// Whether a client may connect without credentials.
+  pub fn accepts_no_authentication(&self) -> bool {
+    matches!(self, Authentication::None | Authentication::AnyUsername)
+  }
+
+  pub fn accepts_username_password(&self) -> bool {
+    !matches!(self, Authentication::None)
+  }
+
+  /// Whether the credentials are accepted.
+  pub fn check(&self, username: &str, password: &str) -> bool {
+    match self {
+      Authentication::None => false,
+      Authentication::AnyUsername => true,
+      Authentication::Credentials(credentials) => {
+        credentials.get(username).map(String::as_str) == Some(password)
+      }
+      Authentication::Callback(check) => check(username, password),
+    }
+  }
+}
+
+impl fmt::Debug for Authentication {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Authentication::None => f.write_str("None"),
+      Authentication::AnyUsername => f.write_str("AnyUsername"),
+      // don't log passwords
+      Authentication::Credentials(credentials) => f
+        .debug_tuple("Credentials")
+        .field(&credentials.keys().collect::<Vec<_>>())
+        .finish(),
+      Authentication::Callback(_) => f.write_str("Callback"),
+    }
+  }
+}
 
 #[derive(Debug)]
 pub enum SocksError {
   InvalidSocksVersion(u8),
+  InvalidUsernamePasswordVersion(u8),
+  AuthenticationFailed(String),
   NoAcceptableMethods,
   CommandNotSupported(u8),
   AddressTypeNotSupported(u8),
+  RequestTooLong,
+  InvalidRequest(&'static str),
+  ConnectionNotAllowed(SocksRequest),
   IOError(io::Error),
 }
 
-/// This only supports no authentication and just accepts
-/// with a reply of success with 0.0.0.0:0
+/// Handshake for socks 5 or socks 4/4a depending on the version the
+/// client sends, see socks5_handshake and socks4_handshake.
+pub async fn socks_handshake<S>(
+  socket: S,
+  auth: &Authentication,
+) -> Result<(S, SocksRequest), SocksError>
+where
+  S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+  SocksHandshake::new(auth).accept(socket).await
+}
+
+/// This supports no authentication and RFC 1929 username/password
+/// authentication as allowed by auth, it just accepts the CONNECT
+/// with a reply of success with 0.0.0.0:0 (see SocksHandshake to
+/// report the real bound address)
 ///
-/// It is simply for redirecting all trafic to the local server
-pub async fn socks5_handshake<S>(mut socket: S) -> Result<S, SocksError>
+/// It is simply for redirecting all trafic to the local server, the
+/// request is returned so the server can tell what the client dialed.
+pub async fn socks5_handshake<S>(
+  mut socket: S,
+  auth: &Authentication,
+) -> Result<(S, SocksRequest), SocksError>
 where
   S: AsyncReadExt + AsyncWriteExt + Unpin,
 {
-  // max possible socks message
-  // 4 header 1 domain length 255 domain name 2 port
-  let mut buffer: [u8; 262] = [0; 262];
+  let mut buffer: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+  let len = read_to(&mut socket, &mut buffer, 0, 1).await?;
+  socks5_handshake_with(socket, &SocksHandshake::new(auth), buffer, len).await
+}
 
+/// socks 5 handshake where the first len bytes are already read
+async fn socks5_handshake_with<S>(
+  mut socket: S,
+  handshake: &SocksHandshake<'_>,
+  mut buffer: [u8; BUFFER_LEN],
+  mut len: usize,
+) -> Result<(S, SocksRequest), SocksError>
+where
+  S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
   // +----+----------+----------+
   // |VER | NMETHODS | METHODS  |
   // +----+----------+----------+
   // | 1  |    1     | 1 to 255 |
   // +----+----------+----------+
-  let mut len = read_at_least_one(&mut socket, &mut buffer).await?;
   let version = buffer[0];
   if version != SOCKS_VERSION {
     return Err(SocksError::InvalidSocksVersion(version));
   }
 
-  if len < 2 {
-    len += read_at_least_one(&mut socket, &mut buffer[len..]).await?;
-  }
+  len = read_to(&mut socket, &mut buffer, len, 2).await?;
 
   let methods_len = buffer[1] as usize;
 
   let end: usize = methods_len + 2;
-  while len < end {
-    len += read_at_least_one(&mut socket, &mut buffer[len..]).await?;
-  }
+  read_to(&mut socket, &mut buffer, len, end).await?;
 
   let methods = &buffer[2..end];
-  let no_auth_required = methods.iter().any(|&b| b == NO_AUTHENTICATION_REQUIRED);
+  let auth = handshake.auth;
 
-  if no_auth_required {
+  // prefer username/password when accepted so the client identifies itself
+  let username = if auth.accepts_username_password() && methods.contains(&USERNAME_PASSWORD) {
+    socket.write_all(&USERNAME_PASSWORD_REPLY).await?;
+    Some(read_username_password(&mut socket, &mut buffer, auth).await?)
+  } else if auth.accepts_no_authentication() && methods.contains(&NO_AUTHENTICATION_REQUIRED) {
     socket.write_all(&NO_AUTHENTICATION_REQUIRED_REPLY).await?;
+    None
   } else {
     socket.write_all(&NO_ACCEPTABLE_METHODS_REPLY).await?;
     return Err(SocksError::NoAcceptableMethods);
-  }
+  };
 
   // +----+-----+-------+------+----------+----------+
   // |VER | CMD |  RSV  | ATYP | DST.ADDR | DST.PORT |
@@ -95,26 +263,25 @@ where
   // | 1  |  1  | X'00' |  1   | Variable |    2     |
   // +----+-----+-------+------+----------+----------+
   // reset buffer position
-  len = read_at_least_one(&mut socket, &mut buffer).await?;
+  len = read_to(&mut socket, &mut buffer, 0, 1).await?;
 
   let version = buffer[0];
   if version != SOCKS_VERSION {
+    socket.write_all(&reply(GENERAL_FAILURE, None)).await?;
     return Err(SocksError::InvalidSocksVersion(version));
   }
 
-  if len < 2 {
-    len += read_at_least_one(&mut socket, &mut buffer[len..]).await?;
-  }
+  len = read_to(&mut socket, &mut buffer, len, 2).await?;
 
   let command = buffer[1];
   if command != CONNECT_COMMAND {
-    socket.write_all(&COMMAND_NOT_SUPPORTED_REPLY).await?;
+    socket
+      .write_all(&reply(COMMAND_NOT_SUPPORTED, None))
+      .await?;
     return Err(SocksError::CommandNotSupported(command));
   }
 
-  while len < 4 {
-    len += read_at_least_one(&mut socket, &mut buffer[len..]).await?;
-  }
+  len = read_to(&mut socket, &mut buffer, len, 4).await?;
 
   let address_type = buffer[3];
 
@@ -122,71 +289,327 @@ where
   let end = match address_type {
     ADDRESS_TYPE_IPV4 => 4 + 4 + 2,
     ADDRESS_TYPE_DOMAIN_NAME => {
-      if len < 5 {
-        len += read_at_least_one(&mut socket, &mut buffer[len..]).await?;
-      }
+      len = read_to(&mut socket, &mut buffer, len, 5).await?;
       let domain_name_len = buffer[4] as usize;
+      if domain_name_len == 0 {
+        socket.write_all(&reply(GENERAL_FAILURE, None)).await?;
+        return Err(SocksError::InvalidRequest("empty domain name"));
+      }
       4 + 1 + domain_name_len + 2
     }
     ADDRESS_TYPE_IPV6 => 4 + 16 + 2,
     _ => {
-      socket.write_all(&ADDRESS_TYPE_NOT_SUPPORTED_REPLY).await?;
+      socket
+        .write_all(&reply(ADDRESS_TYPE_NOT_SUPPORTED, None))
+        .await?;
       return Err(SocksError::AddressTypeNotSupported(address_type));
     }
   };
 
-  while len < end {
-    len += read_at_least_one(&mut socket, &mut buffer[len..]).await?;
-  }
-
-  debug!(
-    "CONNECT {}",
-    match address_type {
-      ADDRESS_TYPE_IPV4 => format!(
-        "{}",
-        net::SocketAddrV4::new(
-          net::Ipv4Addr::new(buffer[4], buffer[5], buffer[6], buffer[7]),
-          u16::from_be_bytes([buffer[8], buffer[9]]),
-        )
-      ),
-      ADDRESS_TYPE_DOMAIN_NAME => format!(
-        "{}:{}",
-        String::from_utf8_lossy(&buffer[5..end - 2]),
-        u16::from_be_bytes([buffer[end - 2], buffer[end - 1]])
-      ),
-      ADDRESS_TYPE_IPV6 => format!(
-        "{}",
-        net::SocketAddrV6::new(
-          net::Ipv6Addr::from([
-            buffer[4], buffer[5], buffer[6], buffer[7], buffer[8], buffer[9], buffer[10],
-            buffer[11], buffer[12], buffer[13], buffer[14], buffer[15], buffer[16], buffer[17],
-            buffer[18], buffer[19],
-          ]),
-          u16::from_be_bytes([buffer[20], buffer[21]]),
-          0,
-          0
-        )
-      ),
-      _ => String::default(),
-    }
-  );
+  read_to(&mut socket, &mut buffer, len, end).await?;
+
+  let destination = match address_type {
+    ADDRESS_TYPE_IPV4 => Destination::Ipv4(net::Ipv4Addr::new(
+      buffer[4], buffer[5], buffer[6], buffer[7],
+    )),
+    ADDRESS_TYPE_IPV6 => {
+      let mut octets = [0u8; 16];
+      octets.copy_from_slice(&buffer[4..20]);
+      Destination::Ipv6(net::Ipv6Addr::from(octets))
+    }
+    _ => Destination::DomainName(String::from_utf8_lossy(&buffer[5..end - 2]).into_owned()),
+  };
+  let request = SocksRequest {
+    username,
+    destination,
+    port: u16::from_be_bytes([buffer[end - 2], buffer[end - 1]]),
+  };
+
+  if !handshake.allows(&request) {
+    socket
+      .write_all(&reply(CONNECTION_NOT_ALLOWED, None))
+      .await?;
+    return Err(SocksError::ConnectionNotAllowed(request));
+  }
+
+  debug!("CONNECT {}", request);
 
   // we are capturing all traffic so we don't care about what we
   // read just as long as we read it all
-  socket.write_all(&CONNECT_REPLY).await?;
+  socket
+    .write_all(&reply(SUCCEEDED, handshake.bound_addr))
+    .await?;
+
+  Ok((socket, request))
+}
+
+/// SOCKS4 and SOCKS4a CONNECT, the userid is the username. There is
+/// no password so credentials are checked with an empty password.
+///
+/// Like socks 5 it just grants the CONNECT, the request is returned
+/// so the server can tell what the client dialed.
+pub async fn socks4_handshake<S>(
+  mut socket: S,
+  auth: &Authentication,
+) -> Result<(S, SocksRequest), SocksError>
+where
+  S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+  let mut buffer: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+  let len = read_to(&mut socket, &mut buffer, 0, 1).await?;
+  socks4_handshake_with(socket, &SocksHandshake::new(auth), buffer, len).await
+}
+
+/// socks 4 handshake where the first len bytes are already read
+async fn socks4_handshake_with<S>(
+  mut socket: S,
+  handshake: &SocksHandshake<'_>,
+  mut buffer: [u8; BUFFER_LEN],
+  mut len: usize,
+) -> Result<(S, SocksRequest), SocksError>
+where
+  S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+  // +----+----+---------+-------+----------+------+
+  // | VN | CD | DSTPORT | DSTIP |  USERID  | NULL |
+  // +----+----+---------+-------+----------+------+
+  // | 1  | 1  |    2    |   4   | variable |  1   |
+  // +----+----+---------+-------+----------+------+
+  // socks 4a sets DSTIP to 0.0.0.x and follows with a NULL
+  // terminated domain name
+  let version = buffer[0];
+  if version != SOCKS4_VERSION {
+    return Err(SocksError::InvalidSocksVersion(version));
+  }
+
+  len = read_to(&mut socket, &mut buffer, len, 8).await?;
+
+  let command = buffer[1];
+  if command != CONNECT_COMMAND {
+    return reject_socks4(socket, SocksError::CommandNotSupported(command)).await;
+  }
+
+  let port = u16::from_be_bytes([buffer[2], buffer[3]]);
+  let ip = net::Ipv4Addr::new(buffer[4], buffer[5], buffer[6], buffer[7]);
+  let is_socks4a = matches!(ip.octets(), [0, 0, 0, x] if x != 0);
+
+  let userid_end = match read_until_null(&mut socket, &mut buffer, &mut len).await {
+    Err(SocksError::RequestTooLong) => {
+      return reject_socks4(socket, SocksError::RequestTooLong).await
+    }
+    result => result?,
+  };
+  let userid = String::from_utf8_lossy(&buffer[8..userid_end]).into_owned();
+
+  let destination = if is_socks4a {
+    let start = userid_end + 1;
+    let end = match read_until_null(&mut socket, &mut buffer, &mut len).await {
+      Err(SocksError::RequestTooLong) => {
+        return reject_socks4(socket, SocksError::RequestTooLong).await
+      }
+      result => result?,
+    };
+    if end == start {
+      return reject_socks4(socket, SocksError::InvalidRequest("empty domain name")).await;
+    }
+    Destination::DomainName(String::from_utf8_lossy(&buffer[start..end]).into_owned())
+  } else {
+    Destination::Ipv4(ip)
+  };
 
-  Ok(socket)
+  let auth = handshake.auth;
+  let username = match auth {
+    Authentication::None => None,
+    Authentication::AnyUsername if userid.is_empty() => None,
+    _ if auth.check(&userid, "") => Some(userid),
+    _ => return reject_socks4(socket, SocksError::AuthenticationFailed(userid)).await,
+  };
+
+  let request = SocksRequest {
+    username,
+    destination,
+    port,
+  };
+
+  if !handshake.allows(&request) {
+    return reject_socks4(socket, SocksError::ConnectionNotAllowed(request)).await;
+  }
+
+  debug!("SOCKS4 CONNECT {}", request);
+
+  socket.write_all(&SOCKS4_GRANTED_REPLY).await?;
+
+  Ok((socket, request))
+}
+
+/// SOCKS4 has a single rejected reply for every failure.
+async fn reject_socks4<S, T>(mut socket: S, err: SocksError) -> Result<T, SocksError>
+where
+  S: AsyncWriteExt + Unpin,
+{
+  socket.write_all(&SOCKS4_REJECTED_REPLY).await?;
+  Err(err)
+}
+
+/// Reads a byte at a time up to and including a NULL, returns the
+/// index of the NULL.
+async fn read_until_null<S>(
+  socket: &mut S,
+  buffer: &mut [u8],
+  len: &mut usize,
+) -> Result<usize, SocksError>
+where
+  S: AsyncReadExt + Unpin,
+{
+  loop {
+    if *len == buffer.len() {
+      return Err(SocksError::RequestTooLong);
+    }
+    let byte = socket.read_u8().await?;
+    buffer[*len] = byte;
+    *len += 1;
+    if byte == 0 {
+      return Ok(*len - 1);
+    }
+  }
+}
+
+/// RFC 1929 username/password sub-negotiation, returns the username
+/// if auth accepts the credentials.
+async fn read_username_password<S>(
+  socket: &mut S,
+  buffer: &mut [u8],
+  auth: &Authentication,
+) -> Result<String, SocksError>
+where
+  S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+  // +----+------+----------+------+----------+
+  // |VER | ULEN |  UNAME   | PLEN |  PASSWD  |
+  // +----+------+----------+------+----------+
+  // | 1  |  1   | 1 to 255 |  1   | 1 to 255 |
+  // +----+------+----------+------+----------+
+  let mut len = read_to(socket, buffer, 0, 1).await?;
+  let version = buffer[0];
+  if version != USERNAME_PASSWORD_VERSION {
+    socket.write_all(&USERNAME_PASSWORD_FAILURE_REPLY).await?;
+    return Err(SocksError::InvalidUsernamePasswordVersion(version));
+  }
+
+  len = read_to(socket, buffer, len, 2).await?;
+
+  let username_end = 2 + buffer[1] as usize;
+  len = read_to(socket, buffer, len, username_end + 1).await?;
+
+  let end = username_end + 1 + buffer[username_end] as usize;
+  read_to(socket, buffer, len, end).await?;
+
+  let username = String::from_utf8_lossy(&buffer[2..username_end]).into_owned();
+  let password = String::from_utf8_lossy(&buffer[username_end + 1..end]);
+
+  if !auth.check(&username, &password) {
+    // a failure status must be followed by closing the connection
+    socket.write_all(&USERNAME_PASSWORD_FAILURE_REPLY).await?;
+    return Err(SocksError::AuthenticationFailed(username));
+  }
+
+  socket.write_all(&USERNAME_PASSWORD_SUCCESS_REPLY).await?;
+
+  Ok(username)
 }
 
-async fn read_at_least_one<S>(socket: &mut S, buffer: &mut [u8]) -> Result<usize, io::Error>
+/// Reads until the buffer holds end bytes and returns the new len.
+///
+/// It never reads past end, a client may send the next message or its
+/// TLS ClientHello without waiting for our reply.
+async fn read_to<S>(
+  socket: &mut S,
+  buffer: &mut [u8],
+  len: usize,
+  end: usize,
+) -> Result<usize, io::Error>
 where
   S: AsyncReadExt + Unpin,
 {
-  let len = socket.read(buffer).await?;
-  if len == 0 {
-    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+  if len < end {
+    socket.read_exact(&mut buffer[len..end]).await?;
+  }
+  Ok(len.max(end))
+}
+
+impl<'a> SocksHandshake<'a> {
+  pub fn new(auth: &'a Authentication) -> Self {
+    SocksHandshake {
+      auth,
+      bound_addr: None,
+      allow: None,
+    }
+  }
+
+  /// Report this address as BND.ADDR and BND.PORT of a granted
+  /// socks 5 CONNECT, usually the local address of the socket.
+  pub fn with_bound_addr(mut self, bound_addr: net::SocketAddr) -> Self {
+    self.bound_addr = Some(bound_addr);
+    self
+  }
+
+  /// Reply connection not allowed to requests allow refuses.
+  pub fn with_allow(mut self, allow: &'a AllowConnect<'a>) -> Self {
+    self.allow = Some(allow);
+    self
+  }
+
+  /// Handshake for socks 5 or socks 4/4a depending on the version
+  /// the client sends.
+  pub async fn accept<S>(&self, mut socket: S) -> Result<(S, SocksRequest), SocksError>
+  where
+    S: AsyncReadExt + AsyncWriteExt + Unpin,
+  {
+    let mut buffer: [u8; BUFFER_LEN] = [0; BUFFER_LEN];
+    let len = read_to(&mut socket, &mut buffer, 0, 1).await?;
+    match buffer[0] {
+      SOCKS_VERSION => socks5_handshake_with(socket, self, buffer, len).await,
+      SOCKS4_VERSION => socks4_handshake_with(socket, self, buffer, len).await,
+      version => Err(SocksError::InvalidSocksVersion(version)),
+    }
+  }
+
+  fn allows(&self, request: &SocksRequest) -> bool {
+    match self.allow {
+      Some(allow) => allow(request),
+      None => true,
+    }
+  }
+}
+
+impl SocksRequest {
+  /// The dialed host:port, IPv6 addresses in brackets.
+  pub fn target(&self) -> String {
+    match self.destination {
+      Destination::Ipv6(ref addr) => format!("[{}]:{}", addr, self.port),
+      ref destination => format!("{}:{}", destination, self.port),
+    }
+  }
+}
+
+impl fmt::Display for SocksRequest {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.target())?;
+    if let Some(ref username) = self.username {
+      f.write_fmt(format_args!(" as {}", username))?;
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for Destination {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      Destination::Ipv4(ref addr) => addr.fmt(f),
+      Destination::Ipv6(ref addr) => addr.fmt(f),
+      Destination::DomainName(ref name) => f.write_str(name),
+    }
   }
-  Ok(len)
 }
 
 impl error::Error for SocksError {
@@ -201,23 +624,35 @@ impl error::Error for SocksError {
 impl fmt::Display for SocksError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match *self {
-      SocksError::InvalidSocksVersion(version) => f.write_fmt(format_args!(
-        "Invalid socks version, expected 5 but got {}",
+      SocksError::InvalidSocksVersion(version) => {
+        f.write_fmt(format_args!("Unsupported socks version {}", version))
+      }
+      SocksError::InvalidUsernamePasswordVersion(version) => f.write_fmt(format_args!(
+        "Invalid username/password auth version, expected 1 but got {}",
         version
       )),
+      SocksError::AuthenticationFailed(ref username) => f.write_fmt(format_args!(
+        "socks authentication failed for username {:?}",
+        username
+      )),
       SocksError::IOError(ref err) => f.write_fmt(format_args!(
-        "an IO error occurred during the socks 5 handshake: {}",
+        "an IO error occurred during the socks handshake: {}",
         err
       )),
-      SocksError::NoAcceptableMethods => {
-        f.write_str("no acceptable socks auth methods, only no authentication is supported")
-      }
+      SocksError::NoAcceptableMethods => f.write_str("no acceptable socks auth methods"),
       SocksError::CommandNotSupported(ref cmd) => {
         f.write_fmt(format_args!("unsupported socks command {}", cmd))
       }
       SocksError::AddressTypeNotSupported(ref atype) => {
         f.write_fmt(format_args!("unsupported address type {}", atype))
       }
+      SocksError::RequestTooLong => f.write_str("socks request too long"),
+      SocksError::InvalidRequest(message) => {
+        f.write_fmt(format_args!("invalid socks request, {}", message))
+      }
+      SocksError::ConnectionNotAllowed(ref request) => {
+        f.write_fmt(format_args!("socks CONNECT {} not allowed", request))
+      }
     }
   }
 }
@@ -227,3 +662,126 @@ impl From<io::Error> for SocksError {
     SocksError::IOError(err)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_check_credentials() {
+    let mut credentials = HashMap::new();
+    credentials.insert(String::from("control"), String::from("secret"));
+    let auth = Authentication::Credentials(Arc::new(credentials));
+    assert!(auth.check("control", "secret"));
+    assert!(!auth.check("control", "wrong"));
+    assert!(!auth.check("experiment", "secret"));
+    assert!(!auth.accepts_no_authentication());
+  }
+
+  #[test]
+  fn test_check_callback() {
+    let auth = Authentication::Callback(Arc::new(|username, _| username.starts_with("set-")));
+    assert!(auth.check("set-control", ""));
+    assert!(!auth.check("control", ""));
+  }
+
+  #[tokio::test]
+  async fn test_socks4a_connect() {
+    let (mut client, server) = tokio::io::duplex(64);
+    client
+      .write_all(b"\x04\x01\x01\xbb\x00\x00\x00\x01control\x00www.example.com\x00")
+      .await
+      .unwrap();
+    let (_, request) = socks_handshake(server, &Authentication::AnyUsername)
+      .await
+      .unwrap();
+    assert_eq!(request.to_string(), "www.example.com:443 as control");
+    let mut reply = [0u8; 8];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, SOCKS4_GRANTED_REPLY);
+  }
+
+  #[tokio::test]
+  async fn test_socks4_connect() {
+    let (mut client, server) = tokio::io::duplex(64);
+    client
+      .write_all(b"\x04\x01\x00\x50\x0a\x00\x00\x01\x00")
+      .await
+      .unwrap();
+    let (_, request) = socks_handshake(server, &Authentication::None)
+      .await
+      .unwrap();
+    assert_eq!(request.to_string(), "10.0.0.1:80");
+  }
+
+  #[tokio::test]
+  async fn test_command_not_supported_reply() {
+    let (mut client, server) = tokio::io::duplex(64);
+    // BIND 10.0.0.1:80
+    client
+      .write_all(b"\x05\x01\x00\x05\x02\x00\x01\x0a\x00\x00\x01\x00\x50")
+      .await
+      .unwrap();
+    let result = socks_handshake(server, &Authentication::None).await;
+    assert!(matches!(result, Err(SocksError::CommandNotSupported(2))));
+    let mut reply = [0u8; 12];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, [5, 0, 5, 7, 0, 1, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[tokio::test]
+  async fn test_connection_not_allowed_reply() {
+    let allow = |request: &SocksRequest| request.port == 443;
+    let (mut client, server) = tokio::io::duplex(64);
+    client
+      .write_all(b"\x05\x01\x00\x05\x01\x00\x01\x0a\x00\x00\x01\x00\x50")
+      .await
+      .unwrap();
+    let result = SocksHandshake::new(&Authentication::None)
+      .with_allow(&allow)
+      .accept(server)
+      .await;
+    assert!(matches!(result, Err(SocksError::ConnectionNotAllowed(_))));
+    let mut reply = [0u8; 12];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, [5, 0, 5, 2, 0, 1, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[tokio::test]
+  async fn test_bound_addr_reply() {
+    let bound_addr = net::SocketAddr::new(net::Ipv6Addr::LOCALHOST.into(), 9000);
+    let (mut client, server) = tokio::io::duplex(64);
+    client
+      .write_all(b"\x05\x01\x00\x05\x01\x00\x03\x0bexample.com\x01\xbb")
+      .await
+      .unwrap();
+    let (_, request) = SocksHandshake::new(&Authentication::None)
+      .with_bound_addr(bound_addr)
+      .accept(server)
+      .await
+      .unwrap();
+    assert_eq!(request.to_string(), "example.com:443");
+    let mut reply = [0u8; 2 + 22];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[2..6], [5, 0, 0, 4]);
+    assert_eq!(reply[6..22], net::Ipv6Addr::LOCALHOST.octets());
+    assert_eq!(reply[22..], [0x23, 0x28]);
+  }
+
+  #[tokio::test]
+  async fn test_socks4_rejected_userid() {
+    let mut credentials = HashMap::new();
+    credentials.insert(String::from("control"), String::from("secret"));
+    let auth = Authentication::Credentials(Arc::new(credentials));
+    let (mut client, server) = tokio::io::duplex(64);
+    client
+      .write_all(b"\x04\x01\x00\x50\x0a\x00\x00\x01control\x00")
+      .await
+      .unwrap();
+    let result = socks_handshake(server, &auth).await;
+    assert!(matches!(result, Err(SocksError::AuthenticationFailed(_))));
+    let mut reply = [0u8; 8];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, SOCKS4_REJECTED_REPLY);
+  }
+}
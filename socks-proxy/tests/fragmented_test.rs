@@ -0,0 +1,204 @@
+use proptest::prelude::*;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracerbench_socks_proxy::{socks_handshake, Authentication, Destination, SocksRequest};
+
+/// Client bytes delivered a fragment per read, then EOF. Replies are
+/// collected in written.
+struct Fragmented {
+  fragments: VecDeque<Vec<u8>>,
+  written: Vec<u8>,
+}
+
+impl Fragmented {
+  /// Splits bytes at the cut points.
+  fn new(bytes: &[u8], cuts: &[usize]) -> Self {
+    let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (bytes.len() + 1)).collect();
+    cuts.push(0);
+    cuts.push(bytes.len());
+    cuts.sort_unstable();
+    cuts.dedup();
+    let fragments = cuts
+      .windows(2)
+      .map(|window| bytes[window[0]..window[1]].to_vec())
+      .collect();
+    Fragmented {
+      fragments,
+      written: Vec::new(),
+    }
+  }
+}
+
+impl AsyncRead for Fragmented {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    if let Some(mut fragment) = self.fragments.pop_front() {
+      let len = fragment.len().min(buf.remaining());
+      buf.put_slice(&fragment[..len]);
+      if len < fragment.len() {
+        self.fragments.push_front(fragment.split_off(len));
+      }
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl AsyncWrite for Fragmented {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    self.written.extend_from_slice(buf);
+    Poll::Ready(Ok(buf.len()))
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+fn handshake(stream: Fragmented, auth: &Authentication) -> (Option<SocksRequest>, Vec<u8>, usize) {
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .build()
+    .unwrap();
+  match runtime.block_on(socks_handshake(stream, auth)) {
+    Ok((stream, request)) => {
+      let unread = stream.fragments.iter().map(Vec::len).sum();
+      (Some(request), stream.written, unread)
+    }
+    // the stream is consumed by a failed handshake
+    Err(_) => (None, Vec::new(), 0),
+  }
+}
+
+fn destination() -> impl Strategy<Value = Destination> {
+  prop_oneof![
+    any::<[u8; 4]>().prop_map(|octets| Destination::Ipv4(Ipv4Addr::from(octets))),
+    any::<[u8; 16]>().prop_map(|octets| Destination::Ipv6(Ipv6Addr::from(octets))),
+    "[a-z0-9.-]{1,255}".prop_map(Destination::DomainName),
+  ]
+}
+
+/// Greeting, optional username/password and CONNECT request.
+fn socks5_bytes(request: &SocksRequest, password: &str) -> Vec<u8> {
+  let mut bytes = match request.username {
+    Some(ref username) => {
+      let mut bytes = vec![5, 1, 2, 1, username.len() as u8];
+      bytes.extend_from_slice(username.as_bytes());
+      bytes.push(password.len() as u8);
+      bytes.extend_from_slice(password.as_bytes());
+      bytes
+    }
+    None => vec![5, 1, 0],
+  };
+  bytes.extend_from_slice(&[5, 1, 0]);
+  match request.destination {
+    Destination::Ipv4(ip) => {
+      bytes.push(1);
+      bytes.extend_from_slice(&ip.octets());
+    }
+    Destination::Ipv6(ip) => {
+      bytes.push(4);
+      bytes.extend_from_slice(&ip.octets());
+    }
+    Destination::DomainName(ref name) => {
+      bytes.push(3);
+      bytes.push(name.len() as u8);
+      bytes.extend_from_slice(name.as_bytes());
+    }
+  }
+  bytes.extend_from_slice(&request.port.to_be_bytes());
+  bytes
+}
+
+/// SOCKS4 for IPv4 destinations, SOCKS4a for domain names.
+fn socks4_bytes(request: &SocksRequest) -> Vec<u8> {
+  let mut bytes = vec![4, 1];
+  bytes.extend_from_slice(&request.port.to_be_bytes());
+  match request.destination {
+    Destination::Ipv4(ip) => bytes.extend_from_slice(&ip.octets()),
+    _ => bytes.extend_from_slice(&[0, 0, 0, 1]),
+  }
+  if let Some(ref username) = request.username {
+    bytes.extend_from_slice(username.as_bytes());
+  }
+  bytes.push(0);
+  if let Destination::DomainName(ref name) = request.destination {
+    bytes.extend_from_slice(name.as_bytes());
+    bytes.push(0);
+  }
+  bytes
+}
+
+proptest! {
+  #[test]
+  fn test_socks5_fragmented(
+    destination in destination(),
+    port in any::<u16>(),
+    username in proptest::option::of("[a-z]{1,255}"),
+    password in "[a-z]{1,255}",
+    cuts in proptest::collection::vec(any::<usize>(), 0..32),
+    trailing in proptest::collection::vec(any::<u8>(), 0..8),
+  ) {
+    let request = SocksRequest { username, destination, port };
+    let mut bytes = socks5_bytes(&request, &password);
+    bytes.extend_from_slice(&trailing);
+
+    let (parsed, written, unread) =
+      handshake(Fragmented::new(&bytes, &cuts), &Authentication::AnyUsername);
+
+    prop_assert_eq!(parsed, Some(request.clone()));
+    let mut expected = match request.username {
+      Some(_) => vec![5, 2, 1, 0],
+      None => vec![5, 0],
+    };
+    expected.extend_from_slice(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    prop_assert_eq!(written, expected);
+    // bytes after the request are left for TLS
+    prop_assert_eq!(unread, trailing.len());
+  }
+
+  #[test]
+  fn test_socks4_fragmented(
+    destination in prop_oneof![
+      any::<[u8; 3]>().prop_map(|[a, b, c]| Destination::Ipv4(Ipv4Addr::new(a.max(1), b, c, 1))),
+      "[a-z0-9.-]{1,255}".prop_map(Destination::DomainName),
+    ],
+    port in any::<u16>(),
+    username in proptest::option::of("[a-z]{1,200}"),
+    cuts in proptest::collection::vec(any::<usize>(), 0..32),
+  ) {
+    let request = SocksRequest { username, destination, port };
+    let bytes = socks4_bytes(&request);
+
+    let (parsed, written, _) =
+      handshake(Fragmented::new(&bytes, &cuts), &Authentication::AnyUsername);
+
+    prop_assert_eq!(parsed, Some(request));
+    prop_assert_eq!(written, vec![0, 0x5A, 0, 0, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn test_arbitrary_bytes_do_not_panic(
+    version in prop_oneof![Just(4u8), Just(5u8)],
+    bytes in proptest::collection::vec(any::<u8>(), 0..1024),
+    cuts in proptest::collection::vec(any::<usize>(), 0..32),
+  ) {
+    let mut stream = vec![version];
+    stream.extend_from_slice(&bytes);
+    // any outcome is fine as long as it returns
+    handshake(Fragmented::new(&stream, &cuts), &Authentication::AnyUsername);
+  }
+}
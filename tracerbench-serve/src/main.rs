@@ -1,32 +1,309 @@
 #![warn(rust_2018_idioms)]
 #![warn(clippy::all)]
 
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use structopt::StructOpt;
+use tracerbench_recorded_response_server::Cidr;
 use tracerbench_recorded_response_server::Config;
+use tracerbench_recorded_response_server::ConfigFile;
+use tracerbench_recorded_response_server::CorsSettings;
+use tracerbench_recorded_response_server::LogFormat;
+use tracerbench_recorded_response_server::ProxyProtocol;
+use tracerbench_recorded_response_server::Recording;
+use tracerbench_recorded_response_server::Resumption;
 use tracerbench_recorded_response_server::Servers;
+use tracerbench_recorded_response_server::SharedSettings;
 
 #[derive(StructOpt)]
 pub struct Opt {
+  /// PEM certificate, overrides tls.cert of the config file
   #[structopt(parse(from_os_str))]
-  pub cert: PathBuf,
+  pub cert: Option<PathBuf>,
+  /// PEM private key, overrides tls.key of the config file
   #[structopt(parse(from_os_str))]
-  pub key: PathBuf,
+  pub key: Option<PathBuf>,
+  /// Recorded response sets archive, replaces the archives of the config file
   #[structopt(parse(from_os_str))]
-  pub sets: PathBuf,
+  pub sets: Option<PathBuf>,
+  /// TOML or JSON config file, command line options take precedence
+  #[structopt(long, parse(from_os_str))]
+  pub config: Option<PathBuf>,
+  /// Treat cert and key as a local CA and mint a leaf certificate per SNI hostname
+  #[structopt(long)]
+  pub ca: bool,
+  /// TOML or JSON file mapping response set names to TLS settings
+  #[structopt(long, parse(from_os_str))]
+  pub tls_config: Option<PathBuf>,
+  /// TLS session resumption: disabled, cache or tickets
+  #[structopt(long)]
+  pub resumption: Option<Resumption>,
+  /// Max sessions kept with the cache resumption mode
+  #[structopt(long)]
+  pub session_cache_size: Option<usize>,
+  /// Address the socks proxy servers bind to
+  #[structopt(long)]
+  pub bind: Option<IpAddr>,
+  /// Accept IPv4 connections as well when binding an IPv6 address
+  #[structopt(long)]
+  pub dual_stack: bool,
+  /// Only accept connections from peers in this network (CIDR), can be repeated
+  #[structopt(long)]
+  pub allow: Vec<Cidr>,
+  /// Added to the recorded socks port of each response set
+  #[structopt(long)]
+  pub port_offset: Option<u16>,
+  /// Listen on OS assigned ports, the ports are reported when listening
+  #[structopt(long)]
+  pub ephemeral_ports: bool,
+  /// Serve all sets from this port, routed by socks username or CONNECT destination
+  #[structopt(long)]
+  pub shared_port: Option<u16>,
+  /// Response set for shared port connections no route matches
+  #[structopt(long)]
+  pub default_set: Option<String>,
+  /// Proxy protocol: socks, http_connect or auto to detect it per connection
+  #[structopt(long)]
+  pub proxy: Option<ProxyProtocol>,
+  /// Require socks username/password authentication with USER:PASSWORD, can be repeated
+  #[structopt(long, parse(try_from_str = parse_credential))]
+  pub socks_user: Vec<(String, String)>,
+  /// Seconds to wait for each of the proxy, TLS and HTTP2 handshakes, 0 waits forever
+  #[structopt(long)]
+  pub handshake_timeout: Option<f64>,
+  /// Seconds a connection without open streams is kept open, 0 keeps it open
+  #[structopt(long)]
+  pub idle_timeout: Option<f64>,
+  /// Seconds to wait for an upstream to respond to a forwarded miss, 0 waits forever
+  #[structopt(long)]
+  pub upstream_timeout: Option<f64>,
+  /// Respond 421 when :authority does not match the socks CONNECT destination
+  #[structopt(long)]
+  pub check_authority: bool,
+  /// End server-sent event streams after the last recorded event instead of keeping them open
+  #[structopt(long)]
+  pub close_event_streams: bool,
+  /// Answer Range requests for every recorded 200 response, not only those with accept-ranges
+  #[structopt(long)]
+  pub range_requests: bool,
+  /// Answer CORS preflights missing from the sets by echoing the request, see cors in the config file
+  #[structopt(long)]
+  pub cors: bool,
+  /// Remove recorded headers HTTP/2 forbids, like upgrade and te, instead of only warning
+  #[structopt(long)]
+  pub strip_invalid_headers: bool,
+  /// Forward requests missing from a set to this HTTP/1.1 host:port instead of responding 404
+  #[structopt(long)]
+  pub upstream: Option<String>,
+  /// Forward requests missing from a set to the destination dialed through the proxy
+  #[structopt(long)]
+  pub forward_to_destination: bool,
+  /// Write the responses forwarded upstream to this archive
+  #[structopt(long, parse(from_os_str))]
+  pub new_recordings: Option<PathBuf>,
+  /// Log format: pretty or json
+  #[structopt(long)]
+  pub log_format: Option<LogFormat>,
+  #[structopt(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+  /// Record a response set by forwarding requests to their destination,
+  /// or to --upstream, instead of serving archives
+  Record(RecordOpt),
+}
+
+#[derive(StructOpt)]
+pub struct RecordOpt {
+  /// Name of the recorded response set
+  #[structopt(long)]
+  pub name: String,
+  /// Socks port to listen on, recorded as the port of the set
+  #[structopt(long)]
+  pub port: u16,
+  /// Request key of the entry request, defaults to the first recorded request
+  #[structopt(long)]
+  pub entry_key: Option<String>,
+  /// CBOR request key program the recorded responses are keyed with
+  #[structopt(long, parse(from_os_str))]
+  pub request_key_program: PathBuf,
+  /// Archive the recorded response set is written to
+  #[structopt(long, parse(from_os_str))]
+  pub output: PathBuf,
+}
+
+impl RecordOpt {
+  fn recording(&self) -> Recording {
+    Recording {
+      name: self.name.clone(),
+      port: self.port,
+      entry_key: self.entry_key.clone(),
+      request_key_program: self.request_key_program.clone(),
+      output: self.output.clone(),
+    }
+  }
+}
+
+impl Opt {
+  /// The config file with the command line options applied on top.
+  fn config_file(&self) -> Result<ConfigFile, io::Error> {
+    let mut file = match &self.config {
+      Some(path) => ConfigFile::read(path)?,
+      None => ConfigFile::default(),
+    };
+    if let Some(cert) = &self.cert {
+      file.tls.cert = Some(cert.clone());
+    }
+    if let Some(key) = &self.key {
+      file.tls.key = Some(key.clone());
+    }
+    if let Some(sets) = &self.sets {
+      file.archives = vec![sets.clone()];
+    }
+    if self.ca {
+      file.tls.ca = Some(true);
+    }
+    if self.resumption.is_some() {
+      file.tls.resumption = self.resumption;
+    }
+    if self.session_cache_size.is_some() {
+      file.tls.session_cache_size = self.session_cache_size;
+    }
+    if self.bind.is_some() {
+      file.bind = self.bind;
+    }
+    if self.dual_stack {
+      file.dual_stack = Some(true);
+    }
+    if !self.allow.is_empty() {
+      file.allow = Some(self.allow.clone());
+    }
+    if self.port_offset.is_some() {
+      file.port_offset = self.port_offset;
+    }
+    if self.ephemeral_ports {
+      file.ephemeral_ports = Some(true);
+    }
+    if self.proxy.is_some() {
+      file.proxy = self.proxy;
+    }
+    if !self.socks_user.is_empty() {
+      file.socks.credentials = Some(self.socks_user.iter().cloned().collect());
+    }
+    if self.handshake_timeout.is_some() {
+      file.timeouts.proxy = self.handshake_timeout;
+      file.timeouts.tls = self.handshake_timeout;
+      file.timeouts.preface = self.handshake_timeout;
+    }
+    if self.idle_timeout.is_some() {
+      file.timeouts.idle = self.idle_timeout;
+    }
+    if self.upstream_timeout.is_some() {
+      file.timeouts.upstream = self.upstream_timeout;
+    }
+    if self.check_authority {
+      file.check_authority = Some(true);
+    }
+    if self.close_event_streams {
+      file.close_event_streams = Some(true);
+    }
+    if self.range_requests {
+      file.range_requests = Some(true);
+    }
+    if self.cors {
+      file.cors.get_or_insert_with(CorsSettings::default);
+    }
+    if self.strip_invalid_headers {
+      file.strip_invalid_headers = Some(true);
+    }
+    if self.upstream.is_some() {
+      file.upstream = self.upstream.clone();
+    }
+    if self.forward_to_destination {
+      file.forward_to_destination = Some(true);
+    }
+    if self.new_recordings.is_some() {
+      file.new_recordings = self.new_recordings.clone();
+    }
+    if let Some(port) = self.shared_port {
+      file.shared.get_or_insert_with(SharedSettings::default).port = port;
+    }
+    if let Some(default_set) = &self.default_set {
+      file
+        .shared
+        .get_or_insert_with(SharedSettings::default)
+        .default_set = Some(default_set.clone());
+    }
+    if self.log_format.is_some() {
+      file.log_format = self.log_format;
+    }
+    Ok(file)
+  }
+}
+
+fn parse_credential(s: &str) -> Result<(String, String), String> {
+  match s.find(':') {
+    Some(i) => Ok((s[..i].to_owned(), s[i + 1..].to_owned())),
+    None => Err(format!(
+      "invalid credential {:?}, expected USER:PASSWORD",
+      s
+    )),
+  }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
   let opt = Opt::from_args();
 
-  pretty_env_logger::init_timed();
+  let file = opt.config_file()?;
+
+  init_logger(file.log_format.unwrap_or_default());
 
-  let config = Config::from_args(&opt.cert, &opt.key, &opt.sets)?;
+  let mut config = match &opt.command {
+    Some(Command::Record(record)) => Config::for_recording(&file, &record.recording())?,
+    None => Config::from_config_file(&file)?,
+  };
+
+  if let Some(tls_config) = &opt.tls_config {
+    config = config.with_tls_config_file(tls_config)?;
+  }
 
   let servers: Servers = config.into();
 
-  servers.start().await?;
+  tokio::select! {
+    result = servers.start() => result?,
+    result = tokio::signal::ctrl_c() => result?,
+  }
+
+  if let Some(new_recordings) = servers.new_recordings() {
+    if new_recordings.flush()? {
+      println!(
+        "new recordings written to {}",
+        new_recordings.path().display()
+      );
+    }
+  }
 
   Ok(())
 }
+
+fn init_logger(log_format: LogFormat) {
+  match log_format {
+    LogFormat::Pretty => pretty_env_logger::init_timed(),
+    LogFormat::Json => env_logger::Builder::from_default_env()
+      .format(|buf, record| {
+        let line = serde_json::json!({
+          "time": buf.timestamp().to_string(),
+          "level": record.level().to_string(),
+          "target": record.target(),
+          "message": record.args().to_string(),
+        });
+        writeln!(buf, "{}", line)
+      })
+      .init(),
+  }
+}